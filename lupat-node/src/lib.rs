@@ -0,0 +1,91 @@
+//! Node.js bindings, built with `napi build` from this directory.
+//! Offsets are in UTF-16 code units, so they index JavaScript strings directly.
+//!
+//! ```js
+//! const { Pattern } = require("lupat-node");
+//! new Pattern("(%a+)=(%d+)").findAll("a=1 b=2"); // [{ start: 0, end: 3, text: "a=1" }, ...]
+//! ```
+
+use lupat::bindings::{self, CompiledPattern};
+use napi::{Error, Result};
+use napi_derive::napi;
+
+/// A match, with offsets in UTF-16 code units
+#[napi(object)]
+pub struct Span {
+	pub start: u32,
+	pub end: u32,
+	pub text: String,
+}
+
+/// A compiled Lua pattern
+#[napi]
+pub struct Pattern {
+	inner: CompiledPattern,
+}
+
+#[napi]
+impl Pattern {
+	/// Compiles `source`, throwing if it's malformed
+	#[napi(constructor)]
+	pub fn new(source: String) -> Result<Self> {
+		let inner = CompiledPattern::new(&source).map_err(to_js)?;
+		Ok(Pattern { inner })
+	}
+
+	#[napi(getter)]
+	pub fn source(&self) -> String {
+		self.inner.source().to_owned()
+	}
+
+	#[napi]
+	pub fn is_match(&self, text: String) -> bool {
+		self.inner.is_match(&text)
+	}
+
+	/// The first match, or null
+	#[napi]
+	pub fn find(&self, text: String) -> Option<Span> {
+		let span = self.inner.find(&text)?;
+		to_spans(&text, &[span]).pop()
+	}
+
+	/// The captures of the first match like Lua's `string.match`, or null
+	#[napi(js_name = "match")]
+	pub fn match_captures(&self, text: String) -> Option<Vec<String>> {
+		self.inner.match_captures(&text)
+	}
+
+	/// The captures of every match like Lua's `string.gmatch`
+	#[napi]
+	pub fn gmatch(&self, text: String) -> Vec<Vec<String>> {
+		self.inner.gmatch(&text)
+	}
+
+	/// Every match
+	#[napi]
+	pub fn find_all(&self, text: String) -> Vec<Span> {
+		to_spans(&text, &self.inner.find_all(&text))
+	}
+
+	/// Replaces every match like Lua's `string.gsub`, throwing if `repl` refers to a missing capture
+	#[napi]
+	pub fn gsub(&self, text: String, repl: String) -> Result<String> {
+		self.inner.gsub(&text, &repl).map_err(to_js)
+	}
+}
+
+fn to_spans(text: &str, spans: &[bindings::Span]) -> Vec<Span> {
+	bindings::spans_to_utf16(text, spans)
+		.into_iter()
+		.map(|span| Span {
+			start: span.start as u32,
+			end: span.end as u32,
+			text: span.text,
+		})
+		.collect()
+}
+
+fn to_js(e: lupat::error::Error) -> Error {
+	Error::from_reason(e.to_string())
+}
@@ -0,0 +1,93 @@
+//! Variants of the allocating methods that take an [Allocator], for allocating from arenas instead of the global heap.
+//! Enable the `nightly` feature of `allocator-api2` to use `std`'s allocators directly.
+
+use allocator_api2::alloc::Allocator;
+use allocator_api2::vec::Vec;
+use pattern::{extend_set_bitmaps, str_check, Flags, LuaMatch, SetBitmap};
+use std::borrow::Cow;
+use {char_range, generate_gsub_patterns, slice, Error, MatchCursor, Pattern, PatternOptions, LUA_MAXCAPTURES};
+
+/// A pattern that owns its source, with it and everything worked out up front allocated from `A`.
+/// For patterns only known at runtime, that can't borrow from somewhere else.
+///
+/// ```
+/// extern crate allocator_api2;
+/// use allocator_api2::alloc::Global;
+/// use lupat::allocator::PatternIn;
+///
+/// let source = format!("(%a+)={}", "%d+");
+/// let compiled: PatternIn<Global> = PatternIn::new_in(&source, Global).unwrap();
+/// drop(source);
+/// assert!(compiled.pattern().matches("x=1"));
+/// ```
+pub struct PatternIn<A: Allocator, const MAXCAPTURES: usize = LUA_MAXCAPTURES> {
+	source: Vec<u8, A>,
+	flags: Flags,
+	sets: Vec<SetBitmap, A>,
+}
+
+impl<A: Allocator + Clone, const MAXCAPTURES: usize> PatternIn<A, MAXCAPTURES> {
+	/// Compiles `source`, failing like [Pattern::new]
+	pub fn new_in<S: AsRef<[u8]> + ?Sized>(source: &S, alloc: A) -> Result<Self, Error> {
+		PatternIn::with_options_in(PatternOptions { src: source.as_ref(), flags: Flags::default() }, alloc)
+	}
+
+	/// Compiles a pattern built with options
+	pub fn with_options_in(options: PatternOptions<MAXCAPTURES>, alloc: A) -> Result<Self, Error> {
+		str_check::<MAXCAPTURES>(options.src, options.flags)?;
+		let mut source = Vec::with_capacity_in(options.src.len(), alloc.clone());
+		source.extend_from_slice(options.src);
+		let mut sets = Vec::new_in(alloc);
+		extend_set_bitmaps(options.src, options.flags, &mut sets);
+		Ok(PatternIn { source, flags: options.flags, sets })
+	}
+}
+
+impl<A: Allocator, const MAXCAPTURES: usize> PatternIn<A, MAXCAPTURES> {
+	pub fn source(&self) -> &[u8] {
+		&self.source
+	}
+
+	/// A [Pattern] borrowing this, for the rest of the API
+	pub fn pattern(&self) -> Pattern<'_, MAXCAPTURES> {
+		// Already checked in `with_options_in`
+		Pattern {
+			patt: &self.source,
+			matches: [LuaMatch { start: 0, end: 0 }; MAXCAPTURES],
+			n_match: 0,
+			flags: self.flags,
+			sets: Cow::Borrowed(&self.sets),
+		}
+	}
+}
+
+impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
+	/// [Pattern::captures], allocating the result with `alloc`
+	pub fn captures_in<'t, A: Allocator>(&mut self, text: &'t str, alloc: A) -> Vec<&'t str, A> {
+		self.matches(text);
+		let mut res = Vec::with_capacity_in(self.n_match, alloc);
+		res.extend((0..self.n_match).map(|i| slice(text, self.capture(i))));
+		res
+	}
+
+	/// [Pattern::gsub], building the output in `alloc`. The bytes are always valid UTF-8.
+	pub fn gsub_in<A: Allocator>(&mut self, text: &str, repl: &str, alloc: A) -> Result<Vec<u8, A>, Error> {
+		let repl = generate_gsub_patterns(repl)?;
+		self.check_substitutions(&repl)?;
+		let mut res = Vec::with_capacity_in(text.len(), alloc);
+		let mut cursor = MatchCursor::new();
+		let mut last = 0;
+		while cursor.advance_str(self, text) {
+			// The previous match may have ended inside the char this one starts in
+			let all = char_range(text, self.range());
+			res.extend_from_slice(&text.as_bytes()[last..all.start.max(last)]);
+			let captures = self.match_captures(text);
+			for r in &repl {
+				res.extend_from_slice(r.expand(&captures).as_bytes());
+			}
+			last = all.end.max(last);
+		}
+		res.extend_from_slice(&text.as_bytes()[last..]);
+		Ok(res)
+	}
+}
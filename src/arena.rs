@@ -0,0 +1,186 @@
+use pattern::{Flags, LuaMatch, SetBitmap};
+use std::borrow::Cow;
+use std::ops;
+#[cfg(feature = "iter")]
+use char_range;
+use {Error, Pattern, PatternOptions, LUA_MAXCAPTURES};
+
+/// A pattern in a [PatternArena]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PatternId(u32);
+
+impl PatternId {
+	/// Position in the order patterns were added
+	pub fn index(self) -> usize {
+		self.0 as usize
+	}
+}
+
+/// Many patterns compiled into one contiguous buffer, for loading large sets of filters without an allocation each.
+///
+/// ```
+/// use lupat::PatternArena;
+///
+/// let mut arena = PatternArena::new();
+/// let spam = arena.add("buy now").unwrap();
+/// let link = arena.add("https?://%S+").unwrap();
+/// assert_eq!(arena.matching("buy now at http://x.y"), [spam, link]);
+/// assert_eq!(arena.get(link).unwrap().match_maybe("see http://x.y"), Some("http://x.y"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PatternArena {
+	buffer: Vec<u8>,
+	/// Source in `buffer`, flags, sets in `sets`
+	entries: Vec<(ops::Range<usize>, Flags, ops::Range<usize>)>,
+	sets: Vec<SetBitmap>,
+}
+
+impl PatternArena {
+	pub fn new() -> Self {
+		PatternArena::default()
+	}
+
+	/// Room for `patterns` patterns with `bytes` bytes of source in total
+	pub fn with_capacity(patterns: usize, bytes: usize) -> Self {
+		PatternArena { buffer: Vec::with_capacity(bytes), entries: Vec::with_capacity(patterns), sets: Vec::new() }
+	}
+
+	/// Compiles `source` into the arena, failing like [Pattern::new]
+	pub fn add<S: AsRef<[u8]> + ?Sized>(&mut self, source: &S) -> Result<PatternId, Error> {
+		self.add_with_options(Pattern::builder(source))
+	}
+
+	/// Compiles a pattern built with options into the arena
+	pub fn add_with_options(&mut self, options: PatternOptions) -> Result<PatternId, Error> {
+		let patt = options.build()?;
+		let start = self.buffer.len();
+		self.buffer.extend_from_slice(patt.patt);
+		let sets_start = self.sets.len();
+		self.sets.extend_from_slice(&patt.sets);
+		self.entries.push((start..self.buffer.len(), patt.flags, sets_start..self.sets.len()));
+		Ok(PatternId(self.entries.len() as u32 - 1))
+	}
+
+	/// A [Pattern] for `id`, borrowing the arena
+	pub fn get(&self, id: PatternId) -> Option<Pattern<'_, LUA_MAXCAPTURES>> {
+		let (range, flags, sets) = self.entries.get(id.index())?;
+		// Already checked in `add_with_options`
+		Some(Pattern {
+			patt: &self.buffer[range.clone()],
+			matches: [LuaMatch { start: 0, end: 0 }; LUA_MAXCAPTURES],
+			n_match: 0,
+			flags: *flags,
+			sets: Cow::Borrowed(&self.sets[sets.clone()]),
+		})
+	}
+
+	pub fn source(&self, id: PatternId) -> Option<&[u8]> {
+		self.entries.get(id.index()).map(|(range, _, _)| &self.buffer[range.clone()])
+	}
+
+	/// Every pattern matching `text`, in the order they were added
+	pub fn matching(&self, text: &str) -> Vec<PatternId> {
+		self.ids().filter(|&id| self.get(id).is_some_and(|mut patt| patt.matches(text))).collect()
+	}
+
+	/// Splits `text` around matches of any of the patterns, in one pass.
+	/// Each field comes with the pattern whose match ended it, `None` for the last field.
+	/// Where matches overlap the leftmost wins, then the one added first. Empty matches are skipped.
+	///
+	/// ```
+	/// use lupat::PatternArena;
+	///
+	/// let mut arena = PatternArena::new();
+	/// let comma = arena.add("%s*,%s*").unwrap();
+	/// let semi = arena.add(";").unwrap();
+	/// let fields: Vec<_> = arena.split("a, b;c").collect();
+	/// assert_eq!(fields, [("a", Some(comma)), ("b", Some(semi)), ("c", None)]);
+	/// ```
+	#[cfg(feature = "iter")]
+	pub fn split<'r, 't>(&'r self, text: &'t str) -> ArenaSplit<'r, 't> {
+		ArenaSplit { arena: self, text, last: 0, next: vec![None; self.len()], done: false }
+	}
+
+	/// Ids of every pattern, in the order they were added
+	pub fn ids(&self) -> impl Iterator<Item = PatternId> {
+		(0..self.entries.len() as u32).map(PatternId)
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+}
+
+/// Iterator over fields and the pattern that ended each, from [PatternArena::split]
+#[cfg(feature = "iter")]
+pub struct ArenaSplit<'r, 't> {
+	arena: &'r PatternArena,
+	text: &'t str,
+	last: usize,
+	/// Next non-empty match of each pattern at or after `last`, if it was searched for since `last` moved past it
+	next: Vec<Option<Option<ops::Range<usize>>>>,
+	done: bool,
+}
+
+#[cfg(feature = "iter")]
+impl<'r, 't> ArenaSplit<'r, 't> {
+	/// The next non-empty match of pattern `id` at or after `init`
+	fn next_match(&self, id: PatternId, init: usize) -> Option<ops::Range<usize>> {
+		let patt = self.arena.get(id)?;
+		let mut slots = [LuaMatch { start: 0, end: 0 }; LUA_MAXCAPTURES];
+		let mut init = init;
+		while init <= self.text.len() {
+			if patt.find_into(self.text.as_bytes(), &mut slots, init, false).ok()? == 0 {
+				return None;
+			}
+			let range = char_range(self.text, slots[0].start..slots[0].end);
+			if !range.is_empty() {
+				return Some(range);
+			}
+			init = slots[0].start + 1;
+		}
+		None
+	}
+}
+
+#[cfg(feature = "iter")]
+impl<'r, 't> Iterator for ArenaSplit<'r, 't> {
+	type Item = (&'t str, Option<PatternId>);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.done {
+			return None;
+		}
+		let mut best: Option<(ops::Range<usize>, PatternId)> = None;
+		for id in self.arena.ids() {
+			let found = match self.next[id.index()] {
+				Some(ref found) if found.as_ref().is_none_or(|range| range.start >= self.last) => found.clone(),
+				_ => {
+					let found = self.next_match(id, self.last);
+					self.next[id.index()] = Some(found.clone());
+					found
+				}
+			};
+			if let Some(range) = found {
+				if best.as_ref().is_none_or(|(best, _)| range.start < best.start) {
+					best = Some((range, id));
+				}
+			}
+		}
+		match best {
+			Some((range, id)) => {
+				let field = &self.text[self.last..range.start];
+				self.last = range.end;
+				Some((field, Some(id)))
+			}
+			None => {
+				self.done = true;
+				Some((&self.text[self.last..], None))
+			}
+		}
+	}
+}
@@ -0,0 +1,764 @@
+//! Syntax tree of a Lua pattern, for inspecting and assembling patterns without touching strings.
+
+use error::Error;
+use pattern::{matchbracketclass, Flags};
+use std::{cmp, fmt, ops};
+
+#[cfg(feature = "extensions")]
+use pattern::balance_seq;
+
+const L_ESC: u8 = b'%';
+
+/// A parsed pattern
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Ast {
+	/// Starts with `^`
+	pub anchor_start: bool,
+	/// Ends with `$`
+	pub anchor_end: bool,
+	pub nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+	/// A single character class with an optional suffix, like `%a+`
+	Single(Single, Quantifier),
+	/// `(...)`
+	Capture(Vec<Node>),
+	/// `()`, captures the current position
+	Position,
+	/// `%1` through `%9`
+	BackRef(u8),
+	/// `%bxy`
+	Balance(u8, u8),
+	/// `%b{open}{close}`, with the `extensions` feature
+	#[cfg(feature = "extensions")]
+	BalanceSeq(Vec<u8>, Vec<u8>),
+	/// `%f[set]`
+	Frontier(Set),
+}
+
+/// Matches exactly one character
+#[derive(Debug, Clone, PartialEq)]
+pub enum Single {
+	/// `.`
+	Any,
+	/// A literal byte, escaped with `%` if needed
+	Literal(u8),
+	/// `%a`, `%D`, etc. Always an ascii letter
+	Class(u8),
+	/// `[...]`
+	Set(Set),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantifier {
+	/// No suffix
+	One,
+	/// `?`
+	Optional,
+	/// `*`
+	ZeroOrMore,
+	/// `+`
+	OneOrMore,
+	/// `-`, shortest zero or more
+	Lazy,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Set {
+	/// `[^...]`
+	pub negated: bool,
+	pub items: Vec<SetItem>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetItem {
+	Literal(u8),
+	/// `%a`, always an ascii letter
+	Class(u8),
+	/// `a-z`, inclusive
+	Range(u8, u8),
+}
+
+impl Set {
+	/// Whether the byte `c` is in the set, as the matcher sees it with default options
+	pub fn contains(&self, c: u8) -> bool {
+		let mut src = Vec::new();
+		write_set(&mut src, self);
+		matchbracketclass(c as u32, &src, 0, src.len() - 1, Flags::DEFAULT)
+	}
+
+	/// Whether no byte is in the set, so a `%f` with it can never match
+	pub fn matches_nothing(&self) -> bool {
+		!(0..=255).any(|c| self.contains(c))
+	}
+
+	/// Whether `%f[set]` matches at `pos` in `text`: the byte before isn't in the set and the one at `pos` is.
+	/// Like in Lua, the start and end count as `\0`.
+	///
+	/// ```
+	/// use lupat::ast::Ast;
+	///
+	/// let ast = Ast::parse("%f[%w]the%f[%W]").unwrap();
+	/// let word_start = ast.frontiers()[0];
+	/// assert!(word_start.is_frontier(b"in the", 3));
+	/// assert!(!word_start.is_frontier(b"in the", 4));
+	/// assert!(ast.frontiers()[1].is_frontier(b"the", 3));
+	/// ```
+	pub fn is_frontier(&self, text: &[u8], pos: usize) -> bool {
+		let previous = if pos == 0 { 0 } else { text.get(pos - 1).copied().unwrap_or(0) };
+		let current = text.get(pos).copied().unwrap_or(0);
+		!self.contains(previous) && self.contains(current)
+	}
+}
+
+impl Ast {
+	/// Parses a pattern, failing on the same malformed patterns [Pattern::new](crate::Pattern::new) does
+	pub fn parse<S: AsRef<[u8]> + ?Sized>(pattern: &S) -> Result<Ast, Error> {
+		parse_escaped(pattern.as_ref(), L_ESC)
+	}
+
+	/// Number of captures, not counting the whole match
+	pub fn num_captures(&self) -> usize {
+		fn count(nodes: &[Node]) -> usize {
+			nodes.iter().map(|n| match n {
+				Node::Capture(inner) => 1 + count(inner),
+				Node::Position => 1,
+				_ => 0,
+			}).sum()
+		}
+		count(&self.nodes)
+	}
+
+	/// Shortest length in bytes any match can have
+	pub fn min_len(&self) -> usize {
+		min_len(&self.nodes, &mut Vec::new())
+	}
+
+	/// Longest length in bytes any match can have, `None` if it's unbounded
+	pub fn max_len(&self) -> Option<usize> {
+		max_len(&self.nodes, &mut Vec::new())
+	}
+
+	/// Whether every match is empty, because the pattern only has anchors, frontiers and position captures (or nothing at all)
+	pub fn is_zero_width(&self) -> bool {
+		self.max_len() == Some(0)
+	}
+
+	/// Shortest and longest length in bytes capture `i` can have, `None` for no limit.
+	/// Numbered like [Pattern::capture](crate::Pattern::capture), so 0 is the whole match. Captures the pattern doesn't have are `None`.
+	///
+	/// ```
+	/// use lupat::ast::Ast;
+	///
+	/// let ast = Ast::parse("(%u%u?)-(%d+)").unwrap();
+	/// assert_eq!(ast.capture_bounds(1), Some((1, Some(2))));
+	/// assert_eq!(ast.capture_bounds(2), Some((1, None)));
+	/// ```
+	pub fn capture_bounds(&self, i: usize) -> Option<(usize, Option<usize>)> {
+		if i == 0 {
+			return Some((self.min_len(), self.max_len()));
+		}
+		let mut mins = Vec::new();
+		let mut maxes = Vec::new();
+		min_len(&self.nodes, &mut mins);
+		max_len(&self.nodes, &mut maxes);
+		Some((*mins.get(i - 1)?, *maxes.get(i - 1)?))
+	}
+
+	/// The capture that capture `i` is nested in, 0 for the whole match if it's not inside another one.
+	/// Captures are numbered by where their `(` is, so a capture always comes after the one it's in, and before the ones in it.
+	/// `None` for the whole match itself and captures the pattern doesn't have.
+	///
+	/// ```
+	/// use lupat::ast::Ast;
+	///
+	/// let ast = Ast::parse("((%a)(%d()))(.)").unwrap();
+	/// assert_eq!(ast.capture_parent(1), Some(0));
+	/// assert_eq!(ast.capture_parent(4), Some(3));
+	/// assert_eq!(ast.capture_children(1), [2, 3]);
+	/// assert_eq!(ast.capture_children(0), [1, 5]);
+	/// ```
+	pub fn capture_parent(&self, i: usize) -> Option<usize> {
+		self.capture_parents().get(i.checked_sub(1)?).copied()
+	}
+
+	/// The captures directly inside capture `i`, in order, see [Ast::capture_parent]. Those of 0 are the outermost ones
+	pub fn capture_children(&self, i: usize) -> Vec<usize> {
+		let parents = self.capture_parents();
+		(1..=parents.len()).filter(|&c| parents[c - 1] == i).collect()
+	}
+
+	/// Parent of every capture, starting with capture 1
+	fn capture_parents(&self) -> Vec<usize> {
+		fn walk(nodes: &[Node], parent: usize, out: &mut Vec<usize>) {
+			for node in nodes {
+				match node {
+					Node::Capture(inner) => {
+						out.push(parent);
+						let number = out.len();
+						walk(inner, number, out);
+					}
+					Node::Position => out.push(parent),
+					_ => (),
+				}
+			}
+		}
+		let mut out = Vec::new();
+		walk(&self.nodes, 0, &mut out);
+		out
+	}
+
+	/// Whether every match and capture in valid UTF-8 starts and ends on char boundaries, so the str API never has to widen them.
+	/// `.`, negated classes and sets, and non-ascii bytes that aren't whole chars can all stop inside a char.
+	/// So can empty matches of unanchored patterns, as every byte offset is tried.
+	pub fn utf8_safe(&self) -> bool {
+		(self.anchor_start || self.min_len() > 0) && utf8_safe(&self.nodes, false)
+	}
+
+	/// The set of every `%f[set]`, in the order they appear, including ones inside captures.
+	/// A set that [matches nothing](Set::matches_nothing) makes its frontier, and so the whole pattern, never match.
+	pub fn frontiers(&self) -> Vec<&Set> {
+		fn collect<'a>(nodes: &'a [Node], out: &mut Vec<&'a Set>) {
+			for node in nodes {
+				match node {
+					Node::Frontier(set) => out.push(set),
+					Node::Capture(inner) => collect(inner, out),
+					_ => (),
+				}
+			}
+		}
+		let mut out = Vec::new();
+		collect(&self.nodes, &mut out);
+		out
+	}
+
+	/// Bytes every match starts with
+	pub fn literal_prefix(&self) -> Vec<u8> {
+		let mut out = Vec::new();
+		literal_affix(&self.nodes, &mut out, false);
+		out
+	}
+
+	/// Bytes every match ends with
+	pub fn literal_suffix(&self) -> Vec<u8> {
+		let mut out = Vec::new();
+		literal_affix(&self.nodes, &mut out, true);
+		out.reverse();
+		out
+	}
+
+	/// Rewrites into a canonical form that matches the same things.
+	/// Set items are sorted and deduplicated, and sets of a single item become that item.
+	/// Together with [Ast::to_bytes] only escaping what's needed, equivalent patterns render the same.
+	pub fn normalize(&mut self) {
+		normalize_nodes(&mut self.nodes);
+	}
+
+	/// Renders back into pattern source
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut out = Vec::new();
+		if self.anchor_start {
+			out.push(b'^');
+		}
+		write_nodes(&mut out, &self.nodes);
+		if self.anchor_end {
+			out.push(b'$');
+		}
+		out
+	}
+}
+
+impl fmt::Display for Ast {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&String::from_utf8_lossy(&self.to_bytes()))
+	}
+}
+
+/// Byte span in the pattern source of every capture, indexed like [Pattern::capture](crate::Pattern::capture).
+/// So the first one is the span of the whole pattern, then `(` of the first group to its `)`, and so on.
+pub fn capture_spans<S: AsRef<[u8]> + ?Sized>(pattern: &S) -> Result<Vec<ops::Range<usize>>, Error> {
+	capture_spans_escaped(pattern.as_ref(), L_ESC)
+}
+
+/// Like [Ast::parse], with `esc` starting escapes instead of `%`.
+/// The tree is the same as for the `%` version of the pattern, and renders as that.
+pub(crate) fn parse_escaped(pattern: &[u8], esc: u8) -> Result<Ast, Error> {
+	parse_spanned(pattern, esc).map(|(ast, _)| ast)
+}
+
+pub(crate) fn capture_spans_escaped(pattern: &[u8], esc: u8) -> Result<Vec<ops::Range<usize>>, Error> {
+	let (_, mut spans) = parse_spanned(pattern, esc)?;
+	spans.insert(0, 0..pattern.len());
+	Ok(spans)
+}
+
+fn parse_spanned(pattern: &[u8], esc: u8) -> Result<(Ast, Vec<ops::Range<usize>>), Error> {
+	let mut p = pattern;
+	let mut ast = Ast::default();
+	if let Some((b'^', rest)) = p.split_first() {
+		ast.anchor_start = true;
+		p = rest;
+	}
+	if let Some((b'$', rest)) = p.split_last() {
+		// `%$` is a literal
+		let escapes = rest.iter().rev().take_while(|&&c| c == esc).count();
+		if escapes % 2 == 0 {
+			ast.anchor_end = true;
+			p = rest;
+		}
+	}
+	let mut parser = Parser {
+		p,
+		esc,
+		pos: 0,
+		open: 0,
+		closed: Vec::new(),
+		offset: ast.anchor_start as usize,
+		spans: Vec::new(),
+	};
+	ast.nodes = parser.parse_seq()?;
+	if parser.pos < p.len() {
+		return Err(Error::NoOpenCapture);
+	}
+	Ok((ast, parser.spans))
+}
+
+struct Parser<'p> {
+	p: &'p [u8],
+	esc: u8,
+	pos: usize,
+	/// Number of captures opened so far
+	open: usize,
+	/// Whether each capture has been closed
+	closed: Vec<bool>,
+	/// Where `p` starts in the original pattern
+	offset: usize,
+	spans: Vec<ops::Range<usize>>,
+}
+
+impl<'p> Parser<'p> {
+	fn peek(&self) -> Option<u8> {
+		self.p.get(self.pos).copied()
+	}
+
+	fn peek_at(&self, n: usize) -> Option<u8> {
+		self.p.get(self.pos + n).copied()
+	}
+
+	/// Parses until the end of the pattern or an unmatched `)`
+	fn parse_seq(&mut self) -> Result<Vec<Node>, Error> {
+		let mut nodes = Vec::new();
+		while let Some(c) = self.peek() {
+			match c {
+				b'(' => {
+					if self.peek_at(1) == Some(b')') {
+						self.spans.push(self.offset + self.pos..self.offset + self.pos + 2);
+						self.pos += 2;
+						self.open += 1;
+						self.closed.push(true);
+						nodes.push(Node::Position);
+						continue;
+					}
+					let start = self.offset + self.pos;
+					self.pos += 1;
+					let index = self.open;
+					self.open += 1;
+					self.closed.push(false);
+					self.spans.push(start..start);
+					let inner = self.parse_seq()?;
+					if self.peek() != Some(b')') {
+						return Err(Error::UnfinishedCapture);
+					}
+					self.pos += 1;
+					self.closed[index] = true;
+					self.spans[index].end = self.offset + self.pos;
+					nodes.push(Node::Capture(inner));
+				}
+				b')' => break,
+				c if c == self.esc => match self.peek_at(1) {
+					None => return Err(Error::ends_with_escape(c)),
+					#[cfg(feature = "extensions")]
+					Some(b'b') if balance_seq(&self.p[self.pos + 2..]).is_some() => {
+						let (open, close, len) = balance_seq(&self.p[self.pos + 2..]).unwrap_or_default();
+						nodes.push(Node::BalanceSeq(open.to_vec(), close.to_vec()));
+						self.pos += 2 + len;
+					}
+					Some(b'b') => {
+						match (self.peek_at(2), self.peek_at(3)) {
+							(Some(open), Some(close)) => nodes.push(Node::Balance(open, close)),
+							_ => return Err(Error::MissingBalanceArgs),
+						}
+						self.pos += 4;
+					}
+					Some(b'f') => {
+						self.pos += 2;
+						if self.peek() != Some(b'[') {
+							return Err(Error::MissingLBracketF);
+						}
+						nodes.push(Node::Frontier(self.parse_set()?));
+					}
+					Some(d @ b'0'..=b'9') => {
+						let l = d as i8 - b'1' as i8;
+						if l < 0 || l as usize >= self.open || !self.closed[l as usize] {
+							return Err(Error::InvalidCapture(Some(l + 1)));
+						}
+						self.pos += 2;
+						nodes.push(Node::BackRef(d - b'0'));
+					}
+					Some(_) => {
+						let single = self.parse_single()?;
+						nodes.push(single);
+					}
+				},
+				_ => {
+					let single = self.parse_single()?;
+					nodes.push(single);
+				}
+			}
+		}
+		Ok(nodes)
+	}
+
+	fn parse_single(&mut self) -> Result<Node, Error> {
+		let single = match self.peek() {
+			Some(b'.') => {
+				self.pos += 1;
+				Single::Any
+			}
+			Some(b'[') => Single::Set(self.parse_set()?),
+			Some(c) if c == self.esc => {
+				let c = self.peek_at(1).ok_or(Error::ends_with_escape(c))?;
+				self.pos += 2;
+				if c.is_ascii_alphabetic() {
+					Single::Class(c)
+				} else {
+					Single::Literal(c)
+				}
+			}
+			Some(c) => {
+				self.pos += 1;
+				Single::Literal(c)
+			}
+			None => unreachable!("parse_single called at end of pattern"),
+		};
+		let quantifier = match self.peek() {
+			Some(b'?') => Quantifier::Optional,
+			Some(b'*') => Quantifier::ZeroOrMore,
+			Some(b'+') => Quantifier::OneOrMore,
+			Some(b'-') => Quantifier::Lazy,
+			_ => return Ok(Node::Single(single, Quantifier::One)),
+		};
+		self.pos += 1;
+		Ok(Node::Single(single, quantifier))
+	}
+
+	/// Parses `[...]`, starting at the `[`
+	fn parse_set(&mut self) -> Result<Set, Error> {
+		self.pos += 1;
+		let mut set = Set::default();
+		if self.peek() == Some(b'^') {
+			set.negated = true;
+			self.pos += 1;
+		}
+		// Like Lua, the end is found first, skipping escapes, and a `]` right after the `[` or `[^` is a literal.
+		// The items are then read up to it, so a range can end in the escape character, as in `[a-%]]`
+		let mut end = self.pos;
+		loop {
+			let c = *self.p.get(end).ok_or(Error::MissingEndBracket)?;
+			end += 1;
+			if c == self.esc && end < self.p.len() {
+				end += 1;
+			}
+			match self.p.get(end) {
+				None => return Err(Error::MissingEndBracket),
+				Some(b']') => break,
+				Some(_) => (),
+			}
+		}
+		while self.pos < end {
+			let c = self.p[self.pos];
+			if c == self.esc {
+				let c = self.p[self.pos + 1];
+				self.pos += 2;
+				set.items.push(if c.is_ascii_alphabetic() { SetItem::Class(c) } else { SetItem::Literal(c) });
+			} else if self.peek_at(1) == Some(b'-') && self.pos + 2 < end {
+				set.items.push(SetItem::Range(c, self.p[self.pos + 2]));
+				self.pos += 3;
+			} else {
+				set.items.push(SetItem::Literal(c));
+				self.pos += 1;
+			}
+		}
+		self.pos = end + 1;
+		Ok(set)
+	}
+}
+
+/// `any_is_char` is for `.` matching whole chars or more, like with grapheme clusters
+pub(crate) fn utf8_safe(nodes: &[Node], any_is_char: bool) -> bool {
+	let ascii_set = |set: &Set| {
+		!set.negated && set.items.iter().all(|item| match *item {
+			SetItem::Literal(c) => c.is_ascii(),
+			SetItem::Class(c) => c.is_ascii_lowercase(),
+			SetItem::Range(_, hi) => hi.is_ascii(),
+		})
+	};
+	let mut i = 0;
+	while i < nodes.len() {
+		let safe = match nodes[i] {
+			Node::Single(Single::Any, _) => any_is_char,
+			Node::Single(Single::Literal(c), _) if c.is_ascii() => true,
+			// The bytes of a whole char, one after the other
+			Node::Single(Single::Literal(_), Quantifier::One) => {
+				let bytes: Vec<u8> = nodes[i..].iter().take(4).map_while(|node| match *node {
+					Node::Single(Single::Literal(c), Quantifier::One) if !c.is_ascii() => Some(c),
+					_ => None,
+				}).collect();
+				match (1..=bytes.len()).find(|&len| std::str::from_utf8(&bytes[..len]).is_ok()) {
+					Some(len) => {
+						i += len;
+						continue;
+					}
+					None => false,
+				}
+			}
+			Node::Single(Single::Literal(_), _) => false,
+			Node::Single(Single::Class(c), _) => c.is_ascii_lowercase(),
+			Node::Single(Single::Set(ref set), _) => ascii_set(set),
+			Node::Capture(ref inner) => utf8_safe(inner, any_is_char),
+			Node::Position | Node::BackRef(_) | Node::Frontier(_) => true,
+			Node::Balance(open, close) => open.is_ascii() && close.is_ascii(),
+			#[cfg(feature = "extensions")]
+			Node::BalanceSeq(ref open, ref close) => std::str::from_utf8(open).is_ok() && std::str::from_utf8(close).is_ok(),
+		};
+		if !safe {
+			return false;
+		}
+		i += 1;
+	}
+	true
+}
+
+/// `caps` collects the minimum length of each capture as they're opened, for backreferences
+fn min_len(nodes: &[Node], caps: &mut Vec<usize>) -> usize {
+	let mut total = 0;
+	for node in nodes {
+		total += match node {
+			Node::Single(_, Quantifier::One | Quantifier::OneOrMore) => 1,
+			Node::Single(..) => 0,
+			Node::Capture(inner) => {
+				let index = caps.len();
+				caps.push(0);
+				let len = min_len(inner, caps);
+				caps[index] = len;
+				len
+			}
+			Node::Position => {
+				caps.push(0);
+				0
+			}
+			Node::BackRef(n) => (*n as usize).checked_sub(1).and_then(|i| caps.get(i)).copied().unwrap_or(0),
+			Node::Balance(..) => 2,
+			#[cfg(feature = "extensions")]
+			Node::BalanceSeq(open, close) => open.len() + close.len(),
+			Node::Frontier(_) => 0,
+		};
+	}
+	total
+}
+
+/// Keeps going past unbounded nodes, so every capture ends up in `caps`
+fn max_len(nodes: &[Node], caps: &mut Vec<Option<usize>>) -> Option<usize> {
+	let mut total = Some(0);
+	for node in nodes {
+		let len = match node {
+			Node::Single(_, Quantifier::One | Quantifier::Optional) => Some(1),
+			Node::Single(..) => None,
+			Node::Capture(inner) => {
+				let index = caps.len();
+				caps.push(None);
+				let len = max_len(inner, caps);
+				caps[index] = len;
+				len
+			}
+			Node::Position => {
+				caps.push(Some(0));
+				Some(0)
+			}
+			Node::BackRef(n) => (*n as usize).checked_sub(1).and_then(|i| caps.get(i)).copied().flatten(),
+			Node::Balance(..) => None,
+			#[cfg(feature = "extensions")]
+			Node::BalanceSeq(..) => None,
+			Node::Frontier(_) => Some(0),
+		};
+		total = total.zip(len).map(|(total, len)| total + len);
+	}
+	total
+}
+
+/// Collects literal bytes from the start (or end, backwards) of `nodes`.
+/// Returns whether every node was literal, so the caller can keep going.
+fn literal_affix(nodes: &[Node], out: &mut Vec<u8>, from_end: bool) -> bool {
+	let mut visit = |node: &Node| match node {
+		Node::Single(Single::Literal(c), Quantifier::One) => {
+			out.push(*c);
+			true
+		}
+		Node::Single(Single::Literal(c), Quantifier::OneOrMore) => {
+			out.push(*c);
+			false
+		}
+		Node::Capture(inner) => literal_affix(inner, out, from_end),
+		// Zero width
+		Node::Position | Node::Frontier(_) => true,
+		_ => false,
+	};
+	if from_end {
+		nodes.iter().rev().all(&mut visit)
+	} else {
+		nodes.iter().all(&mut visit)
+	}
+}
+
+fn normalize_nodes(nodes: &mut [Node]) {
+	for node in nodes {
+		match node {
+			Node::Single(single, _) => {
+				if let Single::Set(set) = single {
+					normalize_set(set);
+					let collapsed = match set.items[..] {
+						[SetItem::Literal(c)] if !set.negated => Some(Single::Literal(c)),
+						[SetItem::Class(c)] if !set.negated => Some(Single::Class(c)),
+						_ => None,
+					};
+					if let Some(collapsed) = collapsed {
+						*single = collapsed;
+					}
+				}
+			}
+			Node::Capture(inner) => normalize_nodes(inner),
+			Node::Frontier(set) => normalize_set(set),
+			_ => (),
+		}
+	}
+}
+
+fn normalize_set(set: &mut Set) {
+	for item in &mut set.items {
+		if let SetItem::Range(lo, hi) = *item {
+			if lo == hi {
+				*item = SetItem::Literal(lo);
+			}
+		}
+	}
+	set.items.sort_by_key(|item| match *item {
+		SetItem::Class(c) => (0, c, c),
+		SetItem::Range(lo, hi) => (1, lo, hi),
+		SetItem::Literal(c) => (2, c, c),
+	});
+	set.items.dedup();
+}
+
+/// Characters that have to be escaped to be literal
+fn is_magic(c: u8) -> bool {
+	matches!(c, b'^' | b'$' | b'(' | b')' | b'%' | b'.' | b'[' | b']' | b'*' | b'+' | b'-' | b'?')
+}
+
+fn write_nodes(out: &mut Vec<u8>, nodes: &[Node]) {
+	for node in nodes {
+		match node {
+			Node::Single(single, quantifier) => {
+				match single {
+					Single::Any => out.push(b'.'),
+					Single::Literal(c) => {
+						if is_magic(*c) {
+							out.push(L_ESC);
+						}
+						out.push(*c);
+					}
+					Single::Class(c) => out.extend_from_slice(&[L_ESC, *c]),
+					Single::Set(set) => write_set(out, set),
+				}
+				match quantifier {
+					Quantifier::One => (),
+					Quantifier::Optional => out.push(b'?'),
+					Quantifier::ZeroOrMore => out.push(b'*'),
+					Quantifier::OneOrMore => out.push(b'+'),
+					Quantifier::Lazy => out.push(b'-'),
+				}
+			}
+			Node::Capture(inner) => {
+				out.push(b'(');
+				write_nodes(out, inner);
+				out.push(b')');
+			}
+			Node::Position => out.extend_from_slice(b"()"),
+			Node::BackRef(n) => out.extend_from_slice(&[L_ESC, b'0' + n]),
+			Node::Balance(open, close) => out.extend_from_slice(&[L_ESC, b'b', *open, *close]),
+			#[cfg(feature = "extensions")]
+			Node::BalanceSeq(open, close) => {
+				out.extend_from_slice(&[L_ESC, b'b', b'{']);
+				out.extend_from_slice(open);
+				out.extend_from_slice(b"}{");
+				out.extend_from_slice(close);
+				out.push(b'}');
+			}
+			Node::Frontier(set) => {
+				out.extend_from_slice(b"%f");
+				write_set(out, set);
+			}
+		}
+	}
+}
+
+fn write_set(out: &mut Vec<u8>, set: &Set) {
+	out.push(b'[');
+	if set.negated {
+		out.push(b'^');
+	}
+	for (i, item) in set.items.iter().enumerate() {
+		match *item {
+			SetItem::Literal(c) => write_set_literal(out, c),
+			SetItem::Class(c) => out.extend_from_slice(&[L_ESC, c]),
+			SetItem::Range(lo, hi) if lo == hi => write_set_literal(out, lo),
+			// Empty, like Lua's `z-a`. Ends that would be read as something else are swapped for ones that aren't
+			SetItem::Range(lo, hi) if lo > hi => {
+				if matches!(lo, b'%' | b']' | b'^') || matches!(hi, b'%' | b']') {
+					out.extend_from_slice(b"b-a");
+				} else {
+					out.extend_from_slice(&[lo, b'-', hi]);
+				}
+			}
+			SetItem::Range(mut lo, mut hi) => {
+				// Range ends can't be escaped, so ones that would be read as something else are split off as literals.
+				// A `^` first would negate the set
+				if matches!(lo, b'%' | b']') || (lo == b'^' && i == 0) {
+					write_set_literal(out, lo);
+					lo += 1;
+				}
+				let last = hi;
+				if matches!(hi, b'%' | b']') {
+					hi -= 1;
+				}
+				match lo.cmp(&hi) {
+					cmp::Ordering::Less => out.extend_from_slice(&[lo, b'-', hi]),
+					cmp::Ordering::Equal => write_set_literal(out, lo),
+					cmp::Ordering::Greater => (),
+				}
+				if hi != last {
+					write_set_literal(out, last);
+				}
+			}
+		}
+	}
+	out.push(b']');
+}
+
+fn write_set_literal(out: &mut Vec<u8>, c: u8) {
+	if matches!(c, b'%' | b']' | b'^' | b'-' | b'[') {
+		out.push(L_ESC);
+	}
+	out.push(c);
+}
@@ -0,0 +1,270 @@
+//! Searches files for lines matching a Lua pattern.
+//!
+//! Directories are searched recursively, skipping hidden files and whatever git would ignore.
+//! Files with a NUL byte near the start are taken as binary and only reported as matching.
+//!
+//! `--json` prints one object per match instead, like
+//! `{"file":"a.lua","line":3,"start":40,"end":45,"captures":["x"]}` with `start` and `end` as byte offsets into the file.
+//! `-0` ends file names with a NUL instead of `:` or a newline, for `xargs -0`.
+//!
+//! Files are searched on `-j` threads, as many as there are cores by default, and printed in the same order as with one.
+//!
+//! `--patterns FILE` loads named patterns, one `name = pattern` per line like the build module's input,
+//! and a PATTERN of `@name` searches with the one registered under that name.
+
+extern crate ignore;
+extern crate lupat;
+
+use ignore::WalkBuilder;
+use lupat::{Pattern, Registry, LUA_MAXCAPTURES};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+const USAGE: &str = "usage: luagrep [-c] [-l] [-0] [--json] [-j THREADS] [--hidden] [--no-ignore] [--patterns FILE]... PATTERN|@NAME [PATH]...";
+
+/// How much of a file is checked for NUL bytes
+const BINARY_SNIFF: usize = 8192;
+
+#[derive(Default)]
+struct Options {
+	/// Only print how many lines matched in each file
+	count: bool,
+	/// Only print the names of files with matches
+	files_with_matches: bool,
+	/// One JSON object per match
+	json: bool,
+	/// End file names with NUL
+	null: bool,
+	hidden: bool,
+	no_ignore: bool,
+	/// How many files to search at once
+	threads: usize,
+	/// Files of named patterns
+	pattern_files: Vec<PathBuf>,
+	pattern: String,
+	paths: Vec<PathBuf>,
+}
+
+fn parse_args() -> Result<Options, String> {
+	let mut opts = Options::default();
+	let mut pattern = None;
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"-c" | "--count" => opts.count = true,
+			"-l" | "--files-with-matches" => opts.files_with_matches = true,
+			"-0" | "--null" => opts.null = true,
+			"--json" => opts.json = true,
+			"-j" | "--threads" => {
+				opts.threads = args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0).ok_or_else(|| format!("{} needs a number of threads\n{}", arg, USAGE))?
+			}
+			"--patterns" => opts.pattern_files.push(args.next().map(PathBuf::from).ok_or_else(|| format!("{} needs a file\n{}", arg, USAGE))?),
+			"--hidden" => opts.hidden = true,
+			"--no-ignore" => opts.no_ignore = true,
+			"-h" | "--help" => return Err(USAGE.to_owned()),
+			_ if arg.starts_with('-') && arg.len() > 1 => return Err(format!("unknown option {}\n{}", arg, USAGE)),
+			_ if pattern.is_none() => pattern = Some(arg),
+			_ => opts.paths.push(PathBuf::from(arg)),
+		}
+	}
+	opts.pattern = pattern.ok_or_else(|| USAGE.to_owned())?;
+	if opts.threads == 0 {
+		opts.threads = thread::available_parallelism().map_or(1, |n| n.get());
+	}
+	if opts.paths.is_empty() {
+		opts.paths.push(PathBuf::from("."));
+	}
+	Ok(opts)
+}
+
+/// Registers every `name = pattern` line of `text`. Empty lines and lines starting with `#` are skipped
+fn load_patterns(registry: &Registry, text: &str) -> Result<(), String> {
+	for (i, line) in text.lines().enumerate() {
+		if line.trim().is_empty() || line.trim_start().starts_with('#') {
+			continue;
+		}
+		let (name, patt) = line.split_once('=').ok_or_else(|| format!("line {}: expected `name = pattern`", i + 1))?;
+		let patt = patt.strip_prefix(' ').unwrap_or(patt);
+		registry.register(name.trim(), patt).map_err(|e| format!("line {}: {}", i + 1, e))?;
+	}
+	Ok(())
+}
+
+/// Every file under `path` to search, in a stable order.
+/// Ignore rules are git's: `.gitignore` files here and in parent directories, `.git/info/exclude` and the global excludes file,
+/// along with `.ignore` files like ripgrep.
+fn collect_files(path: &Path, opts: &Options, files: &mut Vec<PathBuf>) {
+	let walker = WalkBuilder::new(path)
+		.standard_filters(!opts.no_ignore)
+		.hidden(!opts.hidden)
+		.sort_by_file_name(|a, b| a.cmp(b))
+		.build();
+	for entry in walker {
+		match entry {
+			Ok(entry) if !entry.file_type().is_some_and(|t| t.is_dir()) => files.push(entry.into_path()),
+			Ok(_) => (),
+			Err(e) => eprintln!("luagrep: {}", e),
+		}
+	}
+}
+
+/// Appends `s` to `out` as a JSON string, replacing invalid UTF-8
+fn push_json_str(out: &mut String, s: &[u8]) {
+	out.push('"');
+	for c in String::from_utf8_lossy(s).chars() {
+		match c {
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			'\t' => out.push_str("\\t"),
+			c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+			c => out.push(c),
+		}
+	}
+	out.push('"');
+}
+
+/// Writes every match in `line`, which starts at `offset` in the file, as a JSON object
+fn write_json(patt: &mut Pattern, path: &Path, number: usize, line: &[u8], offset: usize, out: &mut dyn Write) -> io::Result<()> {
+	for ranges in patt.gmatch_ranges_bytes(line) {
+		let whole = &ranges[0];
+		let mut obj = String::from("{\"file\":");
+		push_json_str(&mut obj, path.to_string_lossy().as_bytes());
+		obj.push_str(&format!(",\"line\":{},\"start\":{},\"end\":{},\"captures\":[", number, offset + whole.start, offset + whole.end));
+		for (i, cap) in ranges[1..].iter().enumerate() {
+			if i > 0 {
+				obj.push(',');
+			}
+			push_json_str(&mut obj, &line[cap.start..cap.end.max(cap.start)]);
+		}
+		obj.push_str("]}");
+		writeln!(out, "{}", obj)?;
+	}
+	Ok(())
+}
+
+/// Searches one file, writing its results to `out`. Returns whether anything matched
+fn search(patt: &mut Pattern, path: &Path, opts: &Options, out: &mut dyn Write) -> io::Result<bool> {
+	let data = fs::read(path)?;
+	let binary = data[..data.len().min(BINARY_SNIFF)].contains(&0);
+	let sep = if opts.null { "\0" } else { ":" };
+	let mut count = 0;
+	let mut offset = 0;
+	let text = data.strip_suffix(b"\n").unwrap_or(&data);
+	for (i, line) in text.split(|&b| b == b'\n').enumerate() {
+		let line_start = offset;
+		offset += line.len() + 1;
+		let line = line.strip_suffix(b"\r").unwrap_or(line);
+		if !patt.matches_bytes(line) {
+			continue;
+		}
+		count += 1;
+		if opts.count || opts.files_with_matches {
+			continue;
+		}
+		if opts.json {
+			write_json(patt, path, i + 1, line, line_start, out)?;
+		} else if !binary {
+			writeln!(out, "{}{}{}:{}", path.display(), sep, i + 1, String::from_utf8_lossy(line))?;
+		}
+	}
+	if count > 0 {
+		if opts.files_with_matches {
+			write!(out, "{}{}", path.display(), if opts.null { "\0" } else { "\n" })?;
+		} else if opts.count {
+			writeln!(out, "{}{}{}", path.display(), sep, count)?;
+		} else if binary && !opts.json {
+			writeln!(out, "Binary file {} matches", path.display())?;
+		}
+	}
+	Ok(count > 0)
+}
+
+fn main() {
+	let mut opts = match parse_args() {
+		Ok(opts) => opts,
+		Err(msg) => {
+			eprintln!("{}", msg);
+			process::exit(2);
+		}
+	};
+	let registry = Registry::new();
+	for file in &opts.pattern_files {
+		if let Err(e) = fs::read_to_string(file).map_err(|e| e.to_string()).and_then(|text| load_patterns(&registry, &text)) {
+			eprintln!("luagrep: {}: {}", file.display(), e);
+			process::exit(2);
+		}
+	}
+	if let Some(name) = opts.pattern.strip_prefix('@') {
+		match registry.get(name) {
+			Some(patt) => opts.pattern = patt.source().to_owned(),
+			None => {
+				eprintln!("luagrep: {}", lupat::error::Error::UnknownPattern(name.to_owned()));
+				process::exit(2);
+			}
+		}
+	}
+	if let Err(e) = Pattern::<LUA_MAXCAPTURES>::new(&opts.pattern) {
+		eprintln!("luagrep: {}", e);
+		process::exit(2);
+	}
+
+	let mut files = Vec::new();
+	for path in &opts.paths {
+		collect_files(path, &opts, &mut files);
+	}
+
+	// Workers take files in order and send back their output, which is printed once every file before it has been
+	let next = AtomicUsize::new(0);
+	let (tx, rx) = mpsc::channel();
+	let mut matched = false;
+	thread::scope(|scope| {
+		for _ in 0..opts.threads.min(files.len()) {
+			let tx = tx.clone();
+			let (next, files, opts) = (&next, &files, &opts);
+			scope.spawn(move || {
+				let mut patt: Pattern = Pattern::new(&opts.pattern).expect("checked in main");
+				loop {
+					let i = next.fetch_add(1, Ordering::Relaxed);
+					let file = match files.get(i) {
+						Some(file) => file,
+						None => break,
+					};
+					let mut buf = Vec::new();
+					let res = search(&mut patt, file, opts, &mut buf);
+					if tx.send((i, buf, res)).is_err() {
+						break;
+					}
+				}
+			});
+		}
+		drop(tx);
+
+		let stdout = io::stdout();
+		let mut out = stdout.lock();
+		let mut pending = BTreeMap::new();
+		let mut printed = 0;
+		for (i, buf, res) in rx {
+			pending.insert(i, (buf, res));
+			while let Some((buf, res)) = pending.remove(&printed) {
+				// Whatever was written before an error is still printed
+				if out.write_all(&buf).is_err() {
+					process::exit(2);
+				}
+				match res {
+					Ok(found) => matched |= found,
+					Err(e) => eprintln!("luagrep: {}: {}", files[printed].display(), e),
+				}
+				printed += 1;
+			}
+		}
+	});
+	process::exit(if matched { 0 } else { 1 });
+}
@@ -0,0 +1,170 @@
+//! An interactive playground for Lua patterns.
+//!
+//! A plain line sets the pattern, `:t TEXT` the text to match it against and `:r REPL` the gsub replacement.
+//! After each change the matches, their captures and the gsub result are shown, or where the pattern fails to compile.
+
+extern crate lupat;
+
+use lupat::error::Error;
+use lupat::{LuaMatch, Pattern, TokenKind, LUA_MAXCAPTURES};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+PATTERN    set the pattern
+:t TEXT    set the text to match against
+:r REPL    set the gsub replacement
+:h         show this
+:q         quit";
+
+#[derive(Default)]
+struct State {
+	pattern: String,
+	text: String,
+	repl: String,
+}
+
+/// Column of `pos` in `s`, counting chars
+fn column(s: &str, pos: usize) -> usize {
+	s.get(..pos).map_or(pos, |before| before.chars().count())
+}
+
+/// Where in `pattern` the error `err` comes from, as a byte offset, if it can be pinned down
+fn error_position(pattern: &str, err: &Error) -> Option<usize> {
+	let tokens: Vec<_> = lupat::tokens(pattern).collect();
+	let first = |kind: TokenKind| tokens.iter().find(|t| t.kind == kind).map(|t| t.span.start);
+	match err {
+		Error::UnfinishedCapture => {
+			let mut open = Vec::new();
+			for t in &tokens {
+				match t.kind {
+					TokenKind::CaptureOpen => open.push(t.span.start),
+					TokenKind::CaptureClose => {
+						open.pop();
+					}
+					_ => (),
+				}
+			}
+			open.pop()
+		}
+		Error::NoOpenCapture => {
+			let mut depth = 0usize;
+			for t in &tokens {
+				match t.kind {
+					TokenKind::CaptureOpen => depth += 1,
+					TokenKind::CaptureClose if depth == 0 => return Some(t.span.start),
+					TokenKind::CaptureClose => depth -= 1,
+					_ => (),
+				}
+			}
+			None
+		}
+		Error::InvalidCapture(Some(n)) => tokens
+			.iter()
+			.find(|t| t.kind == TokenKind::BackRef && pattern.as_bytes()[t.span.start + 1] == b'0' + *n as u8)
+			.map(|t| t.span.start),
+		Error::EndsWithPercent | Error::MissingEndBracket | Error::MissingBalanceArgs => first(TokenKind::Invalid),
+		Error::MissingLBracketF => first(TokenKind::Frontier),
+		_ => None,
+	}
+}
+
+fn show_error(out: &mut dyn Write, pattern: &str, err: &Error) -> io::Result<()> {
+	writeln!(out, "  {}", pattern)?;
+	if let Some(pos) = error_position(pattern, err) {
+		writeln!(out, "  {}^", " ".repeat(column(pattern, pos)))?;
+	}
+	writeln!(out, "error: {}", err)?;
+	if let Some(hint) = err.suggestion(pattern) {
+		writeln!(out, "hint: {}", hint)?;
+	}
+	Ok(())
+}
+
+fn show(out: &mut dyn Write, state: &State) -> io::Result<()> {
+	let mut patt: Pattern = match Pattern::new(&state.pattern) {
+		Ok(patt) => patt,
+		Err(e) => return show_error(out, &state.pattern, &e),
+	};
+	let text = &state.text;
+
+	let mut slots = [LuaMatch { start: 0, end: 0 }; LUA_MAXCAPTURES];
+	let mut found = Vec::new();
+	let mut init = 0;
+	while init <= text.len() {
+		match patt.find_into(text.as_bytes(), &mut slots, init, false) {
+			Ok(0) => break,
+			Ok(n) => {
+				let whole = slots[0];
+				found.push(slots[..n].to_vec());
+				// Steps past empty matches so they aren't found again
+				init = if whole.end > whole.start { whole.end } else { whole.start + 1 };
+			}
+			Err(e) => {
+				writeln!(out, "error: {}", e)?;
+				return Ok(());
+			}
+		}
+	}
+
+	if found.is_empty() {
+		writeln!(out, "no match")?;
+	} else {
+		// Marks every match under the text
+		let mut marks = vec![' '; text.chars().count() + 1];
+		for m in &found {
+			let (start, end) = (column(text, m[0].start), column(text, m[0].end));
+			if start == end {
+				marks[start] = '|';
+			}
+			for mark in &mut marks[start..end] {
+				*mark = '^';
+			}
+		}
+		writeln!(out, "  {}", text)?;
+		writeln!(out, "  {}", marks.iter().collect::<String>().trim_end())?;
+		for (i, m) in found.iter().enumerate() {
+			let whole = &m[0];
+			write!(out, "{}: {}..{} {:?}", i + 1, whole.start, whole.end, String::from_utf8_lossy(&text.as_bytes()[whole.start..whole.end]))?;
+			for (c, cap) in m[1..].iter().enumerate() {
+				write!(out, "  %{}={:?}", c + 1, String::from_utf8_lossy(&text.as_bytes()[cap.start..cap.end]))?;
+			}
+			writeln!(out)?;
+		}
+	}
+
+	match patt.gsub(text, &state.repl) {
+		Ok(res) => writeln!(out, "gsub: {:?}", res)?,
+		Err(e) => writeln!(out, "gsub: error: {}", e)?,
+	}
+	Ok(())
+}
+
+fn main() -> io::Result<()> {
+	let stdin = io::stdin();
+	let stdout = io::stdout();
+	let mut out = stdout.lock();
+	let mut state = State::default();
+	writeln!(out, "{}", HELP)?;
+	loop {
+		write!(out, "> ")?;
+		out.flush()?;
+		let mut line = String::new();
+		if stdin.lock().read_line(&mut line)? == 0 {
+			return Ok(());
+		}
+		let line = line.trim_end_matches(&['\r', '\n'][..]);
+		if line == ":q" {
+			return Ok(());
+		} else if line == ":h" {
+			writeln!(out, "{}", HELP)?;
+			continue;
+		} else if let Some(text) = line.strip_prefix(":t") {
+			state.text = text.strip_prefix(' ').unwrap_or(text).to_owned();
+		} else if let Some(repl) = line.strip_prefix(":r") {
+			state.repl = repl.strip_prefix(' ').unwrap_or(repl).to_owned();
+		} else {
+			state.pattern = line.to_owned();
+		}
+		show(&mut out, &state)?;
+	}
+}
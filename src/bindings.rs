@@ -0,0 +1,220 @@
+//! Owned, lifetime-free wrappers over the core API, for a separate binding crate to forward to.
+//! Everything here takes and returns owned values with `u64` offsets.
+//!
+//! With the `uniffi` feature [CompiledPattern], [Span] and [Error] are exported through uniffi's proc macros,
+//! so `uniffi-bindgen generate --library` on the built cdylib writes the Swift, Kotlin and Python bindings.
+//! The patterns are shared objects there, and errors are thrown with their message.
+//! The `lupat-node` crate in this repository wraps these for Node.js with napi-rs, with offsets from [spans_to_utf16].
+
+use pattern::{Flags, LuaMatch, SetBitmap};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use {char_range, Captures, Error, MatchCursor, Pattern, PatternOptions, LUA_MAXCAPTURES};
+
+/// A match, with byte offsets into the text
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct Span {
+	pub start: u64,
+	pub end: u64,
+	pub text: String,
+}
+
+impl Span {
+	/// The same span with offsets in UTF-16 code units, how JavaScript and Java strings are indexed.
+	/// `text` has to be the text this span came from. Use [spans_to_utf16] for many spans of the same text.
+	pub fn to_utf16(&self, text: &str) -> Span {
+		spans_to_utf16(text, std::slice::from_ref(self)).remove(0)
+	}
+}
+
+/// [Span::to_utf16] for every span, in a single pass over `text` however many spans there are
+///
+/// ```
+/// use lupat::bindings::{spans_to_utf16, CompiledPattern};
+///
+/// let text = "😀 a=1 😀 b=2";
+/// let spans = CompiledPattern::new("%a=%d").unwrap().find_all(text);
+/// let offsets: Vec<_> = spans_to_utf16(text, &spans).iter().map(|s| (s.start, s.end)).collect();
+/// assert_eq!(offsets, [(3, 6), (10, 13)]);
+/// ```
+pub fn spans_to_utf16(text: &str, spans: &[Span]) -> Vec<Span> {
+	// Every offset, visited in order while counting units once
+	let mut offsets: Vec<(u64, usize)> = spans.iter().enumerate().flat_map(|(i, span)| [(span.start, 2 * i), (span.end, 2 * i + 1)]).collect();
+	offsets.sort_unstable();
+	let mut units = vec![0; offsets.len()];
+	let mut chars = text.char_indices().peekable();
+	let mut count = 0;
+	for (offset, slot) in offsets {
+		while let Some(&(i, c)) = chars.peek() {
+			if i as u64 >= offset {
+				break;
+			}
+			count += c.len_utf16() as u64;
+			chars.next();
+		}
+		units[slot] = count;
+	}
+	spans
+		.iter()
+		.enumerate()
+		.map(|(i, span)| Span {
+			start: units[2 * i],
+			end: units[2 * i + 1],
+			text: span.text.clone(),
+		})
+		.collect()
+}
+
+/// A compiled pattern that owns its source, with room for [LUA_MAXCAPTURES] captures
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
+pub struct CompiledPattern {
+	source: String,
+	flags: Flags,
+	sets: Vec<SetBitmap>,
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl CompiledPattern {
+	/// Compiles `source`, failing like [Pattern::new]
+	#[cfg_attr(feature = "uniffi", uniffi::constructor)]
+	pub fn new(source: &str) -> Result<Self, Error> {
+		CompiledPattern::with_options(Pattern::builder(source))
+	}
+
+	pub fn is_match(&self, text: &str) -> bool {
+		self.pattern().matches(text)
+	}
+
+	/// The first match
+	pub fn find(&self, text: &str) -> Option<Span> {
+		let mut patt = self.pattern();
+		patt.find_match(text).map(|m| span(text, m.range()))
+	}
+
+	/// The captures of the first match like Lua's `string.match`, or the whole match if there are none
+	pub fn match_captures(&self, text: &str) -> Option<Vec<String>> {
+		let mut patt = self.pattern();
+		if !patt.matches(text) {
+			return None;
+		}
+		Some(lua_captures(&patt, text))
+	}
+
+	/// The captures of every match like Lua's `string.gmatch`
+	pub fn gmatch(&self, text: &str) -> Vec<Vec<String>> {
+		let mut patt = self.pattern();
+		let mut cursor = MatchCursor::new();
+		let mut res = Vec::new();
+		while cursor.advance_str(&mut patt, text) {
+			res.push(lua_captures(&patt, text));
+		}
+		res
+	}
+
+	/// Every match
+	pub fn find_all(&self, text: &str) -> Vec<Span> {
+		let mut patt = self.pattern();
+		patt.find_all(text).into_iter().map(|range| span(text, range)).collect()
+	}
+
+	/// See [Pattern::gsub]
+	#[cfg(feature = "gsub")]
+	pub fn gsub(&self, text: &str, repl: &str) -> Result<String, Error> {
+		self.pattern().gsub(text, repl)
+	}
+}
+
+impl CompiledPattern {
+	/// Compiles a pattern built with options
+	pub fn with_options(options: PatternOptions) -> Result<Self, Error> {
+		let patt = options.build()?;
+		Ok(CompiledPattern {
+			source: String::from_utf8_lossy(patt.patt).into_owned(),
+			flags: patt.flags,
+			sets: patt.sets.into_owned(),
+		})
+	}
+
+	pub fn source(&self) -> &str {
+		&self.source
+	}
+
+	/// A [Pattern] borrowing this, for the rest of the API
+	pub fn pattern(&self) -> Pattern<'_, LUA_MAXCAPTURES> {
+		// Already checked in `with_options`, the source can't have changed since
+		Pattern {
+			patt: self.source.as_bytes(),
+			matches: [LuaMatch { start: 0, end: 0 }; LUA_MAXCAPTURES],
+			n_match: 0,
+			flags: self.flags,
+			sets: Cow::Borrowed(&self.sets),
+		}
+	}
+}
+
+/// The source of the first handle, so 0 can mean no pattern on the other side
+const FIRST_HANDLE: u64 = 1;
+
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(FIRST_HANDLE);
+static HANDLES: RwLock<Option<HashMap<u64, Arc<CompiledPattern>>>> = RwLock::new(None);
+
+// A panic while holding the lock can't leave the map half updated, so poisoning is ignored
+fn handles() -> RwLockReadGuard<'static, Option<HashMap<u64, Arc<CompiledPattern>>>> {
+	HANDLES.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn handles_mut() -> RwLockWriteGuard<'static, Option<HashMap<u64, Arc<CompiledPattern>>>> {
+	HANDLES.write().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Compiles `source` into the process wide handle table, for callers that shouldn't hold pointers into Rust memory.
+/// Handles are never 0 and never reused, so a destroyed one can't end up naming another pattern.
+/// The table can be used from any thread.
+///
+/// ```
+/// use lupat::bindings;
+///
+/// let id = bindings::handle_create("(%d+)").unwrap();
+/// assert!(bindings::handle_get(id).unwrap().is_match("a1"));
+/// assert!(bindings::handle_destroy(id));
+/// assert!(bindings::handle_get(id).is_none());
+/// ```
+pub fn handle_create(source: &str) -> Result<u64, Error> {
+	Ok(handle_insert(CompiledPattern::new(source)?))
+}
+
+/// Stores an already compiled pattern in the handle table, see [handle_create]
+pub fn handle_insert(patt: CompiledPattern) -> u64 {
+	let id = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+	handles_mut().get_or_insert_with(HashMap::new).insert(id, Arc::new(patt));
+	id
+}
+
+/// The pattern behind `id`. It stays usable even if the handle is destroyed meanwhile
+pub fn handle_get(id: u64) -> Option<Arc<CompiledPattern>> {
+	handles().as_ref()?.get(&id).cloned()
+}
+
+/// Removes `id` from the table, returning whether it was there
+pub fn handle_destroy(id: u64) -> bool {
+	handles_mut().as_mut().is_some_and(|table| table.remove(&id).is_some())
+}
+
+fn span(text: &str, range: std::ops::Range<usize>) -> Span {
+	let range = char_range(text, range);
+	Span {
+		start: range.start as u64,
+		end: range.end as u64,
+		text: text[range].to_owned(),
+	}
+}
+
+fn lua_captures(patt: &Pattern, text: &str) -> Vec<String> {
+	let captures = Captures { m: patt, text };
+	// Like Lua, `get(1)` is the whole match without captures
+	(1..captures.num_matches().max(2)).map(|i| captures.get(i).to_owned()).collect()
+}
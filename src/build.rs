@@ -0,0 +1,74 @@
+//! Helpers for build scripts, to validate patterns at build time and embed them as constants.
+//!
+//! The input has one `NAME = pattern` per line. Everything after the first `=` (and one following space) is the pattern.
+//! Empty lines and lines starting with `#` are skipped.
+//!
+//! ```no_run
+//! // in build.rs's main
+//! let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("patterns.rs");
+//! lupat::build::generate("patterns.txt", out).unwrap();
+//! println!("cargo:rerun-if-changed=patterns.txt");
+//! ```
+//!
+//! Each pattern becomes a `NAME: &str` constant and a `NAME_CAPTURES: usize` constant,
+//! which is the capture capacity a [Pattern](crate::Pattern) needs for it.
+
+use ast::Ast;
+use error::Error;
+use pattern::LUA_MAXCAPTURES;
+use std::{fmt, fs, io, path::Path};
+use Pattern;
+
+#[derive(Debug, PartialEq)]
+pub enum BuildError {
+	/// Line isn't `NAME = pattern`, or the name isn't an identifier
+	Syntax { line: usize },
+	/// Pattern failed to compile
+	Pattern { line: usize, name: String, error: Error },
+}
+
+impl fmt::Display for BuildError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			BuildError::Syntax { line } => write!(f, "line {}: expected `NAME = pattern`", line),
+			BuildError::Pattern { line, name, error } => write!(f, "line {}: pattern {} is malformed: {}", line, name, error),
+		}
+	}
+}
+
+impl std::error::Error for BuildError {}
+
+/// Validates every pattern in `source`, generating the source of a Rust module with the constants
+pub fn generate_module(source: &str) -> Result<String, BuildError> {
+	let mut ident: Pattern<'_, 1> = Pattern::new("^[%a_][%w_]*$").expect("ident pattern is valid");
+	let mut out = String::from("// Generated by lupat::build, do not edit\n");
+	for (i, line) in source.lines().enumerate() {
+		let line_no = i + 1;
+		if line.trim().is_empty() || line.trim_start().starts_with('#') {
+			continue;
+		}
+		let (name, patt) = line.split_once('=').ok_or(BuildError::Syntax { line: line_no })?;
+		let name = name.trim();
+		let patt = patt.strip_prefix(' ').unwrap_or(patt);
+		if !ident.matches(name) {
+			return Err(BuildError::Syntax { line: line_no });
+		}
+
+		let checked: Result<Pattern<'_, LUA_MAXCAPTURES>, Error> = Pattern::new(patt);
+		let captures = checked
+			.and_then(|_| Ast::parse(patt))
+			.map(|ast| ast.num_captures() + 1)
+			.map_err(|error| BuildError::Pattern { line: line_no, name: name.to_owned(), error })?;
+
+		out.push_str(&format!("pub const {}: &str = {:?};\n", name, patt));
+		out.push_str(&format!("pub const {}_CAPTURES: usize = {};\n", name, captures));
+	}
+	Ok(out)
+}
+
+/// Reads patterns from `input` and writes the generated module to `output`, see [generate_module]
+pub fn generate<I: AsRef<Path>, O: AsRef<Path>>(input: I, output: O) -> io::Result<()> {
+	let source = fs::read_to_string(input)?;
+	let module = generate_module(&source).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+	fs::write(output, module)
+}
@@ -0,0 +1,304 @@
+use ast::{Ast, Node, Quantifier, Set, SetItem, Single};
+use error::Error;
+use std::ops;
+use Pattern;
+
+/// Character classes usable with [PatternBuilder::class]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+	/// `%a`
+	Letter,
+	/// `%c`
+	Control,
+	/// `%d`
+	Digit,
+	/// `%g`
+	Printable,
+	/// `%l`
+	Lower,
+	/// `%p`
+	Punctuation,
+	/// `%s`
+	Space,
+	/// `%u`
+	Upper,
+	/// `%w`
+	Alphanumeric,
+	/// `%x`
+	Hex,
+}
+
+impl Class {
+	const fn letter(self) -> u8 {
+		match self {
+			Class::Letter => b'a',
+			Class::Control => b'c',
+			Class::Digit => b'd',
+			Class::Printable => b'g',
+			Class::Lower => b'l',
+			Class::Punctuation => b'p',
+			Class::Space => b's',
+			Class::Upper => b'u',
+			Class::Alphanumeric => b'w',
+			Class::Hex => b'x',
+		}
+	}
+}
+
+/// Assembles a pattern piece by piece, taking care of escaping.
+///
+/// ```
+/// use lupat::{PatternBuilder, Class};
+///
+/// let pattern = PatternBuilder::new()
+///     .anchor_start()
+///     .capture(PatternBuilder::new().class(Class::Letter).one_or_more())
+///     .literal("=")
+///     .capture(PatternBuilder::new().any().zero_or_more())
+///     .build();
+///
+/// assert_eq!(pattern, "^(%a+)=(.*)");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PatternBuilder {
+	ast: Ast,
+}
+
+impl PatternBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Matches `text` exactly
+	pub fn literal(mut self, text: &str) -> Self {
+		for &c in text.as_bytes() {
+			self.ast.nodes.push(Node::Single(Single::Literal(c), Quantifier::One));
+		}
+		self
+	}
+
+	/// `.`
+	pub fn any(mut self) -> Self {
+		self.ast.nodes.push(Node::Single(Single::Any, Quantifier::One));
+		self
+	}
+
+	/// `%a`, `%d`, ...
+	pub fn class(mut self, class: Class) -> Self {
+		self.ast.nodes.push(Node::Single(Single::Class(class.letter()), Quantifier::One));
+		self
+	}
+
+	/// `%A`, `%D`, ...
+	pub fn not_class(mut self, class: Class) -> Self {
+		let c = class.letter().to_ascii_uppercase();
+		self.ast.nodes.push(Node::Single(Single::Class(c), Quantifier::One));
+		self
+	}
+
+	/// Any of the characters in `chars`
+	///
+	/// # Panics
+	/// If `chars` is empty, as Lua has no empty sets.
+	pub fn set(mut self, chars: &str) -> Self {
+		assert!(!chars.is_empty(), "sets need at least one character");
+		self.ast.nodes.push(Node::Single(Single::Set(literal_set(chars, false)), Quantifier::One));
+		self
+	}
+
+	/// None of the characters in `chars`, see [PatternBuilder::set]
+	pub fn not_set(mut self, chars: &str) -> Self {
+		assert!(!chars.is_empty(), "sets need at least one character");
+		self.ast.nodes.push(Node::Single(Single::Set(literal_set(chars, true)), Quantifier::One));
+		self
+	}
+
+	/// Wraps `inner` in a capture group
+	pub fn capture(mut self, inner: PatternBuilder) -> Self {
+		self.ast.nodes.push(Node::Capture(inner.ast.nodes));
+		self
+	}
+
+	/// `()`, captures the current position
+	pub fn position(mut self) -> Self {
+		self.ast.nodes.push(Node::Position);
+		self
+	}
+
+	/// `%1` through `%9`, matches the same text as an earlier capture
+	///
+	/// # Panics
+	/// If there's no `n`th capture before it.
+	pub fn backref(mut self, n: u8) -> Self {
+		assert!(n > 0 && n as usize <= self.ast.num_captures(), "backreference %{} has no capture to refer to", n);
+		self.ast.nodes.push(Node::BackRef(n));
+		self
+	}
+
+	/// `%bxy`
+	pub fn balanced(mut self, open: u8, close: u8) -> Self {
+		self.ast.nodes.push(Node::Balance(open, close));
+		self
+	}
+
+	/// `%f[%w]`, the start of a word
+	pub fn word_start(mut self) -> Self {
+		self.ast.nodes.push(word_frontier(false));
+		self
+	}
+
+	/// `%f[%W]`, the end of a word
+	pub fn word_end(mut self) -> Self {
+		self.ast.nodes.push(word_frontier(true));
+		self
+	}
+
+	/// Appends `inner` between [PatternBuilder::word_start] and [PatternBuilder::word_end], so it only matches whole words
+	pub fn word(self, inner: PatternBuilder) -> Self {
+		self.word_start().then(inner).word_end()
+	}
+
+	/// Appends everything in `other`. Its anchors are ignored.
+	/// Its backreferences are renumbered to still refer to its own captures, which come after the ones already here.
+	///
+	/// # Panics
+	/// If a backreference would end up past `%9`, see [PatternBuilder::try_concat] for a fallible version.
+	pub fn then(self, other: PatternBuilder) -> Self {
+		match self.try_concat(other) {
+			Ok(builder) => builder,
+			Err(_) => panic!("backreferences can't go past %9"),
+		}
+	}
+
+	/// Same as [PatternBuilder::then], but fails with [Error::TooManyCaptures] if a backreference would end up past `%9`.
+	pub fn try_concat(mut self, other: PatternBuilder) -> Result<Self, Error> {
+		let offset = self.ast.num_captures();
+		let mut nodes = other.ast.nodes;
+		renumber_backrefs(&mut nodes, offset)?;
+		self.ast.nodes.extend(nodes);
+		Ok(self)
+	}
+
+	/// `^`
+	pub fn anchor_start(mut self) -> Self {
+		self.ast.anchor_start = true;
+		self
+	}
+
+	/// `$`
+	pub fn anchor_end(mut self) -> Self {
+		self.ast.anchor_end = true;
+		self
+	}
+
+	/// `?` on the previous single character item
+	///
+	/// # Panics
+	/// If the previous item isn't a single character, as Lua can only repeat those.
+	pub fn optional(self) -> Self {
+		self.quantify(Quantifier::Optional)
+	}
+
+	/// `*` on the previous single character item, see [PatternBuilder::optional]
+	pub fn zero_or_more(self) -> Self {
+		self.quantify(Quantifier::ZeroOrMore)
+	}
+
+	/// `+` on the previous single character item, see [PatternBuilder::optional]
+	pub fn one_or_more(self) -> Self {
+		self.quantify(Quantifier::OneOrMore)
+	}
+
+	/// `-` on the previous single character item, see [PatternBuilder::optional]
+	pub fn lazy(self) -> Self {
+		self.quantify(Quantifier::Lazy)
+	}
+
+	fn quantify(mut self, quantifier: Quantifier) -> Self {
+		match self.ast.nodes.last_mut() {
+			Some(Node::Single(_, q)) if *q == Quantifier::One => *q = quantifier,
+			_ => panic!("quantifiers can only follow a single unquantified character item"),
+		}
+		self
+	}
+
+	pub fn into_ast(self) -> Ast {
+		self.ast
+	}
+
+	/// Renders the pattern source
+	pub fn build(&self) -> String {
+		self.ast.to_string()
+	}
+}
+
+/// Same as [PatternBuilder::then], so fragments can be composed like `ident() + ws() + number()`
+///
+/// ```
+/// use lupat::PatternBuilder;
+///
+/// fn quoted() -> PatternBuilder {
+///     PatternBuilder::new().capture(PatternBuilder::new().set("'\"")).any().lazy().backref(1)
+/// }
+/// let pair = quoted() + PatternBuilder::new().literal("=") + quoted();
+/// assert_eq!(pair.build(), "(['\"]).-%1=(['\"]).-%2");
+/// ```
+///
+/// # Panics
+/// If a backreference would end up past `%9`, use [PatternBuilder::try_concat] to handle that.
+impl ops::Add for PatternBuilder {
+	type Output = PatternBuilder;
+
+	fn add(self, other: PatternBuilder) -> PatternBuilder {
+		self.then(other)
+	}
+}
+
+impl<'a> Pattern<'a> {
+	/// Wraps the pattern source `fragment` in `%f[%w]` and `%f[%W]` so it only matches whole words, like `\b` elsewhere.
+	/// Returns the new source, as patterns borrow theirs.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let src = Pattern::word("cat%a*").unwrap();
+	/// assert_eq!(src, "%f[%w]cat%a*%f[%W]");
+	/// let mut patt: Pattern = Pattern::new(&src).unwrap();
+	/// assert_eq!(patt.find_all("cats concatenate catalog"), [0..4, 17..24]);
+	/// ```
+	pub fn word<S: AsRef<[u8]> + ?Sized>(fragment: &S) -> Result<String, Error> {
+		let mut ast = Ast::parse(fragment)?;
+		ast.nodes.insert(0, word_frontier(false));
+		ast.nodes.push(word_frontier(true));
+		Ok(ast.to_string())
+	}
+}
+
+fn renumber_backrefs(nodes: &mut [Node], offset: usize) -> Result<(), Error> {
+	for node in nodes {
+		match node {
+			Node::BackRef(n) if *n > 0 => {
+				let renumbered = *n as usize + offset;
+				if renumbered > 9 {
+					return Err(Error::TooManyCaptures);
+				}
+				*n = renumbered as u8;
+			}
+			Node::Capture(inner) => renumber_backrefs(inner, offset)?,
+			_ => (),
+		}
+	}
+	Ok(())
+}
+
+fn word_frontier(negated: bool) -> Node {
+	let class = if negated { b'W' } else { b'w' };
+	Node::Frontier(Set { negated: false, items: vec![SetItem::Class(class)] })
+}
+
+fn literal_set(chars: &str, negated: bool) -> Set {
+	Set {
+		negated,
+		items: chars.bytes().map(SetItem::Literal).collect(),
+	}
+}
@@ -0,0 +1,51 @@
+//! Adapters for using patterns as terminals in [nom] parsers.
+//!
+//! ```
+//! extern crate nom;
+//! use lupat::{combinator::pattern, Pattern};
+//! use nom::sequence::separated_pair;
+//! use nom::character::complete::char;
+//!
+//! let mut pair = separated_pair(
+//!     pattern::<(), 1>(Pattern::new("%a+").unwrap()),
+//!     char('='),
+//!     pattern(Pattern::<2>::new("(%d+)").unwrap()),
+//! );
+//! assert_eq!(pair("x=10;").unwrap(), (";", (vec!["x"], vec!["10"])));
+//! ```
+
+use nom::error::{ErrorKind, ParseError};
+use nom::{Err, IResult};
+use {char_range, Pattern};
+
+/// Parser that matches the pattern at the start of the input, consuming the match.
+/// Outputs the captures like Lua's `string.match`, the whole match if there are none.
+/// Fails with [ErrorKind::RegexpMatch] when the input doesn't start with a match.
+pub fn pattern<'a, 't, E: ParseError<&'t str>, const MAXCAPTURES: usize>(
+	mut patt: Pattern<'a, MAXCAPTURES>,
+) -> impl FnMut(&'t str) -> IResult<&'t str, Vec<&'t str>, E> + 'a {
+	move |input: &'t str| {
+		if !patt.matches_bytes_at(input.as_bytes(), 0, true) {
+			return Err(Err::Error(E::from_error_kind(input, ErrorKind::RegexpMatch)));
+		}
+		let captures = patt.match_captures(input);
+		let out = match captures.num_matches() {
+			1 => vec![captures.get(0)],
+			n => (1..n).map(|i| captures.get(i)).collect(),
+		};
+		Ok((&input[char_range(input, patt.range()).end..], out))
+	}
+}
+
+/// Like [pattern], but outputs only the matched text
+pub fn recognize<'a, 't, E: ParseError<&'t str>, const MAXCAPTURES: usize>(
+	mut patt: Pattern<'a, MAXCAPTURES>,
+) -> impl FnMut(&'t str) -> IResult<&'t str, &'t str, E> + 'a {
+	move |input: &'t str| {
+		if !patt.matches_bytes_at(input.as_bytes(), 0, true) {
+			return Err(Err::Error(E::from_error_kind(input, ErrorKind::RegexpMatch)));
+		}
+		let end = char_range(input, patt.range()).end;
+		Ok((&input[end..], &input[..end]))
+	}
+}
@@ -0,0 +1,49 @@
+use error::Error;
+use std::ops;
+use {Pattern, LUA_MAXCAPTURES};
+
+/// An input from [compare] the two patterns treat differently
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disagreement<'c> {
+	pub input: &'c str,
+	/// Where the first pattern matched, the whole match first and then every capture, or `None` if it didn't
+	pub a: Option<Vec<ops::Range<usize>>>,
+	/// Same for the second pattern
+	pub b: Option<Vec<ops::Range<usize>>>,
+}
+
+/// Runs patterns `a` and `b` over every input in `corpus`, and lists the inputs where one matches and the other doesn't,
+/// or they match in different places or capture different things.
+/// For checking a rewritten pattern still does what the old one did.
+///
+/// ```
+/// let corpus = ["x=1", "x = 1", "x=", "="];
+/// let diff = lupat::compare("(%w+)=(%w*)", "(%w+)%s*=%s*(%w*)", corpus.iter().copied()).unwrap();
+/// assert_eq!(diff.len(), 1);
+/// assert_eq!(diff[0].input, "x = 1");
+/// assert_eq!(diff[0].a, None);
+/// assert_eq!(diff[0].b, Some(vec![0..5, 0..1, 4..5]));
+/// ```
+pub fn compare<'c, S, I>(a: &S, b: &S, corpus: I) -> Result<Vec<Disagreement<'c>>, Error>
+where
+	S: AsRef<[u8]> + ?Sized,
+	I: IntoIterator<Item = &'c str>,
+{
+	let mut a: Pattern<LUA_MAXCAPTURES> = Pattern::new(a)?;
+	let mut b: Pattern<LUA_MAXCAPTURES> = Pattern::new(b)?;
+	let mut diff = Vec::new();
+	for input in corpus {
+		let (in_a, in_b) = (run(&mut a, input)?, run(&mut b, input)?);
+		if in_a != in_b {
+			diff.push(Disagreement { input, a: in_a, b: in_b });
+		}
+	}
+	Ok(diff)
+}
+
+fn run(patt: &mut Pattern, input: &str) -> Result<Option<Vec<ops::Range<usize>>>, Error> {
+	if !patt.try_matches(input)? {
+		return Ok(None);
+	}
+	Ok(Some(patt.raw_matches().iter().map(|m| m.start..m.end).collect()))
+}
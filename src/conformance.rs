@@ -0,0 +1,185 @@
+//! Differential testing against PUC-Lua 5.4 (through `mlua`), to find where this engine diverges from the real thing.
+//!
+//! ```no_run
+//! let harness = lupat::conformance::Harness::new().unwrap();
+//! for divergence in harness.compare("(%w+)=(%w+)", "foo=bar", "%2=%1").unwrap() {
+//!     println!("{}", divergence);
+//! }
+//! ```
+
+use mlua::{Function, Lua, Value};
+use pattern::LUA_MAXCAPTURES;
+use std::fmt;
+use Pattern;
+
+/// Results of an operation as raw bytes, or the error message if it failed
+pub type Outcome = Result<Vec<Vec<u8>>, String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+	/// `string.find`, start and end (1-based, inclusive) followed by any captures
+	Find,
+	/// `string.match`
+	Match,
+	/// `string.gmatch`, the first capture of every match, compared with [Pattern::gmatch_bytes]
+	GMatch,
+	/// `string.gsub`, only the resulting string
+	GSub,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+	pub operation: Operation,
+	pub pattern: String,
+	pub text: String,
+	pub ours: Outcome,
+	pub lua: Outcome,
+}
+
+impl fmt::Display for Divergence {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:?} of {:?} on {:?}: lupat gave ", self.operation, self.pattern, self.text)?;
+		write_outcome(f, &self.ours)?;
+		write!(f, ", lua gave ")?;
+		write_outcome(f, &self.lua)
+	}
+}
+
+fn write_outcome(f: &mut fmt::Formatter, outcome: &Outcome) -> fmt::Result {
+	match outcome {
+		Ok(values) => {
+			let values: Vec<String> = values.iter().map(|v| format!("\"{}\"", v.escape_ascii())).collect();
+			write!(f, "[{}]", values.join(", "))
+		}
+		Err(e) => write!(f, "error {:?}", e),
+	}
+}
+
+pub struct Harness {
+	// Functions don't keep the state alive by themselves
+	_lua: Lua,
+	find: Function,
+	match_: Function,
+	gmatch: Function,
+	gsub: Function,
+}
+
+const LUA_OPS: &str = r##"
+local function pack(...) return { n = select("#", ...), ... } end
+local function strings(t)
+	local out = {}
+	for i = 1, t.n do out[i] = tostring(t[i]) end
+	if t.n == 1 and t[1] == nil then return {} end
+	return out
+end
+return
+	function(s, p) return strings(pack(string.find(s, p))) end,
+	function(s, p) return strings(pack(string.match(s, p))) end,
+	function(s, p)
+		local out = {}
+		for m in string.gmatch(s, p) do out[#out + 1] = tostring(m) end
+		return out
+	end,
+	function(s, p, r) return { (string.gsub(s, p, r)) } end
+"##;
+
+impl Harness {
+	pub fn new() -> mlua::Result<Self> {
+		let lua = Lua::new();
+		let (find, match_, gmatch, gsub) = lua.load(LUA_OPS).eval()?;
+		Ok(Harness { _lua: lua, find, match_, gmatch, gsub })
+	}
+
+	/// Runs every [Operation] through both engines, returning the ones that disagree.
+	/// Errors only come from the Lua side failing outside of the operations themselves.
+	pub fn compare(&self, pattern: &str, text: &str, repl: &str) -> mlua::Result<Vec<Divergence>> {
+		let ours = [
+			(Operation::Find, our_find(pattern, text)),
+			(Operation::Match, our_match(pattern, text)),
+			(Operation::GMatch, our_gmatch(pattern, text)),
+			(Operation::GSub, our_gsub(pattern, text, repl)),
+		];
+
+		let mut divergences = Vec::new();
+		for (operation, ours) in ours {
+			let (lua, converted) = match operation {
+				Operation::Find => lua_outcome(self.find.call((text, pattern))),
+				Operation::Match => lua_outcome(self.match_.call((text, pattern))),
+				Operation::GMatch => lua_outcome(self.gmatch.call((text, pattern))),
+				Operation::GSub => lua_outcome(self.gsub.call((text, pattern, repl))),
+			};
+			// A Lua result that couldn't be read is always reported
+			let agree = converted && match (&ours, &lua) {
+				(Ok(a), Ok(b)) => a == b,
+				(Err(a), Err(b)) => a == b,
+				_ => false,
+			};
+			if !agree {
+				divergences.push(Divergence {
+					operation,
+					pattern: pattern.to_owned(),
+					text: text.to_owned(),
+					ours,
+					lua,
+				});
+			}
+		}
+		Ok(divergences)
+	}
+}
+
+/// The outcome, and whether every value could be read as bytes. One that couldn't is an error naming its type
+fn lua_outcome(res: mlua::Result<Vec<Value>>) -> (Outcome, bool) {
+	match res {
+		Ok(values) => {
+			let bytes: Result<Vec<Vec<u8>>, String> = values
+				.iter()
+				.map(|v| match v {
+					Value::String(s) => Ok(s.as_bytes().to_vec()),
+					v => Err(format!("lua returned a {} instead of a string", v.type_name())),
+				})
+				.collect();
+			let converted = bytes.is_ok();
+			(bytes, converted)
+		}
+		// Only the message itself, without the "runtime error: input:1:" in front or the traceback after
+		Err(e) => {
+			let e = e.to_string();
+			let first_line = e.lines().next().unwrap_or_default();
+			(Err(first_line.rsplit(": ").next().unwrap_or_default().to_owned()), true)
+		}
+	}
+}
+
+fn compile(pattern: &str) -> Result<Pattern<'_>, String> {
+	Pattern::<LUA_MAXCAPTURES>::new(pattern).map_err(|e| e.to_string())
+}
+
+fn our_find(pattern: &str, text: &str) -> Outcome {
+	let mut patt = compile(pattern)?;
+	let captures = patt.captures(text);
+	if captures.is_empty() {
+		return Ok(Vec::new());
+	}
+	let range = patt.range();
+	let mut out = vec![(range.start + 1).to_string().into_bytes(), range.end.to_string().into_bytes()];
+	out.extend(captures[1..].iter().map(|s| s.as_bytes().to_vec()));
+	Ok(out)
+}
+
+fn our_match(pattern: &str, text: &str) -> Outcome {
+	let mut patt = compile(pattern)?;
+	let captures = patt.captures(text);
+	let captures = if captures.len() > 1 { &captures[1..] } else { &captures[..] };
+	Ok(captures.iter().map(|s| s.as_bytes().to_vec()).collect())
+}
+
+fn our_gmatch(pattern: &str, text: &str) -> Outcome {
+	let mut patt = compile(pattern)?;
+	Ok(patt.gmatch_bytes(text.as_bytes()).map(|s| s.to_vec()).collect())
+}
+
+fn our_gsub(pattern: &str, text: &str, repl: &str) -> Outcome {
+	let mut patt = compile(pattern)?;
+	patt.gsub(text, repl).map(|s| vec![s.into_bytes()]).map_err(|e| e.to_string())
+}
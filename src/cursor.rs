@@ -0,0 +1,117 @@
+use error::Error;
+use super::{char_range, Pattern};
+use pattern::{Haystack, LuaVersion};
+
+/// Steps through every match in a text the way Lua 5.4's `gsub` and `gmatch` do.
+/// After an empty match the search moves one character ahead, and an empty match right where the previous match ended is skipped.
+/// Versions before 5.4 (see [LuaVersion]) don't skip it, and move ahead right after an empty match instead.
+pub(crate) struct MatchCursor {
+	pos: usize,
+	last: Option<usize>,
+	done: bool,
+}
+
+impl MatchCursor {
+	pub(crate) fn new() -> Self {
+		MatchCursor { pos: 0, last: None, done: false }
+	}
+
+	/// Resumes right after a match that ended at `end`
+	#[cfg(feature = "gsub")]
+	pub(crate) fn after(end: usize) -> Self {
+		MatchCursor { pos: end, last: Some(end), done: false }
+	}
+
+	/// Finds the next match, leaving its captures in `patt` with offsets into `bytes`.
+	/// A match that fails with an error ends the search like not matching would.
+	pub(crate) fn advance<const MAXCAPTURES: usize>(&mut self, patt: &mut Pattern<MAXCAPTURES>, bytes: &[u8]) -> bool {
+		self.try_advance(patt, bytes).unwrap_or(false)
+	}
+
+	/// Like [MatchCursor::advance], but passing on errors. The search ends after an error.
+	/// Like Lua, an empty match steps over a single byte, even inside a utf-8 character.
+	pub(crate) fn try_advance<const MAXCAPTURES: usize>(&mut self, patt: &mut Pattern<MAXCAPTURES>, bytes: &[u8]) -> Result<bool, Error> {
+		self.try_advance_units(patt, bytes, |_, _| 1)
+	}
+
+	/// Like [MatchCursor::advance] over a str. An empty match steps over a whole character, and a match ending inside one ends after it instead,
+	/// so the next one doesn't start in the middle of the same character.
+	pub(crate) fn advance_str<const MAXCAPTURES: usize>(&mut self, patt: &mut Pattern<MAXCAPTURES>, text: &str) -> bool {
+		self.try_advance_str(patt, text).unwrap_or(false)
+	}
+
+	/// Like [MatchCursor::advance_str], but passing on errors like [MatchCursor::try_advance]
+	pub(crate) fn try_advance_str<const MAXCAPTURES: usize>(&mut self, patt: &mut Pattern<MAXCAPTURES>, text: &str) -> Result<bool, Error> {
+		let found = self.try_advance_units(patt, text.as_bytes(), char_len)?;
+		if found && !text.is_char_boundary(patt.range().end) {
+			let end = char_range(text, patt.range()).end;
+			self.pos = self.pos.max(end);
+			self.last = Some(end);
+		}
+		Ok(found)
+	}
+
+	/// Like [MatchCursor::advance] over any haystack of bytes, like a rope
+	#[cfg(feature = "ropey")]
+	pub(crate) fn advance_haystack<H: Haystack<Unit = u8> + ?Sized, const MAXCAPTURES: usize>(&mut self, patt: &mut Pattern<MAXCAPTURES>, bytes: &H) -> bool {
+		self.try_advance_units(patt, bytes, char_len).unwrap_or(false)
+	}
+
+	/// Like [MatchCursor::advance] over UTF-16, stepping over surrogate pairs whole
+	#[cfg(all(feature = "utf16", feature = "iter"))]
+	pub(crate) fn advance_utf16<const MAXCAPTURES: usize>(&mut self, patt: &mut Pattern<MAXCAPTURES>, units: &[u16]) -> bool {
+		let char_len = |units: &[u16], pos: usize| match units.get(pos) {
+			Some(0xD800..=0xDBFF) => 2,
+			_ => 1,
+		};
+		self.try_advance_units(patt, units, char_len).unwrap_or(false)
+	}
+
+	/// The search itself, with `char_len` giving how many units the character at a position takes
+	fn try_advance_units<H, const MAXCAPTURES: usize>(&mut self, patt: &mut Pattern<MAXCAPTURES>, bytes: &H, char_len: fn(&H, usize) -> usize) -> Result<bool, Error>
+	where
+		H: Haystack + ?Sized,
+	{
+		// `^` only matches once, at the start, unless it matches at every line
+		let anchored = patt.patt.first() == Some(&b'^') && !patt.flags.multiline;
+		let skip_after_last = patt.flags.version >= LuaVersion::Lua54;
+		while !self.done {
+			match patt.run(bytes, self.pos, false) {
+				Ok(true) => (),
+				Ok(false) => break,
+				Err(e) => {
+					self.done = true;
+					return Err(e);
+				}
+			}
+			let range = patt.range();
+			self.done = anchored;
+			if range.is_empty() && self.last == Some(range.end) && skip_after_last {
+				if range.end >= bytes.len() {
+					self.done = true;
+				}
+				self.pos = range.end + char_len(bytes, range.end);
+				continue;
+			}
+			self.last = Some(range.end);
+			self.pos = range.end;
+			if range.is_empty() && !skip_after_last {
+				self.done |= range.end >= bytes.len();
+				self.pos += char_len(bytes, range.end);
+			}
+			return Ok(true);
+		}
+		self.done = true;
+		Ok(false)
+	}
+}
+
+/// Length of the utf-8 character at `pos`, or 1 if it's not the start of one
+fn char_len<H: Haystack<Unit = u8> + ?Sized>(bytes: &H, pos: usize) -> usize {
+	match bytes.unit_at(pos) {
+		Some(0xC0..=0xDF) => 2,
+		Some(0xE0..=0xEF) => 3,
+		Some(0xF0..=0xF7) => 4,
+		_ => 1,
+	}
+}
@@ -0,0 +1,104 @@
+//! Matching over text that isn't UTF-8, by decoding it first.
+//! Ranges found in the decoded text are mapped back to byte offsets in the original encoding.
+//!
+//! ```
+//! use lupat::encoding::Encoding;
+//! use lupat::Pattern;
+//!
+//! // "día 12" in UTF-16LE
+//! let bytes: Vec<u8> = "día 12".encode_utf16().flat_map(u16::to_le_bytes).collect();
+//! let mut patt: Pattern = Pattern::new("%d+").unwrap();
+//! assert_eq!(patt.find_encoded(&bytes, Encoding::Utf16Le), Some(8..12));
+//! ```
+
+use std::char::REPLACEMENT_CHARACTER;
+use std::ops;
+use {char_range, Pattern};
+
+/// Encodings that can be decoded for matching
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+	/// ISO-8859-1, every byte is the code point of the same value
+	Latin1,
+	Utf16Le,
+	Utf16Be,
+}
+
+impl Encoding {
+	/// Decodes `bytes`, replacing unpaired surrogates and a trailing odd byte with U+FFFD
+	pub fn decode(self, bytes: &[u8]) -> Decoded {
+		let mut decoded = Decoded { text: String::with_capacity(bytes.len()), offsets: Vec::with_capacity(bytes.len() + 1) };
+		match self {
+			Encoding::Latin1 => {
+				for (i, &b) in bytes.iter().enumerate() {
+					decoded.push(b as char, i);
+				}
+			}
+			Encoding::Utf16Le | Encoding::Utf16Be => {
+				let unit = |pair: &[u8]| match self {
+					Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+					_ => u16::from_be_bytes([pair[0], pair[1]]),
+				};
+				let units = bytes.chunks_exact(2).map(unit);
+				let mut offset = 0;
+				for c in char::decode_utf16(units) {
+					let c = c.unwrap_or(REPLACEMENT_CHARACTER);
+					// Unpaired surrogates are one unit, like every char outside the astral planes
+					let len = if c == REPLACEMENT_CHARACTER { 2 } else { c.len_utf16() * 2 };
+					decoded.push(c, offset);
+					offset += len;
+				}
+				if bytes.len() % 2 == 1 {
+					decoded.push(REPLACEMENT_CHARACTER, bytes.len() - 1);
+				}
+			}
+		}
+		decoded.offsets.push(bytes.len());
+		decoded
+	}
+}
+
+/// Text decoded by [Encoding::decode], remembering where each char came from
+#[derive(Debug, Clone)]
+pub struct Decoded {
+	text: String,
+	/// Original offset of the char each byte of `text` belongs to, and the original length at the end
+	offsets: Vec<usize>,
+}
+
+impl Decoded {
+	fn push(&mut self, c: char, offset: usize) {
+		self.text.push(c);
+		self.offsets.resize(self.text.len(), offset);
+	}
+
+	pub fn as_str(&self) -> &str {
+		&self.text
+	}
+
+	/// Maps a range of the decoded text to the original bytes, widened to whole chars
+	pub fn original_range(&self, range: ops::Range<usize>) -> ops::Range<usize> {
+		let range = char_range(&self.text, range);
+		self.offsets[range.start]..self.offsets[range.end]
+	}
+}
+
+impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
+	/// The first match in `bytes` decoded from `encoding`, as offsets into `bytes`
+	pub fn find_encoded(&mut self, bytes: &[u8], encoding: Encoding) -> Option<ops::Range<usize>> {
+		let decoded = encoding.decode(bytes);
+		if !self.matches(decoded.as_str()) {
+			return None;
+		}
+		Some(decoded.original_range(self.range()))
+	}
+
+	/// Like [Pattern::find_encoded], with the ranges of the whole match and then every capture
+	pub fn captures_encoded(&mut self, bytes: &[u8], encoding: Encoding) -> Option<Vec<ops::Range<usize>>> {
+		let decoded = encoding.decode(bytes);
+		if !self.matches(decoded.as_str()) {
+			return None;
+		}
+		Some((0..self.n_match).map(|i| decoded.original_range(self.capture(i))).collect())
+	}
+}
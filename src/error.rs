@@ -1,6 +1,9 @@
-use std::fmt;
+use std::{fmt, io};
 
+/// New variants can come with any release, so matches need a `_` arm
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Error), uniffi(flat_error))]
+#[non_exhaustive]
 pub enum Error {
 	/// Like doing "(hello) %4".
 	/// Tried to get the fourth group but pattern only has one.
@@ -12,7 +15,7 @@ pub enum Error {
 	/// Unbalanced parenthesis. "(foo)(bar"
 	UnfinishedCapture,
 
-	/// Using ) without a (
+	/// Using ) without a (. Lua reports this as an invalid pattern capture too
 	NoOpenCapture,
 
 	/// Stack overflow limit
@@ -21,6 +24,10 @@ pub enum Error {
 	/// Ends with %
 	EndsWithPercent,
 
+	/// Ends with the escape character, when it was changed from % with the `extensions` feature.
+	/// Always there so enabling the feature doesn't change the enum
+	EndsWithEscape(u8),
+
 	/// Missing ]
 	MissingEndBracket,
 
@@ -31,10 +38,25 @@ pub enum Error {
 	MissingLBracketF,
 
 	/// Unfinished or positional capture where not expected
-	CapLen
+	CapLen,
+
+	/// A % in a replacement string not followed by a digit or another %, like "%x"
+	InvalidReplacement,
+
+	/// The result of a capped gsub would be longer than the limit, like [Pattern::gsub_capped](crate::Pattern::gsub_capped)
+	OutputTooLong(usize),
+
+	/// The haystack is longer than [Pattern::max_haystack_len](crate::Pattern::max_haystack_len), which is given
+	HaystackTooLong(usize),
+
+	/// A `%{name}` that wasn't defined, or a name that can't be defined, with the `extensions` feature
+	UnknownFragment(String),
+
+	/// No pattern is registered under this name in a [Registry](crate::Registry)
+	UnknownPattern(String),
 }
 
-/// Display Error with proper error messages you'd get from lua.
+/// Display Error with the exact error messages you'd get from Lua 5.4, minus the position Lua puts in front (see [Error::with_location]).
 impl fmt::Display for Error {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match self {
@@ -42,13 +64,91 @@ impl fmt::Display for Error {
 			Error::InvalidCapture( None ) => write!(f, "invalid pattern capture"),
 			Error::TooManyCaptures => write!(f, "too many captures"),
 			Error::UnfinishedCapture => write!(f, "unfinished capture"),
-			Error::NoOpenCapture => write!(f, "no open capture"),
+			Error::NoOpenCapture => write!(f, "invalid pattern capture"),
 			Error::TooComplex => write!(f, "pattern too complex"),
 			Error::EndsWithPercent => write!(f, "malformed pattern (ends with '%')"),
+			Error::EndsWithEscape(esc) => write!(f, "malformed pattern (ends with '{}')", *esc as char),
 			Error::MissingEndBracket => write!(f, "malformed pattern (missing ']')"),
 			Error::MissingBalanceArgs => write!(f, "malformed pattern (missing arguments to '%b')"),
 			Error::MissingLBracketF => write!(f, "missing '[' after '%f' in pattern"),
-			Error::CapLen => write!(f, "capture was unfinished or positional (this shouldn't happen..?)")
+			Error::CapLen => write!(f, "capture was unfinished or positional (this shouldn't happen..?)"),
+			Error::InvalidReplacement => write!(f, "invalid use of '%' in replacement string"),
+			Error::OutputTooLong(_) => write!(f, "resulting string too large"),
+			Error::HaystackTooLong(max) => write!(f, "subject longer than {} units", max),
+			Error::UnknownFragment(name) => write!(f, "unknown fragment '%{{{}}}'", name),
+			Error::UnknownPattern(name) => write!(f, "no pattern registered as '{}'", name),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+/// As [io::ErrorKind::InvalidData], for reading code that `?`s pattern failures into I/O ones
+impl From<Error> for io::Error {
+	fn from(e: Error) -> io::Error {
+		io::Error::new(io::ErrorKind::InvalidData, e)
+	}
+}
+
+impl Error {
+	/// Whether this comes from malformed pattern or replacement source, like a client sending a bad pattern.
+	/// Errors can be neither this nor [Error::is_runtime]
+	pub fn is_syntax(&self) -> bool {
+		matches!(
+			self,
+			Error::InvalidCapture(_)
+				| Error::TooManyCaptures
+				| Error::UnfinishedCapture
+				| Error::NoOpenCapture
+				| Error::EndsWithPercent
+				| Error::EndsWithEscape(_)
+				| Error::MissingEndBracket
+				| Error::MissingBalanceArgs
+				| Error::MissingLBracketF
+				| Error::InvalidReplacement
+				| Error::UnknownFragment(_)
+		)
+	}
+
+	/// Whether a valid pattern failed on a particular input, by being too complex for it, it being too long or making too much output
+	pub fn is_runtime(&self) -> bool {
+		matches!(self, Error::TooComplex | Error::OutputTooLong(_) | Error::HaystackTooLong(_) | Error::CapLen)
+	}
+
+	/// The message as a Lua script would see it when raised from `line` of `chunk`, like `input:3: unfinished capture`
+	pub fn with_location(&self, chunk: &str, line: u32) -> String {
+		format!("{}:{}: {}", chunk, line, self)
+	}
+
+	/// The error for a pattern ending with the escape character `esc`
+	pub(crate) fn ends_with_escape(esc: u8) -> Error {
+		#[cfg(feature = "extensions")]
+		if esc != b'%' {
+			return Error::EndsWithEscape(esc);
+		}
+		let _ = esc;
+		Error::EndsWithPercent
+	}
+
+	/// A hint on how to fix `pattern`, which failed to compile with this error.
+	/// Mostly aimed at people used to regex syntax.
+	pub fn suggestion<S: AsRef<[u8]> + ?Sized>(&self, pattern: &S) -> Option<&'static str> {
+		let pattern = pattern.as_ref();
+		let contains = |needle: &[u8]| pattern.windows(needle.len()).any(|w| w == needle);
+
+		if [&b"(?:"[..], b"(?=", b"(?!", b"(?<"].iter().any(|n| contains(n)) {
+			return Some("Lua has no non-capturing groups or lookarounds, use a plain (...) or remove the group");
+		}
+		if pattern.windows(2).any(|w| w[0] == b'\\' && w[1].is_ascii_alphanumeric()) {
+			return Some("Lua escapes with % instead of \\, like %d instead of \\d");
+		}
+		match self {
+			Error::EndsWithPercent => Some("use %% for a literal %"),
+			Error::MissingEndBracket if contains(b"\\]") => Some("escape ] inside a set with %]"),
+			Error::NoOpenCapture | Error::UnfinishedCapture => Some("escape literal parentheses with %( and %)"),
+			Error::MissingBalanceArgs => Some("%b needs an opening and closing character, like %b()"),
+			Error::MissingLBracketF => Some("%f needs a set, like %f[%w]"),
+			_ => None,
 		}
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,52 @@
+use std::ops;
+use pattern::str_match_traced;
+use {tokens, Pattern, TokenKind};
+
+/// Where a pattern gave up on a text, see [Pattern::explain_failure]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+	/// Byte offset in the text where the attempt that went the furthest stopped
+	pub position: usize,
+	/// Span of the pattern element that failed there, including its quantifier.
+	/// Empty at the end of the pattern source.
+	pub element: ops::Range<usize>,
+}
+
+impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
+	/// Explains why the pattern doesn't match `text`, `None` if it does.
+	/// Of every starting position tried, this reports where the one that got the furthest from its start stopped and the element it got stuck on.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("^(%a+)=(%d+)$").unwrap();
+	/// let failure = patt.explain_failure("width=12px").unwrap();
+	/// assert_eq!(failure.position, 8);
+	/// assert_eq!(&"^(%a+)=(%d+)$"[failure.element], "$");
+	/// ```
+	pub fn explain_failure(&mut self, text: &str) -> Option<Failure> {
+		let mut furthest = (0, 0);
+		let res = str_match_traced::<_, MAXCAPTURES>(text.as_bytes(), self.patt, &mut self.matches, 0, false, self.flags, &self.sets, Some(&mut furthest));
+		self.n_match = *res.as_ref().unwrap_or(&0);
+		if self.n_match > 0 {
+			return None;
+		}
+
+		let (position, p) = furthest;
+		let mut toks = tokens(self.patt).skip_while(|t| t.span.start < p);
+		let element = match toks.next() {
+			Some(tok) => {
+				let mut end = tok.span.end;
+				// A quantifier, or the set of a frontier, is part of the element
+				if let Some(next) = toks.next() {
+					if next.kind == TokenKind::Quantifier || (tok.kind == TokenKind::Frontier && next.kind == TokenKind::Set) {
+						end = next.span.end;
+					}
+				}
+				tok.span.start..end
+			}
+			None => self.patt.len()..self.patt.len(),
+		};
+		Some(Failure { position, element })
+	}
+}
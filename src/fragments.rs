@@ -0,0 +1,82 @@
+use bindings::CompiledPattern;
+use std::collections::HashMap;
+use {Error, Pattern, LUA_MAXCAPTURES};
+
+/// Named pattern fragments, referenced from patterns as `%{name}` and expanded before compiling.
+/// Expansion is textual, so a quantifier after `%{name}` only applies to the last item of the fragment.
+///
+/// ```
+/// use lupat::Fragments;
+///
+/// let mut fragments = Fragments::new();
+/// fragments.define("ident", "[%a_][%w_]*").unwrap();
+/// fragments.define("assign", "(%{ident})%s*=").unwrap();
+///
+/// assert_eq!(fragments.expand("^local %{assign}").unwrap(), "^local ([%a_][%w_]*)%s*=");
+/// let patt = fragments.compile("%{assign}%s*(%d+)").unwrap();
+/// assert_eq!(patt.match_captures("x = 5"), Some(vec!["x".to_owned(), "5".to_owned()]));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Fragments {
+	defs: HashMap<String, String>,
+}
+
+impl Fragments {
+	pub fn new() -> Self {
+		Fragments::default()
+	}
+
+	/// Defines `name`, which has to be letters, digits and underscores.
+	/// `fragment` can use fragments defined before it, and has to be a valid pattern on its own.
+	pub fn define(&mut self, name: &str, fragment: &str) -> Result<(), Error> {
+		if !is_name(name.as_bytes()) {
+			return Err( Error::UnknownFragment(name.to_owned()) );
+		}
+		let expanded = self.expand(fragment)?;
+		Pattern::<LUA_MAXCAPTURES>::new(&expanded)?;
+		self.defs.insert(name.to_owned(), expanded);
+		Ok(())
+	}
+
+	/// Replaces every `%{name}` in `source` with its fragment.
+	/// A `%{` not followed by a name and `}` is left alone, like `[%{}]`.
+	pub fn expand(&self, source: &str) -> Result<String, Error> {
+		let bytes = source.as_bytes();
+		let mut res = String::with_capacity(source.len());
+		let mut last = 0;
+		let mut i = 0;
+		while i < bytes.len() {
+			if bytes[i] != b'%' {
+				i += 1;
+				continue;
+			}
+			let name_len = match bytes.get(i + 1) {
+				Some(b'{') => bytes[i + 2..].iter().position(|&c| c == b'}').filter(|&len| is_name(&bytes[i + 2..i + 2 + len])),
+				_ => None,
+			};
+			match name_len {
+				Some(len) => {
+					let name = &source[i + 2..i + 2 + len];
+					let fragment = self.defs.get(name).ok_or_else(|| Error::UnknownFragment(name.to_owned()))?;
+					res.push_str(&source[last..i]);
+					res.push_str(fragment);
+					i += len + 3;
+					last = i;
+				}
+				// Skips the escaped character, so `%%{x}` stays a literal %
+				None => i += 2,
+			}
+		}
+		res.push_str(&source[last.min(source.len())..]);
+		Ok(res)
+	}
+
+	/// Expands `source` and compiles it into a pattern that owns its source
+	pub fn compile(&self, source: &str) -> Result<CompiledPattern, Error> {
+		CompiledPattern::new(&self.expand(source)?)
+	}
+}
+
+fn is_name(name: &[u8]) -> bool {
+	!name.is_empty() && name.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'_')
+}
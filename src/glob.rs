@@ -0,0 +1,99 @@
+/// Converts a shell glob into an anchored Lua pattern.
+///
+/// Supports `*`, `?`, `[...]` sets (negated with `!` or `^`) and `\` to escape the next character.
+/// Everything else is matched literally. Note `*` also matches `/`, like `fnmatch` without `FNM_PATHNAME`.
+///
+/// ```
+/// assert_eq!(lupat::from_glob("*.tar.gz"), "^.*%.tar%.gz$");
+/// ```
+pub fn from_glob(glob: &str) -> String {
+	translate(glob, false)
+}
+
+/// Like [from_glob], but for paths like `.gitignore` rules: `*` and `?` don't match `/`, while `**` matches across directories.
+///
+/// ```
+/// assert_eq!(lupat::from_path_glob("src/*.rs"), "^src%/[^/]*%.rs$");
+/// assert_eq!(lupat::from_path_glob("docs/**"), "^docs%/.*$");
+/// ```
+pub fn from_path_glob(glob: &str) -> String {
+	translate(glob, true)
+}
+
+/// With `pathname`, wildcards stop at `/` like `fnmatch` with `FNM_PATHNAME`
+fn translate(glob: &str, pathname: bool) -> String {
+	let mut res = String::with_capacity(glob.len() + 2);
+	res.push('^');
+	let mut chars = glob.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'*' if pathname && chars.peek() == Some(&'*') => {
+				chars.next();
+				res.push_str(".*");
+			}
+			'*' if pathname => res.push_str("[^/]*"),
+			'?' if pathname => res.push_str("[^/]"),
+			'*' => res.push_str(".*"),
+			'?' => res.push('.'),
+			'\\' => match chars.next() {
+				Some(c) => push_literal(&mut res, c),
+				None => push_literal(&mut res, '\\'),
+			},
+			'[' => {
+				// Only a set if it is closed, otherwise a literal '['
+				let rest: String = chars.clone().collect();
+				match set_len(&rest) {
+					Some(len) => {
+						push_set(&mut res, &rest[..len]);
+						for _ in rest[..=len].chars() {
+							chars.next();
+						}
+					}
+					None => push_literal(&mut res, '['),
+				}
+			}
+			_ => push_literal(&mut res, c),
+		}
+	}
+	res.push('$');
+	res
+}
+
+fn push_literal(res: &mut String, c: char) {
+	if c.is_ascii_punctuation() {
+		res.push('%');
+	}
+	res.push(c);
+}
+
+/// Byte length of the set contents before the closing `]`.
+/// A `]` right after the opening (or negation) is part of the set, like in shells.
+fn set_len(rest: &str) -> Option<usize> {
+	let mut skip = 0;
+	if rest.starts_with(['!', '^']) {
+		skip += 1;
+	}
+	if rest[skip..].starts_with(']') {
+		skip += 1;
+	}
+	rest[skip..].find(']').map(|i| i + skip)
+}
+
+fn push_set(res: &mut String, set: &str) {
+	res.push('[');
+	let mut set = set;
+	if let Some(negated) = set.strip_prefix(['!', '^']) {
+		res.push('^');
+		set = negated;
+	}
+	let chars: Vec<char> = set.chars().collect();
+	for (i, &c) in chars.iter().enumerate() {
+		// '-' only forms a range between two characters
+		let is_range = c == '-' && i > 0 && i + 1 < chars.len();
+		if c.is_ascii_punctuation() && !is_range {
+			res.push('%');
+		}
+		res.push(c);
+	}
+	res.push(']');
+}
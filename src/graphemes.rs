@@ -0,0 +1,91 @@
+//! Extended grapheme cluster boundaries for `.` with [PatternOptions::graphemes](crate::PatternOptions::graphemes).
+//!
+//! This follows the rules of UAX #29 that come up in practice: CR LF, Hangul syllables, combining marks and other extenders,
+//! emoji ZWJ sequences and regional indicator flags. Prepended concatenation marks don't start clusters.
+
+use grapheme_tables::EXTEND;
+use pattern::{Haystack, Pos, Unit};
+
+const CR: char = '\r';
+const LF: char = '\n';
+const ZWJ: char = '\u{200d}';
+
+fn is_extend(c: char) -> bool {
+	let c = c as u32;
+	EXTEND.binary_search_by(|&(start, end)| if end < c { std::cmp::Ordering::Less } else if start > c { std::cmp::Ordering::Greater } else { std::cmp::Ordering::Equal }).is_ok()
+}
+
+fn is_regional_indicator(c: char) -> bool {
+	('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+/// Emoji and other pictographs, what ZWJ sequences join
+fn is_pictographic(c: char) -> bool {
+	matches!(c as u32,
+		0xA9 | 0xAE | 0x203C | 0x2049 | 0x2122 | 0x2139 | 0x2194..=0x21AA | 0x231A..=0x23FF | 0x24C2 | 0x25AA..=0x25FE
+		| 0x2600..=0x27BF | 0x2934 | 0x2935 | 0x2B05..=0x2B55 | 0x3030 | 0x303D | 0x3297 | 0x3299
+		| 0x1F000..=0x1F1E5 | 0x1F200..=0x1F3FA | 0x1F400..=0x1FAFF)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Hangul {
+	L,
+	V,
+	T,
+	Lv,
+	Lvt,
+}
+
+fn hangul(c: char) -> Option<Hangul> {
+	match c as u32 {
+		0x1100..=0x115F | 0xA960..=0xA97C => Some(Hangul::L),
+		0x1160..=0x11A7 | 0xD7B0..=0xD7C6 => Some(Hangul::V),
+		0x11A8..=0x11FF | 0xD7CB..=0xD7FB => Some(Hangul::T),
+		s @ 0xAC00..=0xD7A3 if (s - 0xAC00) % 28 == 0 => Some(Hangul::Lv),
+		0xAC00..=0xD7A3 => Some(Hangul::Lvt),
+		_ => None,
+	}
+}
+
+/// Whether a cluster ending in `prev` goes on with `next`
+fn joins(prev: char, next: char, ris: usize, zwj_after_pictograph: bool) -> bool {
+	if prev == CR {
+		return next == LF;
+	}
+	if prev.is_control() || next.is_control() {
+		return false;
+	}
+	if let (Some(a), Some(b)) = (hangul(prev), hangul(next)) {
+		use self::Hangul::*;
+		return matches!((a, b), (L, L | V | Lv | Lvt) | (V | Lv, V | T) | (T | Lvt, T));
+	}
+	if is_extend(next) || next == ZWJ {
+		return true;
+	}
+	if prev == ZWJ && zwj_after_pictograph && is_pictographic(next) {
+		return true;
+	}
+	// Flags are pairs of regional indicators
+	is_regional_indicator(prev) && is_regional_indicator(next) && ris % 2 == 1
+}
+
+/// Length in units of the cluster starting at `s`, at least 1 unless at the end
+pub fn cluster_len<H: Haystack + ?Sized>(src: &H, s: Pos) -> Pos {
+	let (mut prev, mut end) = match H::Unit::decode(src, s) {
+		Some((c, len)) => (c, s + len),
+		None => return 0,
+	};
+	let mut ris = is_regional_indicator(prev) as usize;
+	let mut pictograph = is_pictographic(prev);
+	while let Some((next, len)) = H::Unit::decode(src, end) {
+		if !joins(prev, next, ris, pictograph) {
+			break;
+		}
+		ris = if is_regional_indicator(next) { ris + 1 } else { 0 };
+		// Extenders keep a pictograph going until the ZWJ
+		pictograph = is_pictographic(next) || (pictograph && (is_extend(next) || next == ZWJ));
+		prev = next;
+		end += len;
+	}
+	end - s
+}
@@ -0,0 +1,56 @@
+use std::ops;
+use {char_range, MatchCursor, Pattern};
+
+/// Runs every pattern over `text`, returning sorted, non-overlapping spans tagged by the pattern that matched.
+/// Earlier rules take precedence: where matches overlap, later ones are clipped to what's left uncovered.
+/// Adjacent spans with the same tag are merged, and empty matches are ignored.
+///
+/// ```
+/// use lupat::{highlight, Pattern};
+///
+/// let mut rules = [
+///     (Pattern::<1>::new("%-%-[^\n]*").unwrap(), "comment"),
+///     (Pattern::new("%d+").unwrap(), "number"),
+/// ];
+/// let spans = highlight("x = 1 -- 2", &mut rules);
+/// assert_eq!(spans, [(4..5, "number"), (6..10, "comment")]);
+/// ```
+pub fn highlight<T: Clone + PartialEq, const MAXCAPTURES: usize>(text: &str, rules: &mut [(Pattern<MAXCAPTURES>, T)]) -> Vec<(ops::Range<usize>, T)> {
+	// Kept sorted by start
+	let mut spans: Vec<(ops::Range<usize>, T)> = Vec::new();
+	for (patt, tag) in rules.iter_mut() {
+		let mut cursor = MatchCursor::new();
+		while cursor.advance_str(patt, text) {
+			let mut range = char_range(text, patt.range());
+			// Fill in the gaps between already taken spans
+			let first = spans.partition_point(|(taken, _)| taken.end <= range.start);
+			let mut i = first;
+			while !range.is_empty() {
+				let next_taken = spans.get(i).map(|(taken, _)| taken.clone());
+				match next_taken {
+					Some(taken) if taken.start < range.end => {
+						if range.start < taken.start {
+							spans.insert(i, (range.start..taken.start, tag.clone()));
+							i += 1;
+						}
+						range.start = range.start.max(taken.end);
+						i += 1;
+					}
+					_ => {
+						spans.insert(i, (range.clone(), tag.clone()));
+						break;
+					}
+				}
+			}
+		}
+	}
+
+	let mut merged: Vec<(ops::Range<usize>, T)> = Vec::with_capacity(spans.len());
+	for (range, tag) in spans {
+		match merged.last_mut() {
+			Some((last, last_tag)) if last.end == range.start && *last_tag == tag => last.end = range.end,
+			_ => merged.push((range, tag)),
+		}
+	}
+	merged
+}
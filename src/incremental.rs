@@ -0,0 +1,48 @@
+use std::ops;
+use {Edit, MatchCursor, Pattern};
+
+impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
+	/// Updates the ranges of every match (as from [Pattern::find_all]) after `edit` was applied, giving `text`.
+	/// Matches far enough before the edit to be unaffected by it are kept, see [Pattern::max_match_len],
+	/// and scanning stops once it lines up with a previous match after the edit. The rest are moved over.
+	/// Patterns with no longest match, like `%a+`, rescan from the start but still stop after the edit.
+	///
+	/// ```
+	/// use lupat::{Edit, Pattern};
+	///
+	/// let mut patt = Pattern::<1>::new("%d%d").unwrap();
+	/// let previous = [0..2, 6..8, 9..11];
+	/// let edit = Edit { range: 3..4, replacement: "12".to_owned() };
+	/// assert_eq!(patt.rematch("10 12b 20 30", &previous, &edit), [0..2, 3..5, 7..9, 10..12]);
+	/// ```
+	pub fn rematch(&mut self, text: &str, previous: &[ops::Range<usize>], edit: &Edit) -> Vec<ops::Range<usize>> {
+		let new_end = edit.range.start + edit.replacement.len();
+
+		// A match attempt at `p` reads from `p - 1` (for %f) up to one past its longest match (for $)
+		let kept = match self.max_match_len() {
+			Some(max) if self.patt.first() != Some(&b'^') => previous.partition_point(|m| m.start + max < edit.range.start),
+			_ => 0,
+		};
+		let mut out = previous[..kept].to_vec();
+		let mut cursor = match out.last() {
+			Some(m) => MatchCursor::after(m.end),
+			None => MatchCursor::new(),
+		};
+
+		while cursor.advance_str(self, text) {
+			let range = self.range();
+			if range.start > new_end {
+				// Everything from here on reads only text the edit didn't touch
+				let old_start = range.start - new_end + edit.range.end;
+				if let Ok(i) = previous.binary_search_by_key(&old_start, |m| m.start) {
+					if previous[i].len() == range.len() {
+						out.extend(previous[i..].iter().map(|m| m.start - edit.range.end + new_end..m.end - edit.range.end + new_end));
+						return out;
+					}
+				}
+			}
+			out.push(range);
+		}
+		out
+	}
+}
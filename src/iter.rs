@@ -0,0 +1,401 @@
+#[cfg(not(feature = "safe"))]
+use pattern::LuaMatch;
+use std::ffi::OsStr;
+#[cfg(not(feature = "safe"))]
+use std::ops;
+use {char_range, slice, Error, Match, MatchCursor, MatchRanges, Pattern, LUA_MAXCAPTURES};
+
+impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
+	pub fn gmatch<'b, 'c>(&'c mut self, text: &'b str) -> GMatch<'a, 'b, 'c, MAXCAPTURES> {
+		GMatch { m: self, text, cursor: MatchCursor::new(), last: 0 }
+	}
+
+	/// Like [Pattern::gmatch], but with copies of the matches, so they can outlive `text` and the pattern.
+	/// Every match is found up front.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let words = {
+	///     let text = String::from("one two");
+	///     let mut patt: Pattern = Pattern::new("%a+").unwrap();
+	///     patt.gmatch_owned(&text)
+	/// };
+	/// assert_eq!(words.collect::<Vec<_>>(), ["one", "two"]);
+	/// ```
+	pub fn gmatch_owned(&mut self, text: &str) -> impl Iterator<Item = String> + 'static {
+		self.gmatch(text).map(str::to_owned).collect::<Vec<_>>().into_iter()
+	}
+
+	/// Like [Pattern::gmatch], with each whole match and where it is in `text`.
+	/// Empty matches are stepped over like Lua does, so this always ends.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("%d+").unwrap();
+	/// let found: Vec<_> = patt.gmatch_with_offsets("a1 b22").map(|m| (m.as_str(), m.range())).collect();
+	/// assert_eq!(found, [("1", 1..2), ("22", 4..6)]);
+	/// ```
+	pub fn gmatch_with_offsets<'b, 'c>(&'c mut self, text: &'b str) -> GMatchOffsets<'a, 'b, 'c, MAXCAPTURES> {
+		GMatchOffsets { m: self, text, cursor: MatchCursor::new() }
+	}
+
+	/// Like [Pattern::gmatch_with_offsets], but a match failing with an error, like [Error::TooComplex], is yielded instead of ending the iteration quietly.
+	/// Nothing comes after an error.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("%d+").unwrap();
+	/// let found: Result<Vec<_>, _> = patt.try_gmatch("a1 b22").map(|m| m.map(|m| m.as_str())).collect();
+	/// assert_eq!(found.unwrap(), ["1", "22"]);
+	/// ```
+	pub fn try_gmatch<'b, 'c>(&'c mut self, text: &'b str) -> TryGMatch<'a, 'b, 'c, MAXCAPTURES> {
+		TryGMatch { offsets: self.gmatch_with_offsets(text) }
+	}
+
+	#[cfg(not(feature = "safe"))]
+	pub fn gmatch_captures<'b, 'c>(&'c mut self, text: &'b str) -> GMatchCaptures<'a, 'b, 'c, MAXCAPTURES> {
+		GMatchCaptures { m: self, text, cursor: MatchCursor::new() }
+	}
+
+	pub fn gmatch_bytes<'b, 'c>(&'c mut self, bytes: &'b [u8]) -> GMatchBytes<'a, 'b, 'c, MAXCAPTURES> {
+		GMatchBytes { m: self, bytes, cursor: MatchCursor::new(), last: 0 }
+	}
+
+	/// Ranges of the whole match and every capture for each match in `bytes`, stepping over empty matches like [Pattern::gmatch]
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("(%a)%d").unwrap();
+	/// let found: Vec<_> = patt.gmatch_ranges_bytes(b"a1 b2").map(|m| m.to_vec()).collect();
+	/// assert_eq!(found, [vec![0..2, 0..1], vec![3..5, 3..4]]);
+	/// ```
+	pub fn gmatch_ranges_bytes<'b, 'c>(&'c mut self, bytes: &'b [u8]) -> GMatchRangesBytes<'a, 'b, 'c, MAXCAPTURES> {
+		GMatchRangesBytes { m: self, bytes, cursor: MatchCursor::new() }
+	}
+
+	/// Iterates matches over the platform encoding of an [OsStr], see [Pattern::matches_os]
+	pub fn gmatch_os<'b, 'c>(&'c mut self, s: &'b OsStr) -> GMatchBytes<'a, 'b, 'c, MAXCAPTURES> {
+		self.gmatch_bytes(s.as_encoded_bytes())
+	}
+
+	/// Splits `text` around every match, like [str::split] with a pattern
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("%s*,%s*").unwrap();
+	/// assert_eq!(patt.split("a , b,c").collect::<Vec<_>>(), ["a", "b", "c"]);
+	/// ```
+	pub fn split<'b, 'c>(&'c mut self, text: &'b str) -> Split<'a, 'b, 'c, MAXCAPTURES> {
+		Split { m: self, text, cursor: MatchCursor::new(), last: 0, done: false }
+	}
+
+	/// Like [Pattern::split], with the separators in between the pieces.
+	/// Text and separators alternate, starting and ending with (maybe empty) text.
+	///
+	/// ```
+	/// use lupat::{Pattern, Piece};
+	///
+	/// let mut patt: Pattern = Pattern::new("[+-]").unwrap();
+	/// let pieces: Vec<Piece> = patt.split_with_delimiters("1+2-3").collect();
+	/// assert_eq!(pieces, [Piece::Text("1"), Piece::Sep("+"), Piece::Text("2"), Piece::Sep("-"), Piece::Text("3")]);
+	/// ```
+	pub fn split_with_delimiters<'b, 'c>(&'c mut self, text: &'b str) -> SplitDelimiters<'a, 'b, 'c, MAXCAPTURES> {
+		SplitDelimiters { split: self.split(text), sep: None }
+	}
+
+	#[cfg(feature = "utf16")]
+	pub fn gmatch_utf16<'b, 'c>(&'c mut self, units: &'b [u16]) -> GMatchUtf16<'a, 'b, 'c, MAXCAPTURES> {
+		GMatchUtf16 { m: self, units, cursor: MatchCursor::new() }
+	}
+}
+
+pub struct GMatch<'a, 'b, 'c, const MAXCAPTURES: usize = LUA_MAXCAPTURES>
+where
+	'a: 'c,
+{
+	m: &'c mut Pattern<'a, MAXCAPTURES>,
+	text: &'b str,
+	cursor: MatchCursor,
+	last: usize,
+}
+
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> GMatch<'a, 'b, 'c, MAXCAPTURES> {
+	/// The text after the last match, which hasn't been searched yet
+	pub fn remainder(&self) -> &'b str {
+		&self.text[self.last..]
+	}
+}
+
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> Iterator for GMatch<'a, 'b, 'c, MAXCAPTURES> {
+	type Item = &'b str;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if !self.cursor.advance_str(self.m, self.text) {
+			return None;
+		}
+		self.last = char_range(self.text, self.m.range()).end.max(self.last);
+		Some(slice(self.text, self.m.first_capture()))
+	}
+}
+
+/// Iterator over matches and their offsets into the whole text, from [Pattern::gmatch_with_offsets]
+pub struct GMatchOffsets<'a, 'b, 'c, const MAXCAPTURES: usize = LUA_MAXCAPTURES>
+where
+	'a: 'c,
+{
+	m: &'c mut Pattern<'a, MAXCAPTURES>,
+	text: &'b str,
+	cursor: MatchCursor,
+}
+
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> Iterator for GMatchOffsets<'a, 'b, 'c, MAXCAPTURES> {
+	type Item = Match<'b>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if !self.cursor.advance_str(self.m, self.text) {
+			return None;
+		}
+		let range = char_range(self.text, self.m.range());
+		Some(Match { text: &self.text[range.clone()], range })
+	}
+}
+
+/// Iterator over matches or the error that stopped it, from [Pattern::try_gmatch]
+pub struct TryGMatch<'a, 'b, 'c, const MAXCAPTURES: usize = LUA_MAXCAPTURES>
+where
+	'a: 'c,
+{
+	offsets: GMatchOffsets<'a, 'b, 'c, MAXCAPTURES>,
+}
+
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> Iterator for TryGMatch<'a, 'b, 'c, MAXCAPTURES> {
+	type Item = Result<Match<'b>, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let GMatchOffsets { m, text, cursor } = &mut self.offsets;
+		match cursor.try_advance_str(m, text) {
+			Ok(true) => {
+				let range = char_range(text, m.range());
+				Some(Ok(Match { text: &text[range.clone()], range }))
+			}
+			Ok(false) => None,
+			Err(e) => Some(Err(e)),
+		}
+	}
+}
+
+#[cfg(not(feature = "safe"))]
+pub struct CapturesUnsafe<'b> {
+	matches: *const LuaMatch,
+	text: &'b str,
+}
+
+#[cfg(not(feature = "safe"))]
+impl<'b> CapturesUnsafe<'b> {
+	/// get the capture as a string slice
+	pub fn get(&self, i: usize) -> &'b str {
+		unsafe {
+			let p = self.matches.add(i);
+			let range = ops::Range {
+				start: (*p).start,
+				end: (*p).end,
+			};
+			slice(self.text, range)
+		}
+	}
+}
+
+#[cfg(not(feature = "safe"))]
+pub struct GMatchCaptures<'a, 'b, 'c, const MAXCAPTURES: usize = LUA_MAXCAPTURES>
+where
+	'a: 'c,
+{
+	m: &'c mut Pattern<'a, MAXCAPTURES>,
+	text: &'b str,
+	cursor: MatchCursor,
+}
+
+#[cfg(not(feature = "safe"))]
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> Iterator for GMatchCaptures<'a, 'b, 'c, MAXCAPTURES>
+where
+	'a: 'c,
+{
+	type Item = CapturesUnsafe<'b>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if !self.cursor.advance_str(self.m, self.text) {
+			return None;
+		}
+		let match_ptr: *const LuaMatch = self.m.matches.as_ptr();
+		Some(CapturesUnsafe {
+			matches: match_ptr,
+			text: self.text,
+		})
+	}
+}
+
+/// Iterator over the text between matches, from [Pattern::split]
+pub struct Split<'a, 'b, 'c, const MAXCAPTURES: usize = LUA_MAXCAPTURES>
+where
+	'a: 'c,
+{
+	m: &'c mut Pattern<'a, MAXCAPTURES>,
+	text: &'b str,
+	cursor: MatchCursor,
+	last: usize,
+	done: bool,
+}
+
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> Split<'a, 'b, 'c, MAXCAPTURES> {
+	/// The text that hasn't been split yet, `None` once the last piece was returned
+	pub fn remainder(&self) -> Option<&'b str> {
+		if self.done {
+			None
+		} else {
+			Some(&self.text[self.last..])
+		}
+	}
+
+	/// The next piece, and the separator after it unless it's the last one
+	fn next_piece(&mut self) -> Option<(&'b str, Option<&'b str>)> {
+		if self.done {
+			return None;
+		}
+		if self.cursor.advance_str(self.m, self.text) {
+			// The previous match may have ended inside the char this one starts in
+			let all = char_range(self.text, self.m.range());
+			let start = all.start.max(self.last);
+			let piece = &self.text[self.last..start];
+			self.last = all.end.max(self.last);
+			Some((piece, Some(&self.text[start..self.last])))
+		} else {
+			self.done = true;
+			Some((&self.text[self.last..], None))
+		}
+	}
+}
+
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> Iterator for Split<'a, 'b, 'c, MAXCAPTURES> {
+	type Item = &'b str;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.next_piece().map(|(piece, _)| piece)
+	}
+}
+
+/// A part of the text from [Pattern::split_with_delimiters]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Piece<'b> {
+	/// Text between separators
+	Text(&'b str),
+	/// A match of the pattern
+	Sep(&'b str),
+}
+
+/// Iterator over the text between matches and the matches themselves, from [Pattern::split_with_delimiters]
+pub struct SplitDelimiters<'a, 'b, 'c, const MAXCAPTURES: usize = LUA_MAXCAPTURES>
+where
+	'a: 'c,
+{
+	split: Split<'a, 'b, 'c, MAXCAPTURES>,
+	sep: Option<&'b str>,
+}
+
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> SplitDelimiters<'a, 'b, 'c, MAXCAPTURES> {
+	/// See [Split::remainder]
+	pub fn remainder(&self) -> Option<&'b str> {
+		self.split.remainder()
+	}
+}
+
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> Iterator for SplitDelimiters<'a, 'b, 'c, MAXCAPTURES> {
+	type Item = Piece<'b>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if let Some(sep) = self.sep.take() {
+			return Some(Piece::Sep(sep));
+		}
+		let (piece, sep) = self.split.next_piece()?;
+		self.sep = sep;
+		Some(Piece::Text(piece))
+	}
+}
+
+/// Iterator for all byte slices from `gmatch_bytes`
+pub struct GMatchBytes<'a, 'b, 'c, const MAXCAPTURES: usize = LUA_MAXCAPTURES>
+where
+	'a: 'c,
+{
+	m: &'c mut Pattern<'a, MAXCAPTURES>,
+	bytes: &'b [u8],
+	cursor: MatchCursor,
+	last: usize,
+}
+
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> GMatchBytes<'a, 'b, 'c, MAXCAPTURES> {
+	/// The bytes after the last match, which haven't been searched yet
+	pub fn remainder(&self) -> &'b [u8] {
+		&self.bytes[self.last..]
+	}
+}
+
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> Iterator for GMatchBytes<'a, 'b, 'c, MAXCAPTURES> {
+	type Item = &'b [u8];
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if !self.cursor.advance(self.m, self.bytes) {
+			return None;
+		}
+		self.last = self.m.range().end;
+		Some(&self.bytes[self.m.first_capture()])
+	}
+}
+
+/// Iterator over the ranges of every match, from [Pattern::gmatch_ranges_bytes]
+pub struct GMatchRangesBytes<'a, 'b, 'c, const MAXCAPTURES: usize = LUA_MAXCAPTURES>
+where
+	'a: 'c,
+{
+	m: &'c mut Pattern<'a, MAXCAPTURES>,
+	bytes: &'b [u8],
+	cursor: MatchCursor,
+}
+
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> Iterator for GMatchRangesBytes<'a, 'b, 'c, MAXCAPTURES> {
+	type Item = MatchRanges<MAXCAPTURES>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if !self.cursor.advance(self.m, self.bytes) {
+			return None;
+		}
+		Some(self.m.last_ranges())
+	}
+}
+
+/// Iterator for all UTF-16 slices from `gmatch_utf16`
+#[cfg(feature = "utf16")]
+pub struct GMatchUtf16<'a, 'b, 'c, const MAXCAPTURES: usize = LUA_MAXCAPTURES>
+where
+	'a: 'c,
+{
+	m: &'c mut Pattern<'a, MAXCAPTURES>,
+	units: &'b [u16],
+	cursor: MatchCursor,
+}
+
+#[cfg(feature = "utf16")]
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> Iterator for GMatchUtf16<'a, 'b, 'c, MAXCAPTURES> {
+	type Item = &'b [u16];
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if !self.cursor.advance_utf16(self.m, self.units) {
+			return None;
+		}
+		Some(&self.units[self.m.first_capture()])
+	}
+}
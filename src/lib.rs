@@ -1,44 +1,471 @@
+#![cfg_attr(feature = "safe", forbid(unsafe_code))]
+
+#[cfg(feature = "conformance")]
+extern crate mlua;
+#[cfg(feature = "ropey")]
+extern crate ropey;
+#[cfg(feature = "nom")]
+extern crate nom;
+#[cfg(feature = "allocator-api2")]
+extern crate allocator_api2;
+#[cfg(feature = "serde")]
+extern crate serde_core;
+#[cfg(feature = "uniffi")]
+extern crate uniffi;
+
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
+
+use ast::Ast;
+use std::borrow::Cow;
+use std::ffi::OsStr;
 use std::ops;
+use std::path::Path;
 
 pub mod error;
 use error::*;
 mod pattern;
 use pattern::*;
+#[cfg(feature = "tables")]
+pub mod tables;
+#[cfg(not(feature = "tables"))]
+mod tables;
+mod scanner;
+pub use scanner::Scanner;
+#[cfg(feature = "glob")]
+mod glob;
+#[cfg(feature = "glob")]
+pub use glob::{from_glob, from_path_glob};
+pub mod ast;
+#[cfg(feature = "build")]
+pub mod build;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+mod builder;
+pub use builder::{Class, PatternBuilder};
+mod tokens;
+pub use tokens::{tokens, Token, TokenKind, Tokens};
+#[cfg(feature = "gsub")]
+mod pipeline;
+#[cfg(feature = "gsub")]
+pub use pipeline::{gsub_pipeline, gsub_pipeline_longest};
+#[cfg(feature = "gsub")]
+mod substitute;
+#[cfg(feature = "gsub")]
+pub use substitute::{generate_gsub_patterns, gsub_lines, BoundSubstitute, ByteCaptures, Edit, Fixpoint, Occurrence, Replacer, SourceMap, Span, Subst, Substitute};
+#[cfg(feature = "iter")]
+mod iter;
+#[cfg(feature = "iter")]
+pub use iter::{GMatch, GMatchBytes, GMatchOffsets, GMatchRangesBytes, Piece, Split, SplitDelimiters, TryGMatch};
+#[cfg(all(feature = "iter", not(feature = "safe")))]
+pub use iter::{CapturesUnsafe, GMatchCaptures};
+#[cfg(all(feature = "iter", feature = "utf16"))]
+pub use iter::GMatchUtf16;
+mod cursor;
+use cursor::MatchCursor;
+mod highlight;
+pub use highlight::highlight;
+mod lines;
+pub use lines::LineIndex;
+mod validate;
+pub use validate::{validate, validate_all, PatternInfo, SyntaxError};
+mod compare;
+pub use compare::{compare, Disagreement};
+mod oneshot;
+pub use oneshot::{find, is_match};
+#[cfg(feature = "gsub")]
+pub use oneshot::gsub;
+#[cfg(feature = "gsub")]
+mod incremental;
+#[cfg(feature = "iter")]
+mod typed;
+mod options;
+mod explain;
+pub mod bindings;
+mod registry;
+mod owned;
+pub use owned::{CapturesOwned, MatchOwned};
+mod slow;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "metrics")]
+pub use metrics::{clear_metrics_hook, set_metrics_hook, MatchMetrics};
+mod arena;
+#[cfg(feature = "extensions")]
+mod fragments;
+#[cfg(feature = "extensions")]
+pub use fragments::Fragments;
+pub use arena::{PatternArena, PatternId};
+#[cfg(feature = "iter")]
+pub use arena::ArenaSplit;
+pub use slow::{clear_slow_match_hook, set_slow_match_hook, SlowKind, SlowMatch};
+pub use registry::Registry;
+pub use explain::Failure;
+pub use options::PatternOptions;
+pub use pattern::{Haystack, LuaMatch, LuaVersion, Unit, LUA_MAXCAPTURES};
+#[cfg(feature = "iter")]
+pub use typed::{FromCapture, FromCaptures, GMatchScan};
+#[cfg(feature = "ropey")]
+pub mod rope;
+#[cfg(feature = "nom")]
+pub mod combinator;
+#[cfg(all(feature = "allocator-api2", feature = "gsub"))]
+pub mod allocator;
+#[cfg(feature = "encoding")]
+pub mod encoding;
+#[cfg(feature = "unicode")]
+pub mod unicode;
+#[cfg(feature = "unicode")]
+mod nfc_tables;
+#[cfg(feature = "segmentation")]
+mod graphemes;
+#[cfg(feature = "segmentation")]
+mod grapheme_tables;
+pub mod prelude;
 
 /// Represents a Lua string pattern and the results of a match
 pub struct Pattern<'a, const MAXCAPTURES: usize = LUA_MAXCAPTURES> {
 	patt: &'a [u8],
 	matches: [LuaMatch; MAXCAPTURES],
 	n_match: usize,
+	flags: Flags,
+	/// Every `[...]` set, precomputed for bytes
+	sets: Cow<'a, [SetBitmap]>,
 }
 
 impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
 	pub fn try_from_bytes(bytes: &'a [u8]) -> Result<Self, Error> {
-		str_check::<MAXCAPTURES>(bytes)?;
+		Pattern::with_flags(bytes, Flags::default())
+	}
+
+	fn with_flags(bytes: &'a [u8], flags: Flags) -> Result<Self, Error> {
+		str_check::<MAXCAPTURES>(bytes, flags)?;
 		let matches = [LuaMatch { start: 0, end: 0 }; MAXCAPTURES];
 		Ok(Pattern {
 			patt: bytes,
 			matches,
 			n_match: 0,
+			flags,
+			sets: Cow::Owned(set_bitmaps(bytes, flags)),
 		})
 	}
 
+	/// The syntax tree, parsed with the escape character of the pattern
+	fn ast(&self) -> Result<Ast, Error> {
+		ast::parse_escaped(self.patt, self.flags.escape)
+	}
+
+	/// Makes `^` and `$` also match at the start and end of every line, rather than only the whole text.
+	/// This is off by default to behave like Lua.
+	pub fn multiline(mut self, enabled: bool) -> Self {
+		self.flags.multiline = enabled;
+		self
+	}
+
 	pub fn new<S: AsRef<[u8]> + ?Sized>(pattern: &'a S) -> Result<Self, Error> {
 		Pattern::try_from_bytes( pattern.as_ref() )
 	}
 
+	/// Converts to a pattern with room for a different number of captures, keeping its options.
+	/// Fails with [Error::TooManyCaptures] if the pattern doesn't fit in `M`.
+	pub fn with_captures<const M: usize>(self) -> Result<Pattern<'a, M>, Error> {
+		Pattern::<M>::with_flags(self.patt, self.flags)
+	}
+
+	/// Shortest length in bytes any match can have.
+	/// Texts shorter than this can be skipped without matching.
+	pub fn min_match_len(&self) -> usize {
+		self.ast().map(|ast| ast.min_len()).unwrap_or(0)
+	}
+
+	/// Longest length in bytes any match can have, `None` if it's unbounded
+	pub fn max_match_len(&self) -> Option<usize> {
+		self.ast().ok()?.max_len()
+	}
+
+	/// Whether every match is empty, like `%f[%w]` or `^()`, see [Ast::is_zero_width].
+	/// Iterating or substituting such a pattern still ends: like Lua, the search moves one character ahead after each empty match.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("%f[%w]").unwrap();
+	/// assert!(patt.is_zero_width());
+	/// assert_eq!(patt.find_all("hi there"), [0..0, 3..3]);
+	/// ```
+	pub fn is_zero_width(&self) -> bool {
+		self.ast().is_ok_and(|ast| ast.is_zero_width())
+	}
+
+	/// Shortest and longest length in bytes capture `i` can have, `None` for no limit, see [Ast::capture_bounds].
+	/// Captures the pattern doesn't have are always empty, like [Captures::get] gives for them.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let patt: Pattern = Pattern::new("^(%a%a?%a?) (%d+)$").unwrap();
+	/// // The first capture always fits a 3 byte column
+	/// assert_eq!(patt.capture_bounds(1), (1, Some(3)));
+	/// assert_eq!(patt.capture_bounds(2), (1, None));
+	/// ```
+	pub fn capture_bounds(&self, i: usize) -> (usize, Option<usize>) {
+		self.ast().ok().and_then(|ast| ast.capture_bounds(i)).unwrap_or((0, Some(0)))
+	}
+
+	/// Whether matches and captures in valid UTF-8 always land on char boundaries, see [Ast::utf8_safe].
+	/// If so the str API never widens ranges, otherwise the bytes API might be the better fit.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// assert!(Pattern::<1>::new("%a+ é").unwrap().utf8_safe());
+	/// assert!(!Pattern::<1>::new("[^,]+").unwrap().utf8_safe());
+	/// ```
+	pub fn utf8_safe(&self) -> bool {
+		#[cfg(feature = "segmentation")]
+		let any_is_char = self.flags.graphemes;
+		#[cfg(not(feature = "segmentation"))]
+		let any_is_char = false;
+		self.ast().is_ok_and(|ast| (ast.anchor_start || ast.min_len() > 0) && ast::utf8_safe(&ast.nodes, any_is_char))
+	}
+
+	/// Bytes every match starts with, for building prefilters.
+	/// Empty for case insensitive patterns, as matches don't have to start with these exact bytes.
+	pub fn literal_prefix(&self) -> Vec<u8> {
+		if self.flags.case_insensitive {
+			return Vec::new();
+		}
+		self.ast().map(|ast| ast.literal_prefix()).unwrap_or_default()
+	}
+
+	/// Bytes every match ends with, for building prefilters. Empty for case insensitive patterns like [Pattern::literal_prefix]
+	pub fn literal_suffix(&self) -> Vec<u8> {
+		if self.flags.case_insensitive {
+			return Vec::new();
+		}
+		self.ast().map(|ast| ast.literal_suffix()).unwrap_or_default()
+	}
+
+	/// Where each capture is defined in the pattern source, see [ast::capture_spans]
+	pub fn capture_spans(&self) -> Vec<ops::Range<usize>> {
+		ast::capture_spans_escaped(self.patt, self.flags.escape).unwrap_or_default()
+	}
+
+	/// Canonical source of this pattern, see [Ast::normalize]
+	pub fn normalize(&self) -> String {
+		match self.ast() {
+			Ok(mut ast) => {
+				ast.normalize();
+				ast.to_string()
+			}
+			Err(_) => String::from_utf8_lossy(self.patt).into_owned(),
+		}
+	}
+
+	/// Whether both patterns have the same options and normalize to the same source.
+	/// Patterns that match the same things but are written very differently (like `%d` and `[0-9]`) aren't detected.
+	pub fn equivalent<const M: usize>(&self, other: &Pattern<M>) -> bool {
+		self.flags == other.flags && self.normalize() == other.normalize()
+	}
+
+	/// The matcher everything else is built on, for custom iteration and FFI layers.
+	/// Searches `haystack` from `init`, or only there with `anchor`, storing the whole match and then every capture into `slots`.
+	/// Returns how many slots were filled, 0 if it didn't match. The pattern's own match is left as it was.
+	///
+	/// `slots` needs room for `MAXCAPTURES` matches, or this fails with [Error::TooManyCaptures].
+	///
+	/// ```
+	/// use lupat::{LuaMatch, Pattern};
+	///
+	/// let patt: Pattern<'_, 2> = Pattern::new("(%d+)").unwrap();
+	/// let mut slots = [LuaMatch { start: 0, end: 0 }; 2];
+	/// assert_eq!(patt.find_into(b"a1 b22", &mut slots, 3, false), Ok(2));
+	/// assert_eq!(slots[1], LuaMatch { start: 4, end: 6 });
+	/// ```
+	pub fn find_into(&self, haystack: &[u8], slots: &mut [LuaMatch], init: usize, anchor: bool) -> Result<usize, Error> {
+		if slots.len() < MAXCAPTURES.max(1) {
+			return Err( Error::TooManyCaptures );
+		}
+		find_raw::<_, MAXCAPTURES>(self.patt, self.flags, &self.sets, haystack, slots, init, anchor)
+	}
+
+	/// Runs the matcher, leaving no match behind if it fails
+	fn run<H: Haystack + ?Sized>(&mut self, s: &H, init: usize, anchor: bool) -> Result<bool, Error> {
+		let res = find_raw::<_, MAXCAPTURES>(self.patt, self.flags, &self.sets, s, &mut self.matches, init, anchor);
+		self.n_match = *res.as_ref().unwrap_or(&0);
+		res.map(|n| n > 0)
+	}
+
+	/// Whether the pattern matches anywhere in `s`.
+	/// A pattern too complex to finish matching (see [Error::TooComplex]) counts as not matching, use [Pattern::try_matches_bytes] to tell them apart.
 	pub fn matches_bytes(&mut self, s: &[u8]) -> bool {
-		self.n_match = str_match::<MAXCAPTURES>(s, self.patt, &mut self.matches).expect("Should not fail - report as bug");
-		self.n_match > 0
+		self.run(s, 0, false).unwrap_or(false)
+	}
+
+	/// Like [Pattern::matches_bytes], but fails instead of not matching if the pattern is too complex
+	pub fn try_matches_bytes(&mut self, s: &[u8]) -> Result<bool, Error> {
+		self.run(s, 0, false)
+	}
+
+	/// Like [Pattern::matches_bytes], but starting the search at `init`, and with `anchor` only trying there
+	pub(crate) fn matches_bytes_at(&mut self, s: &[u8], init: usize, anchor: bool) -> bool {
+		self.run(s, init, anchor).unwrap_or(false)
+	}
+
+	/// Matches against UTF-16 code units. Ranges from [Pattern::capture] are in code units.
+	/// Classes like `%a` only ever match ascii, and literals in the pattern should be ascii too.
+	#[cfg(feature = "utf16")]
+	pub fn matches_utf16(&mut self, s: &[u16]) -> bool {
+		self.run(s, 0, false).unwrap_or(false)
+	}
+
+	/// Matches against any [Haystack], like a [VecDeque](std::collections::VecDeque) ring buffer or a pair of slices,
+	/// as if its parts were one contiguous slice. Ranges from [Pattern::capture] are offsets into the whole haystack.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	/// use std::collections::VecDeque;
+	///
+	/// let mut buf: VecDeque<u8> = VecDeque::with_capacity(8);
+	/// buf.extend(b"xxxxxGET");
+	/// buf.drain(..5);
+	/// buf.extend(b" /a\r\n");
+	/// let mut patt: Pattern<'_, 2> = Pattern::new("^GET (%S+)").unwrap();
+	/// assert!(patt.matches_haystack(&buf));
+	/// assert_eq!(patt.capture(1), 4..6);
+	/// assert!(patt.matches_haystack(&(&b"GE"[..], &b"T /x"[..])));
+	/// ```
+	pub fn matches_haystack<H: Haystack + ?Sized>(&mut self, haystack: &H) -> bool {
+		self.run(haystack, 0, false).unwrap_or(false)
+	}
+
+	/// Like [Pattern::matches_haystack], but fails instead of not matching if the pattern is too complex or the haystack too long
+	pub fn try_matches_haystack<H: Haystack + ?Sized>(&mut self, haystack: &H) -> Result<bool, Error> {
+		self.run(haystack, 0, false)
+	}
+
+	/// The longest haystack, in units, that can be matched against.
+	/// Offsets in [LuaMatch] are `usize`, and the matcher steps one past the end, so anything longer fails with [Error::HaystackTooLong] instead of overflowing.
+	/// Slices can never be longer than this, only combined haystacks like a pair of slices can.
+	pub const fn max_haystack_len() -> usize {
+		MAX_HAYSTACK_LEN
 	}
 
 	pub fn matches(&mut self, text: &str) -> bool {
 		self.matches_bytes(text.as_bytes())
 	}
 
+	/// See [Pattern::try_matches_bytes]
+	pub fn try_matches(&mut self, text: &str) -> Result<bool, Error> {
+		self.try_matches_bytes(text.as_bytes())
+	}
+
+	/// Length of the match at the very start of `text`, as if the pattern began with `^`
+	pub fn is_prefix_of(&mut self, text: &str) -> Option<usize> {
+		if self.matches_bytes_at(text.as_bytes(), 0, true) {
+			Some(self.range().end)
+		} else {
+			None
+		}
+	}
+
+	/// Whether the pattern matches starting exactly at byte `pos` of `text`, without skipping ahead to a later match like a search would.
+	/// Tokenizers can match at their cursor this way without adding `^` to every pattern.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("%d+").unwrap();
+	/// assert!(patt.matches_at_exactly("x = 42", 4));
+	/// assert_eq!(patt.range(), 4..6);
+	/// assert!(!patt.matches_at_exactly("x = 42", 2));
+	/// ```
+	pub fn matches_at_exactly(&mut self, text: &str, pos: usize) -> bool {
+		if pos > text.len() {
+			self.n_match = 0;
+			return false;
+		}
+		self.matches_bytes_at(text.as_bytes(), pos, true)
+	}
+
+	/// Length of the longest match ending at the very end of `text`, as if the pattern ended with `$`
+	pub fn is_suffix_of(&mut self, text: &str) -> Option<usize> {
+		let mut ast = self.ast().ok()?;
+		ast.anchor_end = true;
+		let anchored = ast.to_bytes();
+		// The rebuilt source always escapes with `%`
+		let flags = Flags { escape: Flags::DEFAULT.escape, ..self.flags };
+		let mut patt = Pattern::<MAXCAPTURES>::with_flags(&anchored, flags).ok()?;
+		if patt.matches(text) {
+			let range = patt.range();
+			self.matches = patt.matches;
+			self.n_match = patt.n_match;
+			Some(range.len())
+		} else {
+			None
+		}
+	}
+
+	/// Like [Pattern::matches], but only searches within `range` of `text`.
+	/// `^` and `$` anchor to the bounds of `range`, while captures are still reported as offsets into `text`.
+	pub fn matches_in(&mut self, text: &str, range: ops::Range<usize>) -> bool {
+		let offset = range.start;
+		let Some(bytes) = text.as_bytes().get(range) else {
+			self.n_match = 0;
+			return false;
+		};
+		let found = self.matches_bytes(bytes);
+		for m in &mut self.matches[..self.n_match] {
+			m.start += offset;
+			m.end += offset;
+		}
+		found
+	}
+
+	/// Range of the first match within `range` of `text`, see [Pattern::matches_in]
+	pub fn find_in(&mut self, text: &str, range: ops::Range<usize>) -> Option<ops::Range<usize>> {
+		if self.matches_in(text, range) {
+			Some(self.range())
+		} else {
+			None
+		}
+	}
+
+	/// Range of the first match at or after byte `init` like Lua's `string.find(s, p, init)`, with offsets into the whole of `text`.
+	/// `^` anchors at `init`, and an `init` past the end of `text` never matches.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("%d+").unwrap();
+	/// assert_eq!(patt.find("a1 b22", 0), Some(1..2));
+	/// assert_eq!(patt.find("a1 b22", 2), Some(4..6));
+	/// assert_eq!(patt.find("a1 b22", 7), None);
+	/// ```
+	pub fn find(&mut self, text: &str, init: usize) -> Option<ops::Range<usize>> {
+		if init > text.len() {
+			self.n_match = 0;
+			return None;
+		}
+		if self.run(text.as_bytes(), init, false).unwrap_or(false) {
+			Some(self.range())
+		} else {
+			None
+		}
+	}
+
+	/// The first match in `text`, with the text it matched
+	pub fn find_match<'t>(&mut self, text: &'t str) -> Option<Match<'t>> {
+		if self.matches(text) {
+			Some(Match { text: slice(text, self.range()), range: self.range() })
+		} else {
+			None
+		}
+	}
+
 	pub fn match_maybe<'t>(&mut self, text: &'t str) -> Option<&'t str> {
 		if self.matches(text) {
-			Some(&text[self.first_capture()])
+			Some(slice(text, self.first_capture()))
 		} else {
 			None
 		}
@@ -74,6 +501,46 @@ impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
 		res
 	}
 
+	/// Like [Pattern::captures], but matching bytes that may not be valid UTF-8, such as log lines.
+	/// Each capture is only copied if it has to be, to replace invalid sequences with `U+FFFD` like [String::from_utf8_lossy].
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("user=(%S+)").unwrap();
+	/// assert_eq!(patt.captures_lossy(b"user=bob ok"), ["user=bob", "bob"]);
+	/// assert_eq!(patt.captures_lossy(b"user=b\xffb"), ["user=b\u{FFFD}b", "b\u{FFFD}b"]);
+	/// ```
+	pub fn captures_lossy<'t>(&mut self, bytes: &'t [u8]) -> Vec<Cow<'t, str>> {
+		self.matches_bytes(bytes);
+		(0..self.n_match).map(|i| String::from_utf8_lossy(&bytes[self.capture(i)])).collect()
+	}
+
+	/// Like [Pattern::match_maybe] over bytes, see [Pattern::captures_lossy]
+	pub fn match_maybe_lossy<'t>(&mut self, bytes: &'t [u8]) -> Option<Cow<'t, str>> {
+		if self.matches_bytes(bytes) {
+			Some(String::from_utf8_lossy(&bytes[self.first_capture()]))
+		} else {
+			None
+		}
+	}
+
+	/// Runs [Pattern::captures] over every text, an empty result meaning it didn't match
+	pub fn match_many<'t, I: IntoIterator<Item = &'t str>>(&mut self, texts: I) -> Vec<Vec<&'t str>> {
+		texts.into_iter().map(|text| self.captures(text)).collect()
+	}
+
+	/// Like [Pattern::match_many], over byte slices
+	pub fn match_many_bytes<'t, I: IntoIterator<Item = &'t [u8]>>(&mut self, texts: I) -> Vec<Vec<&'t [u8]>> {
+		texts
+			.into_iter()
+			.map(|bytes| {
+				self.matches_bytes(bytes);
+				(0..self.n_match).map(|i| &bytes[self.capture(i)]).collect()
+			})
+			.collect()
+	}
+
 	pub fn match_captures<'b, 'c>(&'c self, text: &'b str) -> Captures<'a, 'b, 'c, MAXCAPTURES> {
 		Captures { m: self, text }
 	}
@@ -82,11 +549,22 @@ impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
 		self.matches(text);
 		vec.clear();
 		for i in 0..self.n_match {
-			vec.push(&text[self.capture(i)]);
+			vec.push(slice(text, self.capture(i)));
 		}
 		self.n_match > 0
 	}
 
+	/// The slots of the last match, the whole match first and then each capture.
+	/// Empty if it didn't match.
+	pub fn raw_matches(&self) -> &[LuaMatch] {
+		&self.matches[..self.n_match]
+	}
+
+	/// Every slot, including ones the last match didn't fill which hold stale or zero offsets
+	pub fn raw_slots(&self) -> &[LuaMatch] {
+		&self.matches
+	}
+
 	/// The full match (same as `capture(0)`)
 	pub fn range(&self) -> ops::Range<usize> {
 		self.capture(0)
@@ -94,8 +572,17 @@ impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
 
 	pub fn capture(&self, i: usize) -> ops::Range<usize> {
 		ops::Range {
-			start: self.matches[i].start as usize,
-			end: self.matches[i].end as usize,
+			start: self.matches[i].start,
+			end: self.matches[i].end,
+		}
+	}
+
+	/// Like [Pattern::capture], but `None` if there's no such capture in the last match instead of a stale or panicking result
+	pub fn try_capture(&self, i: usize) -> Option<ops::Range<usize>> {
+		if i < self.n_match {
+			Some(self.capture(i))
+		} else {
+			None
 		}
 	}
 
@@ -104,146 +591,135 @@ impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
 		self.capture(idx)
 	}
 
-	pub fn gmatch<'b, 'c>(&'c mut self, text: &'b str) -> GMatch<'a, 'b, 'c, MAXCAPTURES> {
-		GMatch { m: self, text }
+	/// Matches against the platform encoding of an [OsStr] (WTF-8 on Windows, raw bytes elsewhere).
+	/// Ranges from [Pattern::capture] index into [OsStr::as_encoded_bytes].
+	pub fn matches_os(&mut self, s: &OsStr) -> bool {
+		self.matches_bytes(s.as_encoded_bytes())
 	}
 
-	pub fn gmatch_captures<'b, 'c>(&'c mut self, text: &'b str) -> GMatchCaptures<'a, 'b, 'c, MAXCAPTURES> {
-		GMatchCaptures { m: self, text }
+	/// Matches against a path, see [Pattern::matches_os]
+	pub fn match_path(&mut self, path: &Path) -> bool {
+		self.matches_os(path.as_os_str())
 	}
 
-	pub fn gmatch_bytes<'b>(&'a mut self, bytes: &'b [u8]) -> GMatchBytes<'a, 'b, MAXCAPTURES> {
-		GMatchBytes { m: self, bytes }
+	/// Ranges of the whole match and every capture, in a value that doesn't borrow `text` or the pattern
+	pub fn match_ranges(&mut self, text: &str) -> Option<MatchRanges<MAXCAPTURES>> {
+		self.match_ranges_bytes(text.as_bytes())
 	}
 
-	pub fn gsub_with<F>(&mut self, text: &str, lookup: F) -> String
-	where
-		F: Fn(Captures<MAXCAPTURES>) -> String,
-	{
-		let mut slice = text;
-		let mut res = String::new();
-		while self.matches(slice) {
-			// full range of match
-			let all = self.range();
-			// append everything up to match
-			res.push_str(&slice[0..all.start]);
-			let captures = Captures {
-				m: self,
-				text: slice,
-			};
-			let repl = lookup(captures);
-			res.push_str(&repl);
-			slice = &slice[all.end..];
+	/// Like [Pattern::match_ranges], over bytes
+	pub fn match_ranges_bytes(&mut self, bytes: &[u8]) -> Option<MatchRanges<MAXCAPTURES>> {
+		if !self.matches_bytes(bytes) {
+			return None;
 		}
-		res.push_str(slice);
-		res
+		Some(self.last_ranges())
 	}
 
-	pub fn gsub(&mut self, text: &str, repl: &str) -> Result<String, Error> {
-		let repl = generate_gsub_patterns(repl)?;
-		let mut slice = text;
-		let mut res = String::new();
-		while self.matches(slice) {
-			let all = self.range();
-			res.push_str(&slice[0..all.start]);
-			let captures = Captures {
-				m: self,
-				text: slice,
-			};
-			for r in &repl {
-				match *r {
-					Subst::Text(ref s) => res.push_str(s),
-					Subst::Capture(i) => res.push_str(captures.get(i)),
-				}
-			}
-			slice = &slice[all.end..];
+	/// [MatchRanges] of the last match
+	pub(crate) fn last_ranges(&self) -> MatchRanges<MAXCAPTURES> {
+		MatchRanges {
+			ranges: std::array::from_fn(|i| self.capture(i)),
+			len: self.n_match,
 		}
-		res.push_str(slice);
-		Ok(res)
 	}
 
-	pub fn gsub_bytes_with<F>(&mut self, bytes: &[u8], lookup: F) -> Vec<u8>
-	where
-		F: Fn(ByteCaptures<MAXCAPTURES>) -> Vec<u8>,
-	{
-		let mut slice = bytes;
-		let mut res = Vec::new();
-		while self.matches_bytes(slice) {
-			let all = self.range();
-			let capture = &slice[0..all.start];
-			res.extend_from_slice(capture);
-			let captures = ByteCaptures {
-				m: self,
-				bytes: slice,
-			};
-			let repl = lookup(captures);
-			res.extend(repl);
-			slice = &slice[all.end..];
+	/// Ranges of every match in `text`, as offsets into it
+	pub fn find_all(&mut self, text: &str) -> Vec<ops::Range<usize>> {
+		let mut cursor = MatchCursor::new();
+		let mut ranges = Vec::new();
+		while cursor.advance_str(self, text) {
+			ranges.push(self.range());
 		}
-		res.extend_from_slice(slice);
-		res
+		ranges
+	}
+
+	/// Like [Pattern::find_all], over bytes. Empty matches step over one byte at a time, like Lua.
+	pub fn find_all_bytes(&mut self, bytes: &[u8]) -> Vec<ops::Range<usize>> {
+		let mut cursor = MatchCursor::new();
+		let mut ranges = Vec::new();
+		while cursor.advance(self, bytes) {
+			ranges.push(self.range());
+		}
+		ranges
+	}
+}
+
+/// See [Pattern::max_haystack_len]
+const MAX_HAYSTACK_LEN: usize = isize::MAX as usize;
+
+/// What [Pattern::find_into] and every matching method runs, timed for [set_slow_match_hook]
+fn find_raw<H: Haystack + ?Sized, const MAXCAPTURES: usize>(patt: &[u8], flags: Flags, sets: &[SetBitmap], s: &H, slots: &mut [LuaMatch], init: usize, anchor: bool) -> Result<usize, Error> {
+	if s.len() > MAX_HAYSTACK_LEN {
+		return Err( Error::HaystackTooLong(MAX_HAYSTACK_LEN) );
 	}
+	let _timer = slow::Timer::start(SlowKind::Match, patt, s.len());
+	#[cfg(feature = "metrics")]
+	let recorder = metrics::Recorder::start(patt);
+	let res = str_match_with::<_, MAXCAPTURES>(s, patt, slots, init, anchor, flags, sets);
+	#[cfg(feature = "metrics")]
+	recorder.finish(res.as_ref().is_ok_and(|&n| n > 0));
+	res
 }
 
-#[derive(Debug)]
-pub enum Subst {
-	Text(String),
-	Capture(usize),
+/// Finds the first span at or after `start` that opens with `open` and ends with its matching `close`, like `%b` does.
+///
+/// ```
+/// assert_eq!(lupat::find_balanced("f(a, g(b)) + (c)", b'(', b')', 0), Some(1..10));
+/// ```
+pub fn find_balanced(text: &str, open: u8, close: u8, start: usize) -> Option<ops::Range<usize>> {
+	let bytes = text.as_bytes();
+	(start..bytes.len()).find_map(|s| balance_end(bytes, s, open as u32, close as u32).map(|end| s..end))
 }
 
-impl Subst {
-	fn new_text(text: &str) -> Subst {
-		Subst::Text(text.to_string())
+/// Widens `range` out to the nearest char boundaries of `text`, for matches that split a char
+pub(crate) fn char_range(text: &str, range: ops::Range<usize>) -> ops::Range<usize> {
+	let mut start = range.start.min(text.len());
+	while !text.is_char_boundary(start) {
+		start -= 1;
 	}
+	let mut end = range.end.clamp(start, text.len());
+	while !text.is_char_boundary(end) {
+		end += 1;
+	}
+	start..end
 }
 
-pub fn generate_gsub_patterns(repl: &str) -> Result<Vec<Subst>, Error> {
-	let mut m: Pattern<'_, 2> = Pattern::new("%%([%%%d])")?;
+/// `text[range]`, including all of any char the range cuts through instead of panicking
+pub(crate) fn slice(text: &str, range: ops::Range<usize>) -> &str {
+	&text[char_range(text, range)]
+}
 
-	let mut res = Vec::new();
-	let mut slice = repl;
-	while m.matches(slice) {
-		let all = m.range();
-		let before = &slice[0..all.start];
-		if !before.is_empty() {
-			res.push(Subst::new_text(before));
-		}
-		let capture = &slice[m.capture(1)];
-		if capture == "%" {
-			// escaped literal '%'
-			res.push(Subst::new_text("%"));
-		} else {
-			// has to be a digit
-			let index: usize = capture.parse().unwrap();
-			res.push(Subst::Capture(index));
-		}
-		slice = &slice[all.end..];
+/// Owned ranges from [Pattern::match_ranges], the whole match first and then each capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchRanges<const MAXCAPTURES: usize = LUA_MAXCAPTURES> {
+	ranges: [ops::Range<usize>; MAXCAPTURES],
+	len: usize,
+}
+
+impl<const MAXCAPTURES: usize> ops::Deref for MatchRanges<MAXCAPTURES> {
+	type Target = [ops::Range<usize>];
+
+	fn deref(&self) -> &Self::Target {
+		&self.ranges[..self.len]
 	}
-	res.push(Subst::new_text(slice));
-	Ok(res)
 }
 
-pub struct Substitute {
-	repl: Vec<Subst>,
+/// A whole match from [Pattern::find_match]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match<'t> {
+	text: &'t str,
+	range: ops::Range<usize>,
 }
 
-impl Substitute {
-	pub fn new(repl: &str) -> Result<Self, Error> {
-		Ok(Substitute {
-			repl: generate_gsub_patterns(repl)?,
-		})
+impl<'t> Match<'t> {
+	/// The matched text
+	pub fn as_str(&self) -> &'t str {
+		self.text
 	}
 
-	pub fn subst<const MAXCAPTURES: usize>(&self, patt: &Pattern<MAXCAPTURES>, text: &str) -> String {
-		let mut res = String::new();
-		let captures = patt.match_captures(text);
-		for r in &self.repl {
-			match *r {
-				Subst::Text(ref s) => res.push_str(s),
-				Subst::Capture(i) => res.push_str(captures.get(i)),
-			}
-		}
-		res
+	/// Where the match is in the searched text
+	pub fn range(&self) -> ops::Range<usize> {
+		self.range.clone()
 	}
 }
 
@@ -256,118 +732,69 @@ where
 }
 
 impl<'a, 'b, 'c, const MAXCAPTURES: usize> Captures<'a, 'b, 'c, MAXCAPTURES> {
-	/// get the capture as a string slice
+	/// get the capture as a string slice, or an empty one if there's no such capture.
+	/// Like Lua, `get(1)` is the whole match when the pattern has no captures.
 	pub fn get(&self, i: usize) -> &'b str {
-		&self.text[self.m.capture(i)]
+		self.try_get(i).unwrap_or("")
 	}
 
-	/// number of matches
-	pub fn num_matches(&self) -> usize {
-		self.m.n_match
+	/// Like [Captures::get], but `None` if there's no such capture
+	pub fn try_get(&self, i: usize) -> Option<&'b str> {
+		let range = match self.m.try_capture(i) {
+			Some(range) => range,
+			None if i == 1 && self.m.n_match == 1 => self.m.range(),
+			None => return None,
+		};
+		Some(slice(self.text, range))
 	}
-}
-
-pub struct ByteCaptures<'a, 'b, const MAXCAPTURES: usize = LUA_MAXCAPTURES> {
-	m: &'a Pattern<'a, MAXCAPTURES>,
-	bytes: &'b [u8],
-}
 
-impl<'a, 'b, const MAXCAPTURES: usize> ByteCaptures<'a, 'b, MAXCAPTURES> {
-	pub fn get(&self, i: usize) -> &'b [u8] {
-		&self.bytes[self.m.capture(i)]
+	/// Whether slot `i` was filled by the last match, so an empty capture can be told apart from one that isn't there.
+	/// Unlike [Captures::get], `is_set(1)` is false when the pattern has no captures.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("()a(b*)").unwrap();
+	/// assert!(patt.matches("a"));
+	/// let captures = patt.match_captures("a");
+	/// assert!(captures.is_set(1) && captures.is_set(2));
+	/// assert_eq!(captures.get(2), "");
+	/// assert!(!captures.is_set(3));
+	/// ```
+	pub fn is_set(&self, i: usize) -> bool {
+		i < self.m.n_match
 	}
 
+	/// number of matches
 	pub fn num_matches(&self) -> usize {
 		self.m.n_match
 	}
-}
-
-pub struct GMatch<'a, 'b, 'c, const MAXCAPTURES: usize = LUA_MAXCAPTURES>
-where
-	'a: 'c,
-{
-	m: &'c mut Pattern<'a, MAXCAPTURES>,
-	text: &'b str,
-}
 
-impl<'a, 'b, 'c, const MAXCAPTURES: usize> Iterator for GMatch<'a, 'b, 'c, MAXCAPTURES> {
-	type Item = &'b str;
-
-	fn next(&mut self) -> Option<Self::Item> {
-		if !self.m.matches(self.text) {
-			None
-		} else {
-			let slice = &self.text[self.m.first_capture()];
-			self.text = &self.text[self.m.range().end..];
-			Some(slice)
-		}
+	/// The capture that capture `i` is nested in, 0 if it's only in the whole match, see [Ast::capture_parent].
+	/// Together with [Captures::children] this gives the tree of groups, which spans alone can't tell apart when captures are empty or equal.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("(a(b*))(c)").unwrap();
+	/// assert!(patt.matches("ac"));
+	/// let captures = patt.match_captures("ac");
+	/// assert_eq!(captures.parent(2), Some(1));
+	/// assert_eq!(captures.children(0), [1, 3]);
+	/// ```
+	pub fn parent(&self, i: usize) -> Option<usize> {
+		self.m.ast().ok()?.capture_parent(i)
 	}
-}
 
-pub struct CapturesUnsafe<'b> {
-	matches: *const LuaMatch,
-	text: &'b str,
-}
-
-impl<'b> CapturesUnsafe<'b> {
-	/// get the capture as a string slice
-	pub fn get(&self, i: usize) -> &'b str {
-		unsafe {
-			let p = self.matches.add(i);
-			let range = ops::Range {
-				start: (*p).start as usize,
-				end: (*p).end as usize,
-			};
-			&self.text[range]
-		}
+	/// The captures directly inside capture `i`, in order, see [Captures::parent]
+	pub fn children(&self, i: usize) -> Vec<usize> {
+		self.m.ast().map(|ast| ast.capture_children(i)).unwrap_or_default()
 	}
-}
 
-pub struct GMatchCaptures<'a, 'b, 'c, const MAXCAPTURES: usize = LUA_MAXCAPTURES>
-where
-	'a: 'c,
-{
-	m: &'c mut Pattern<'a, MAXCAPTURES>,
-	text: &'b str,
-}
-
-impl<'a, 'b, 'c, const MAXCAPTURES: usize> Iterator for GMatchCaptures<'a, 'b, 'c, MAXCAPTURES>
-where
-	'a: 'c,
-{
-	type Item = CapturesUnsafe<'b>;
-
-	fn next(&mut self) -> Option<Self::Item> {
-		if !self.m.matches(self.text) {
-			None
-		} else {
-			let split = self.text.split_at(self.m.range().end);
-			self.text = split.1;
-			let match_ptr: *const LuaMatch = self.m.matches.as_ptr();
-			Some(CapturesUnsafe {
-				matches: match_ptr,
-				text: split.0,
-			})
-		}
+	/// Where capture `i` is in the text, 0 being the whole match, widened to whole chars like [Captures::get].
+	/// In [Pattern::gsub_with] these are offsets into the whole text given to it.
+	pub fn range(&self, i: usize) -> Option<ops::Range<usize>> {
+		self.m.try_capture(i).map(|range| char_range(self.text, range))
 	}
 }
 
-/// Iterator for all byte slices from `gmatch_bytes`
-pub struct GMatchBytes<'a, 'b, const MAXCAPTURES: usize = LUA_MAXCAPTURES> {
-	m: &'a mut Pattern<'a, MAXCAPTURES>,
-	bytes: &'b [u8],
-}
-
-impl<'a, 'b, const MAXCAPTURES: usize> Iterator for GMatchBytes<'a, 'b, MAXCAPTURES> {
-	type Item = &'b [u8];
-
-	fn next(&mut self) -> Option<Self::Item> {
-		if !self.m.matches_bytes(self.bytes) {
-			None
-		} else {
-			let slice = &self.bytes[self.m.first_capture()];
-			self.bytes = &self.bytes[self.m.range().end..];
-			Some(slice)
-		}
-	}
-}
@@ -0,0 +1,34 @@
+use Match;
+
+/// Byte offsets of where every line starts, for turning offsets into line and column numbers.
+/// Build it once per text and reuse it for every match.
+///
+/// ```
+/// let index = lupat::LineIndex::new("one\ntwo\nthree");
+/// assert_eq!(index.position(5), (2, 2));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+	starts: Vec<usize>,
+}
+
+impl LineIndex {
+	pub fn new(text: &str) -> Self {
+		let mut starts = vec![0];
+		starts.extend(text.bytes().enumerate().filter(|&(_, b)| b == b'\n').map(|(i, _)| i + 1));
+		LineIndex { starts }
+	}
+
+	/// Line and column of a byte offset, both starting at 1. The column counts bytes, like grep.
+	pub fn position(&self, offset: usize) -> (usize, usize) {
+		let line = self.starts.partition_point(|&start| start <= offset) - 1;
+		(line + 1, offset - self.starts[line] + 1)
+	}
+}
+
+impl<'t> Match<'t> {
+	/// Line and column the match starts at, see [LineIndex::position]
+	pub fn position(&self, index: &LineIndex) -> (usize, usize) {
+		index.position(self.range.start)
+	}
+}
@@ -0,0 +1,92 @@
+//! Counters for every search, to chart which patterns are hot and which waste time, with the `metrics` feature.
+//! Nothing is measured until a hook is set.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// One search, given to the hook from [set_metrics_hook]
+#[derive(Debug, Clone)]
+pub struct MatchMetrics<'p> {
+	/// Source of the pattern, which also identifies a [Registry](crate::Registry) entry through its [source](crate::bindings::CompiledPattern::source)
+	pub pattern: &'p [u8],
+	pub matched: bool,
+	/// Roughly how much work the matcher did, growing with every item it tries and retries while backtracking
+	pub steps: usize,
+	pub elapsed: Duration,
+}
+
+type Hook = Box<dyn Fn(&MatchMetrics) + Send + Sync>;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+thread_local! {
+	/// Steps of the search running on this thread
+	static STEPS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Calls `hook` after every search, including each one inside a gsub or an iterator, from any thread.
+/// Replaces the previous hook.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use std::sync::Mutex;
+///
+/// static CALLS: Mutex<Option<HashMap<Vec<u8>, (usize, usize)>>> = Mutex::new(None);
+///
+/// lupat::set_metrics_hook(|m| {
+///     let mut calls = CALLS.lock().unwrap();
+///     let entry = calls.get_or_insert_with(HashMap::new).entry(m.pattern.to_vec()).or_default();
+///     entry.0 += 1;
+///     entry.1 += m.matched as usize;
+/// });
+/// ```
+pub fn set_metrics_hook<F: Fn(&MatchMetrics) + Send + Sync + 'static>(hook: F) {
+	let mut slot = HOOK.write().unwrap_or_else(|e| e.into_inner());
+	*slot = Some(Box::new(hook));
+	ENABLED.store(true, Ordering::Release);
+}
+
+/// Removes the hook from [set_metrics_hook]
+pub fn clear_metrics_hook() {
+	let mut slot = HOOK.write().unwrap_or_else(|e| e.into_inner());
+	ENABLED.store(false, Ordering::Release);
+	*slot = None;
+}
+
+/// Adds the steps of a finished matcher run to the current search
+pub(crate) fn add_steps(steps: usize) {
+	STEPS.with(|total| total.set(total.get() + steps));
+}
+
+/// Measures a search, reporting it once it's finished
+pub(crate) struct Recorder<'p> {
+	started: Option<Instant>,
+	pattern: &'p [u8],
+}
+
+impl<'p> Recorder<'p> {
+	pub(crate) fn start(pattern: &'p [u8]) -> Self {
+		let started = if ENABLED.load(Ordering::Acquire) {
+			STEPS.with(|total| total.set(0));
+			Some(Instant::now())
+		} else {
+			None
+		};
+		Recorder { started, pattern }
+	}
+
+	pub(crate) fn finish(self, matched: bool) {
+		let elapsed = match self.started {
+			Some(started) => started.elapsed(),
+			None => return,
+		};
+		let steps = STEPS.with(Cell::get);
+		let slot = HOOK.read().unwrap_or_else(|e| e.into_inner());
+		if let Some(ref hook) = *slot {
+			hook(&MatchMetrics { pattern: self.pattern, matched, steps, elapsed });
+		}
+	}
+}
@@ -0,0 +1,69 @@
+use bindings::CompiledPattern;
+use std::cell::RefCell;
+use std::ops;
+use std::rc::Rc;
+use {char_range, Error};
+
+/// How many patterns each thread keeps compiled for the one-shot functions
+const CACHE_SIZE: usize = 16;
+
+thread_local! {
+	/// Most recently used last
+	static CACHE: RefCell<Vec<Rc<CompiledPattern>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// `pattern` compiled, from this thread's cache if it was used recently
+fn compiled(pattern: &str) -> Result<Rc<CompiledPattern>, Error> {
+	let cached = CACHE.with(|cache| {
+		let mut cache = cache.borrow_mut();
+		let i = cache.iter().position(|patt| patt.source() == pattern)?;
+		let patt = cache.remove(i);
+		cache.push(patt.clone());
+		Some(patt)
+	});
+	if let Some(patt) = cached {
+		return Ok(patt);
+	}
+	let patt = Rc::new(CompiledPattern::new(pattern)?);
+	CACHE.with(|cache| {
+		let mut cache = cache.borrow_mut();
+		if cache.len() == CACHE_SIZE {
+			cache.remove(0);
+		}
+		cache.push(patt.clone());
+	});
+	Ok(patt)
+}
+
+/// Whether `pattern` matches anywhere in `text`, without keeping a [Pattern](crate::Pattern) around.
+/// The last few patterns each thread used stay compiled, along with their precomputed sets,
+/// so calling this in a loop costs about as much as reusing a compiled pattern.
+///
+/// ```
+/// assert_eq!(lupat::is_match("^%d+$", "123"), Ok(true));
+/// assert!(lupat::is_match("(", "").is_err());
+/// ```
+pub fn is_match(pattern: &str, text: &str) -> Result<bool, Error> {
+	compiled(pattern)?.pattern().try_matches(text)
+}
+
+/// Where the first match of `pattern` is in `text`, cached like [is_match]
+///
+/// ```
+/// assert_eq!(lupat::find("%d+", "ab 123"), Ok(Some(3..6)));
+/// ```
+pub fn find(pattern: &str, text: &str) -> Result<Option<ops::Range<usize>>, Error> {
+	let patt = compiled(pattern)?;
+	let mut patt = patt.pattern();
+	Ok(if patt.try_matches(text)? { Some(char_range(text, patt.range())) } else { None })
+}
+
+/// [Pattern::gsub](crate::Pattern::gsub) with `pattern` cached like [is_match]
+///
+/// ```
+/// assert_eq!(lupat::gsub("(%w+)=(%w+)", "a=1, b=2", "%2=%1").unwrap(), "1=a, 2=b");
+/// ```
+#[cfg(feature = "gsub")]
+pub fn gsub(pattern: &str, text: &str, repl: &str) -> Result<String, Error> {
+	compiled(pattern)?.pattern().gsub(text, repl)
+}
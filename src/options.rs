@@ -0,0 +1,98 @@
+use pattern::{Flags, LuaVersion, LUA_MAXCAPTURES};
+use std::num::NonZeroU16;
+use {Error, Pattern};
+
+/// Options for compiling a [Pattern], from [Pattern::builder]
+///
+/// ```
+/// use lupat::{LuaVersion, Pattern};
+///
+/// let mut patt = Pattern::builder("^select (%w+)")
+///     .case_insensitive(true)
+///     .version(LuaVersion::Lua53)
+///     .captures::<2>()
+///     .build()
+///     .unwrap();
+/// assert_eq!(patt.match_maybe("SELECT name"), Some("name"));
+/// ```
+#[derive(Clone, Copy)]
+pub struct PatternOptions<'a, const MAXCAPTURES: usize = LUA_MAXCAPTURES> {
+	pub(crate) src: &'a [u8],
+	pub(crate) flags: Flags,
+}
+
+impl<'a> Pattern<'a> {
+	/// Starts building a pattern with options, with room for [LUA_MAXCAPTURES] captures unless changed
+	pub fn builder<S: AsRef<[u8]> + ?Sized>(src: &'a S) -> PatternOptions<'a> {
+		PatternOptions {
+			src: src.as_ref(),
+			flags: Flags::default(),
+		}
+	}
+}
+
+impl<'a, const MAXCAPTURES: usize> PatternOptions<'a, MAXCAPTURES> {
+	/// Letters match either case, only for ascii
+	pub fn case_insensitive(mut self, enabled: bool) -> Self {
+		self.flags.case_insensitive = enabled;
+		self
+	}
+
+	/// See [Pattern::multiline]
+	pub fn multiline(mut self, enabled: bool) -> Self {
+		self.flags.multiline = enabled;
+		self
+	}
+
+	/// Finds the longest match at each start like POSIX, instead of the first one backtracking gets to.
+	/// This tries every way the pattern can match, which can be a lot slower.
+	pub fn longest(mut self, enabled: bool) -> Self {
+		self.flags.longest = enabled;
+		self
+	}
+
+	/// Which Lua to follow where they differ, the latest by default
+	pub fn version(mut self, version: LuaVersion) -> Self {
+		self.flags.version = version;
+		self
+	}
+
+	/// How deep matching can recurse before failing with [Error::TooComplex], 200 by default like Lua
+	pub fn max_depth(mut self, depth: u16) -> Self {
+		self.flags.max_depth = NonZeroU16::new(depth.max(1));
+		self
+	}
+
+	/// Uses `esc` instead of `%` for escapes and classes, like `\d` for digits with `\\`.
+	/// It shouldn't be one of the other special characters `^$*+?.()[]-`.
+	#[cfg(feature = "extensions")]
+	pub fn escape(mut self, esc: u8) -> Self {
+		self.flags.escape = esc;
+		self
+	}
+
+	/// `.` matches a whole extended grapheme cluster instead of a byte, so emoji and accented letters aren't split.
+	/// Everything else still matches bytes.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt = Pattern::builder("^.$").graphemes(true).build().unwrap();
+	/// assert!(patt.matches("e\u{301}"));
+	/// assert!(patt.matches("👍🏽"));
+	/// ```
+	#[cfg(feature = "segmentation")]
+	pub fn graphemes(mut self, enabled: bool) -> Self {
+		self.flags.graphemes = enabled;
+		self
+	}
+
+	/// Room for `M` captures, counting the whole match
+	pub fn captures<const M: usize>(self) -> PatternOptions<'a, M> {
+		PatternOptions { src: self.src, flags: self.flags }
+	}
+
+	pub fn build(self) -> Result<Pattern<'a, MAXCAPTURES>, Error> {
+		Pattern::with_flags(self.src, self.flags)
+	}
+}
@@ -0,0 +1,136 @@
+use std::ops;
+use {Captures, Match};
+
+/// A match that owns its text, see [Match]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MatchOwned {
+	/// The matched text
+	pub text: String,
+	/// Where it was in the searched text
+	pub range: ops::Range<usize>,
+}
+
+impl MatchOwned {
+	pub fn as_str(&self) -> &str {
+		&self.text
+	}
+}
+
+impl<'t> From<Match<'t>> for MatchOwned {
+	fn from(m: Match<'t>) -> Self {
+		MatchOwned { text: m.as_str().to_owned(), range: m.range() }
+	}
+}
+
+/// The whole match and every capture, owning their text. Unlike [Captures] it doesn't borrow the pattern or the text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct CapturesOwned {
+	/// The whole match first, then the captures in order
+	pub groups: Vec<MatchOwned>,
+}
+
+impl CapturesOwned {
+	/// Text of a capture, the whole match being 0
+	pub fn get(&self, i: usize) -> Option<&str> {
+		self.groups.get(i).map(MatchOwned::as_str)
+	}
+
+	pub fn range(&self, i: usize) -> Option<ops::Range<usize>> {
+		self.groups.get(i).map(|m| m.range.clone())
+	}
+
+	/// Number of groups, counting the whole match
+	pub fn len(&self) -> usize {
+		self.groups.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.groups.is_empty()
+	}
+}
+
+impl<'a, 'b, 'c, const MAXCAPTURES: usize> From<Captures<'a, 'b, 'c, MAXCAPTURES>> for CapturesOwned {
+	fn from(captures: Captures<'a, 'b, 'c, MAXCAPTURES>) -> Self {
+		let groups = (0..captures.num_matches())
+			.filter_map(|i| {
+				let range = captures.m.try_capture(i)?;
+				let text = captures.try_get(i)?.to_owned();
+				Some(MatchOwned { text, range })
+			})
+			.collect();
+		CapturesOwned { groups }
+	}
+}
+
+#[cfg(feature = "serde")]
+mod serde_impls {
+	use super::{CapturesOwned, MatchOwned};
+	use serde_core::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+	use serde_core::ser::{Serialize, SerializeStruct, Serializer};
+	use std::fmt;
+
+	const FIELDS: &[&str] = &["text", "start", "end"];
+
+	/// As `{ text, start, end }`
+	impl Serialize for MatchOwned {
+		fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+			let mut state = serializer.serialize_struct("MatchOwned", 3)?;
+			state.serialize_field("text", &self.text)?;
+			state.serialize_field("start", &self.range.start)?;
+			state.serialize_field("end", &self.range.end)?;
+			state.end()
+		}
+	}
+
+	impl<'de> Deserialize<'de> for MatchOwned {
+		fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+			deserializer.deserialize_struct("MatchOwned", FIELDS, MatchVisitor)
+		}
+	}
+
+	struct MatchVisitor;
+
+	impl<'de> Visitor<'de> for MatchVisitor {
+		type Value = MatchOwned;
+
+		fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.write_str("a match with text, start and end")
+		}
+
+		fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<MatchOwned, A::Error> {
+			let text = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+			let start = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+			let end = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(2, &self))?;
+			Ok(MatchOwned { text, range: start..end })
+		}
+
+		fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<MatchOwned, A::Error> {
+			let (mut text, mut start, mut end) = (None, None, None);
+			while let Some(key) = map.next_key::<String>()? {
+				match key.as_str() {
+					"text" => text = Some(map.next_value()?),
+					"start" => start = Some(map.next_value()?),
+					"end" => end = Some(map.next_value()?),
+					other => return Err(de::Error::unknown_field(other, FIELDS)),
+				}
+			}
+			let text = text.ok_or_else(|| de::Error::missing_field("text"))?;
+			let start = start.ok_or_else(|| de::Error::missing_field("start"))?;
+			let end = end.ok_or_else(|| de::Error::missing_field("end"))?;
+			Ok(MatchOwned { text, range: start..end })
+		}
+	}
+
+	/// As a list of the groups
+	impl Serialize for CapturesOwned {
+		fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+			self.groups.serialize(serializer)
+		}
+	}
+
+	impl<'de> Deserialize<'de> for CapturesOwned {
+		fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+			Vec::deserialize(deserializer).map(|groups| CapturesOwned { groups })
+		}
+	}
+}
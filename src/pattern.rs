@@ -1,42 +1,129 @@
 // Translation of Lua 5.2 string pattern code
 use error::Error;
-use std::ptr::null;
+use std::collections::VecDeque;
+use tables;
+#[cfg(feature = "segmentation")]
+use graphemes::cluster_len;
 
 pub const LUA_MAXCAPTURES: usize = 32;
 const MAXCCALLS: usize = 200;
 const L_ESC: u8 = b'%';
 
-type CPtr = *const u8;
+/// A code unit of the text being matched against.
+/// Patterns are always bytes, and get compared against units by value.
+pub trait Unit: Copy {
+	fn to_u32(self) -> u32;
 
-#[inline(always)]
-fn add(p: CPtr, count: usize) -> CPtr {
-	unsafe { p.add(count) }
+	/// The char starting at `at` and how many units it takes, anything invalid being U+FFFD of one unit
+	#[cfg(feature = "segmentation")]
+	fn decode<H: Haystack<Unit = Self> + ?Sized>(units: &H, at: usize) -> Option<(char, usize)>;
 }
 
-#[inline(always)]
-fn sub(p: CPtr, count: usize) -> CPtr {
-	unsafe { p.sub(count) }
+/// Text the matcher can search, as units by position.
+/// Implemented for slices, pairs of slices and [VecDeque], so ring buffers can be searched across the wrap point without copying.
+pub trait Haystack {
+	type Unit: Unit;
+
+	/// Length in units
+	fn len(&self) -> usize;
+
+	/// The unit at `i`, `None` past the end
+	fn unit_at(&self, i: usize) -> Option<Self::Unit>;
+
+	fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
 }
 
-#[inline(always)]
-fn next(p: CPtr) -> CPtr {
-	add(p, 1)
+impl<T: Unit> Haystack for [T] {
+	type Unit = T;
+
+	#[inline(always)]
+	fn len(&self) -> usize {
+		<[T]>::len(self)
+	}
+
+	#[inline(always)]
+	fn unit_at(&self, i: usize) -> Option<T> {
+		self.get(i).copied()
+	}
 }
 
-#[inline(always)]
-const fn at(p: CPtr) -> u8 {
-	unsafe { *p }
+/// The first slice followed by the second, like [VecDeque::as_slices]
+impl<'a, T: Unit> Haystack for (&'a [T], &'a [T]) {
+	type Unit = T;
+
+	fn len(&self) -> usize {
+		self.0.len() + self.1.len()
+	}
+
+	#[inline]
+	fn unit_at(&self, i: usize) -> Option<T> {
+		match self.0.get(i) {
+			Some(&c) => Some(c),
+			None => self.1.get(i - self.0.len()).copied(),
+		}
+	}
 }
 
-#[inline(always)]
-fn diff(p1: CPtr, p2: CPtr) -> usize {
-	let d = (p1 as isize).wrapping_sub(p2 as isize);
-	d as usize
+impl<T: Unit> Haystack for VecDeque<T> {
+	type Unit = T;
+
+	fn len(&self) -> usize {
+		VecDeque::len(self)
+	}
+
+	#[inline]
+	fn unit_at(&self, i: usize) -> Option<T> {
+		self.get(i).copied()
+	}
+}
+
+impl Unit for u8 {
+	#[inline(always)]
+	fn to_u32(self) -> u32 {
+		self as u32
+	}
+
+	#[cfg(feature = "segmentation")]
+	fn decode<H: Haystack<Unit = u8> + ?Sized>(units: &H, at: usize) -> Option<(char, usize)> {
+		let len = match units.unit_at(at)? {
+			0xF0.. => 4,
+			0xE0.. => 3,
+			0xC0.. => 2,
+			_ => 1,
+		};
+		let mut buf = [0; 4];
+		let complete = (0..len).all(|i| units.unit_at(at + i).map(|c| buf[i] = c).is_some());
+		match std::str::from_utf8(&buf[..len]).ok().filter(|_| complete) {
+			Some(c) => c.chars().next().map(|c| (c, len)),
+			None => Some((std::char::REPLACEMENT_CHARACTER, 1)),
+		}
+	}
+}
+
+#[cfg(feature = "utf16")]
+impl Unit for u16 {
+	#[inline(always)]
+	fn to_u32(self) -> u32 {
+		self as u32
+	}
+
+	#[cfg(feature = "segmentation")]
+	fn decode<H: Haystack<Unit = u16> + ?Sized>(units: &H, at: usize) -> Option<(char, usize)> {
+		let c = char::decode_utf16((at..at + 2).map_while(|i| units.unit_at(i))).next()?;
+		Some(c.map_or((std::char::REPLACEMENT_CHARACTER, 1), |c| (c, c.len_utf16())))
+	}
 }
 
-#[derive(Copy, Clone, Debug)]
+/// A raw match slot, see [Pattern::raw_matches](crate::Pattern::raw_matches).
+/// Laid out like a C `struct { size_t start; size_t end; }`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
 pub struct LuaMatch {
+	/// Offset of the first byte (or unit) of the capture
 	pub start: usize,
+	/// Offset just past the capture, equal to `start` for empty and position captures
 	pub end: usize,
 }
 
@@ -60,9 +147,12 @@ impl CapLen {
 	}
 }
 
+/// Positions are indices into the source and pattern, where C would use pointers
+pub(crate) type Pos = usize;
+
 #[derive(Copy, Clone)]
 struct Capture {
-	init: CPtr,
+	init: Pos,
 	len: CapLen,
 }
 
@@ -72,30 +162,103 @@ impl Capture {
 	}
 }
 
+use std::num::NonZeroU16;
 use std::result;
 
 type Result<T> = result::Result<T, Error>;
 
-struct MatchState<const MAXCAPTURES: usize = LUA_MAXCAPTURES> {
+/// Which Lua's behavior to follow where they differ.
+/// `%g` is new in 5.2 (a plain `g` before), and before 5.4 gsub and gmatch can match empty right after the previous match.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LuaVersion {
+	Lua51,
+	Lua52,
+	Lua53,
+	#[default]
+	Lua54,
+}
+
+/// Opt-in behavior that differs from stock Lua
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Flags {
+	/// `^` and `$` also anchor at line boundaries
+	pub multiline: bool,
+	/// Letters match either case, ascii only
+	pub case_insensitive: bool,
+	/// Keep backtracking for the longest match at each start, like POSIX
+	pub longest: bool,
+	pub version: LuaVersion,
+	/// Recursion limit, [MAXCCALLS] if unset
+	pub max_depth: Option<NonZeroU16>,
+	/// Starts escapes and classes, only changeable from `%` with the `extensions` feature
+	pub escape: u8,
+	/// `.` matches a whole grapheme cluster, with the `segmentation` feature
+	#[cfg(feature = "segmentation")]
+	pub graphemes: bool,
+}
+
+impl Flags {
+	pub const DEFAULT: Flags = Flags {
+		multiline: false,
+		case_insensitive: false,
+		longest: false,
+		version: LuaVersion::Lua54,
+		max_depth: None,
+		escape: L_ESC,
+		#[cfg(feature = "segmentation")]
+		graphemes: false,
+	};
+}
+
+impl Default for Flags {
+	fn default() -> Self {
+		Flags::DEFAULT
+	}
+}
+
+struct MatchState<'s, 'p, H: ?Sized, const MAXCAPTURES: usize = LUA_MAXCAPTURES> {
 	matchdepth: usize, /* control for recursive depth (to avoid stack overflow) */
-	src_init: CPtr,    /* init of source string */
-	src_end: CPtr,     /* end ('\0') of source string */
-	p_end: CPtr,       /* end ('\0') of pattern */
+	flags: Flags,
+	src: &'s H,        /* source string, its length is the end */
+	pat: &'p [u8],     /* pattern, its length is the end */
+	sets: &'p [SetBitmap], /* precomputed sets of the pattern, if any */
 	level: usize,      /* total number of captures (finished or unfinished) */
 	capture: [Capture; MAXCAPTURES],
+	furthest: (Pos, Pos), /* furthest source and pattern positions reached */
+	longest: Option<(Pos, usize, [Capture; MAXCAPTURES])>, /* longest match so far in longest mode, with its captures */
+	#[cfg(feature = "metrics")]
+	steps: usize, /* calls to patt_match, reported when dropped */
 }
 
-impl<const MAXCAPTURES: usize> MatchState<MAXCAPTURES> {
-	const fn new(s: CPtr, se: CPtr, pe: CPtr) -> Self {
+#[cfg(feature = "metrics")]
+impl<'s, 'p, H: ?Sized, const MAXCAPTURES: usize> Drop for MatchState<'s, 'p, H, MAXCAPTURES> {
+	fn drop(&mut self) {
+		::metrics::add_steps(self.steps);
+	}
+}
+
+/// Pattern byte at `p`, or `'\0'` past the end like the C strings this was written for
+#[inline(always)]
+fn byte_at(pat: &[u8], p: Pos) -> u8 {
+	pat.get(p).copied().unwrap_or(0)
+}
+
+impl<'s, 'p, H: Haystack + ?Sized, const MAXCAPTURES: usize> MatchState<'s, 'p, H, MAXCAPTURES> {
+	const fn new(src: &'s H, pat: &'p [u8]) -> Self {
 		Self {
 			matchdepth: MAXCCALLS,
-			src_init: s,
-			src_end: se,
-			p_end: pe,
+			flags: Flags::DEFAULT,
+			src,
+			pat,
+			sets: &[],
 			level: 0,
+			furthest: (0, 0),
+			longest: None,
+			#[cfg(feature = "metrics")]
+			steps: 0,
 			capture: [
 				Capture {
-					init: null(),
+					init: 0,
 					len: CapLen::Len(0),
 				};
 				MAXCAPTURES
@@ -103,6 +266,17 @@ impl<const MAXCAPTURES: usize> MatchState<MAXCAPTURES> {
 		}
 	}
 
+	#[inline(always)]
+	fn p_at(&self, p: Pos) -> u8 {
+		byte_at(self.pat, p)
+	}
+
+	/// Source unit at `s`, or `'\0'` at the end
+	#[inline(always)]
+	fn s_at(&self, s: Pos) -> u32 {
+		self.src.unit_at(s).map_or(0, Unit::to_u32)
+	}
+
 	const fn check_capture(&self, l: usize) -> Result<usize> {
 		let l = l as i8 - b'1' as i8;
 		if l < 0 || l as usize >= self.level || self.capture[l as usize].is_unfinished() {
@@ -112,7 +286,7 @@ impl<const MAXCAPTURES: usize> MatchState<MAXCAPTURES> {
 	}
 
 	const fn capture_to_close(&self) -> Result<usize> {
-		let mut level = (self.level - 1) as isize;
+		let mut level = self.level as isize - 1;
 		while level >= 0 {
 			if self.capture[level as usize].is_unfinished() {
 				return Ok(level as usize);
@@ -122,51 +296,70 @@ impl<const MAXCAPTURES: usize> MatchState<MAXCAPTURES> {
 		Err( Error::InvalidCapture(None) )
 	}
 
-	fn classend(&self, p: CPtr) -> Result<CPtr> {
-		let ch = at(p);
-		let mut next_p = next(p);
+	fn classend(&self, p: Pos) -> Result<Pos> {
+		let p_end = self.pat.len();
+		let ch = self.p_at(p);
+		let mut next_p = p + 1;
 		Ok(match ch {
-			L_ESC => {
-				if next_p == self.p_end {
-					return Err( Error::EndsWithPercent );
+			c if c == self.flags.escape => {
+				if next_p >= p_end {
+					return Err( Error::ends_with_escape(c) );
 				}
-				next(next_p)
+				next_p + 1
 			}
 			b'[' => {
-				if at(next_p) == b'^' {
-					next_p = next(next_p);
+				if self.p_at(next_p) == b'^' {
+					next_p += 1;
 				}
-				while at(next_p) != b']' {
-					if next_p == self.p_end {
+				/* look for a `]', the first character is always part of the set (e.g. `[]]') */
+				loop {
+					if next_p >= p_end {
 						return Err( Error::MissingEndBracket );
 					}
-					let ch = at(next_p);
-					next_p = next(next_p);
-					if ch == L_ESC && p < self.p_end {
-						next_p = next(next_p); /* skip escapes (e.g. `%]') */
+					let ch = self.p_at(next_p);
+					next_p += 1;
+					if ch == self.flags.escape && next_p < p_end {
+						next_p += 1; /* skip escapes (e.g. `%]') */
+					}
+					if self.p_at(next_p) == b']' {
+						break;
 					}
 				}
-				next(next_p)
+				next_p + 1
 			}
 			_ => next_p,
 		})
 	}
 }
 
-fn match_class(ch: u8, class: u8) -> bool {
-	let res = match class.to_ascii_lowercase() {
-		b'a' => ch.is_ascii_alphabetic(),
-		b'c' => ch.is_ascii_control(),
-		b'd' => ch.is_ascii_digit(),
-		b'g' => ch.is_ascii_graphic(),
-		b'l' => ch.is_ascii_lowercase(),
-		b'p' => ch.is_ascii_punctuation(),
-		b's' => ch.is_ascii_whitespace(),
-		b'u' => ch.is_ascii_uppercase(),
-		b'w' => ch.is_ascii_alphanumeric(),
-		b'x' => ch.is_ascii_hexdigit(),
-		lc => return lc == ch,
+/// The other case of an ascii letter
+fn other_case(c: u32) -> Option<u32> {
+	if c < 0x80 && (c as u8).is_ascii_alphabetic() {
+		Some(c ^ 0x20)
+	} else {
+		None
+	}
+}
+
+fn same_char(c: u32, pc: u8, flags: Flags) -> bool {
+	same_unit(c, pc as u32, flags)
+}
+
+fn same_unit(a: u32, b: u32, flags: Flags) -> bool {
+	a == b || (flags.case_insensitive && other_case(a) == Some(b))
+}
+
+fn match_class(ch: u32, class: u8, flags: Flags) -> bool {
+	let table = match tables::for_class(class) {
+		Some(_) if flags.version == LuaVersion::Lua51 && class.eq_ignore_ascii_case(&b'g') => None,
+		table => table,
+	};
+	let Some(table) = table else {
+		return same_char(ch, class, flags);
 	};
+	// Classes only ever match ascii, which 0xFF is not
+	let in_table = |c: u32| table[c.min(0xFF) as usize];
+	let res = in_table(ch) || (flags.case_insensitive && other_case(ch).is_some_and(in_table));
 	if class.is_ascii_lowercase() {
 		res
 	} else {
@@ -174,111 +367,198 @@ fn match_class(ch: u8, class: u8) -> bool {
 	}
 }
 
-fn matchbracketclass(c: u8, p: CPtr, ec: CPtr) -> bool {
+/// Whether `c` is in the set starting with the `[` at `p` and closed by the `]` at `ec`
+pub(crate) fn matchbracketclass(c: u32, pat: &[u8], p: Pos, ec: Pos, flags: Flags) -> bool {
 	let mut p = p;
 	// [^ inverts match
-	let sig = if at(next(p)) == b'^' {
-		p = next(p);
+	let sig = if byte_at(pat, p + 1) == b'^' {
+		p += 1;
 		false
 	} else {
 		true
 	};
-	p = next(p);
+	p += 1;
 	while p < ec {
-		if at(p) == L_ESC {
+		if byte_at(pat, p) == flags.escape {
 			// e.g %s
-			p = next(p);
-			if match_class(c, at(p)) {
+			p += 1;
+			if match_class(c, byte_at(pat, p), flags) {
 				return sig;
 			}
-		} else if at(next(p)) == b'-' && add(p, 2) < ec {
-			let lastc = at(p);
-			p = add(p, 2);
-			if lastc <= c && c <= at(p) {
+		} else if byte_at(pat, p + 1) == b'-' && p + 2 < ec {
+			let lastc = byte_at(pat, p) as u32;
+			p += 2;
+			let in_range = |c: u32| lastc <= c && c <= byte_at(pat, p) as u32;
+			if in_range(c) || (flags.case_insensitive && other_case(c).is_some_and(in_range)) {
 				return sig;
 			}
-		} else if at(p) == c {
+		} else if same_char(c, byte_at(pat, p), flags) {
 			return sig;
 		}
-		p = next(p);
+		p += 1;
 	}
 
 	!sig
 }
 
-impl<const MAXCAPTURES: usize> MatchState<MAXCAPTURES> {
-	fn singlematch(&self, s: CPtr, p: CPtr, ep: CPtr) -> bool {
-		if s >= self.src_end {
+/// A `[...]` set of a pattern, worked out for every byte up front so matching doesn't rescan its syntax
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SetBitmap {
+	/// Position of the `[`, after any leading `^` of the pattern like the matcher sees it
+	at: Pos,
+	bits: [u64; 4],
+}
+
+impl SetBitmap {
+	fn contains(&self, c: u8) -> bool {
+		self.bits[(c >> 6) as usize] >> (c & 63) & 1 == 1
+	}
+}
+
+/// Precomputes every set in `pat`, a pattern that passed [str_check], as matched with `flags`
+pub(crate) fn set_bitmaps(pat: &[u8], flags: Flags) -> Vec<SetBitmap> {
+	let mut sets = Vec::new();
+	extend_set_bitmaps(pat, flags, &mut sets);
+	sets
+}
+
+/// Like [set_bitmaps], adding the sets to any collection
+pub(crate) fn extend_set_bitmaps<E: Extend<SetBitmap>>(pat: &[u8], flags: Flags, sets: &mut E) {
+	let pat = match pat.split_first() {
+		Some((b'^', rest)) => rest,
+		_ => pat,
+	};
+	let mut ms: MatchState<[u8], 1> = MatchState::new(&[], pat);
+	ms.flags = flags;
+	let mut p = 0;
+	while p < pat.len() {
+		let ch = pat[p];
+		if ch == flags.escape {
+			p += match byte_at(pat, p + 1) {
+				#[cfg(feature = "extensions")]
+				b'b' if balance_seq(&pat[p + 2..]).is_some() => 2 + balance_seq(&pat[p + 2..]).map_or(0, |(_, _, len)| len),
+				b'b' => 4, /* the delimiters aren't a set, even if one is '[' */
+				_ => 2,    /* a set after %f is picked up next */
+			};
+		} else if ch == b'[' {
+			let Ok(ep) = ms.classend(p) else { break };
+			let mut bits = [0u64; 4];
+			for c in 0..=255u8 {
+				if matchbracketclass(c as u32, pat, p, ep - 1, flags) {
+					bits[(c >> 6) as usize] |= 1 << (c & 63);
+				}
+			}
+			sets.extend(Some(SetBitmap { at: p, bits }));
+			p = ep;
+		} else {
+			p += 1;
+		}
+	}
+}
+
+impl<'s, 'p, H: Haystack + ?Sized, const MAXCAPTURES: usize> MatchState<'s, 'p, H, MAXCAPTURES> {
+	/// Whether `c` is in the set at `p`, ending just before `ep`, going by its bitmap if it has one
+	fn in_set(&self, c: u32, p: Pos, ep: Pos) -> bool {
+		if c < 0x100 {
+			if let Ok(i) = self.sets.binary_search_by_key(&p, |set| set.at) {
+				return self.sets[i].contains(c as u8);
+			}
+		}
+		matchbracketclass(c, self.pat, p, ep - 1, self.flags)
+	}
+
+	fn singlematch(&self, s: Pos, p: Pos, ep: Pos) -> bool {
+		if s >= self.src.len() {
 			return false;
 		}
-		let c = at(s);
-		let pc = at(p);
-		match pc {
+		let c = self.s_at(s);
+		match self.p_at(p) {
 			b'.' => true, /* matches any char */
-			L_ESC => match_class(c, at(next(p))),
-			b'[' => matchbracketclass(c, p, sub(ep, 1)),
-			_ => c == pc,
+			pc if pc == self.flags.escape => match_class(c, self.p_at(p + 1), self.flags),
+			b'[' => self.in_set(c, p, ep),
+			pc => same_char(c, pc, self.flags),
 		}
 	}
 
-	fn matchbalance(&self, s: CPtr, p: CPtr) -> Result<CPtr> {
-		if p >= sub(self.p_end, 1) {
-			return Err( Error::MissingBalanceArgs );
+	/// How many units the single item at `p` takes at `s`, once it matched there
+	fn single_len(&self, s: Pos, p: Pos) -> Pos {
+		#[cfg(feature = "segmentation")]
+		if self.flags.graphemes && self.p_at(p) == b'.' {
+			return cluster_len(self.src, s);
 		}
-		if at(s) != at(p) {
-			return Ok(null());
+		let _ = (s, p);
+		1
+	}
+
+	/// Matches the arguments of `%b` at `p`, returning where the match ends (if it did) and where the pattern continues
+	fn matchbalance(&self, s: Pos, p: Pos) -> Result<(Option<Pos>, Pos)> {
+		#[cfg(feature = "extensions")]
+		if let Some((open, close, len)) = balance_seq(&self.pat[p..]) {
+			// e.g. %b{<<}{>>}
+			return Ok((balance_seq_end(self.src, s, open, close), p + len));
 		}
-		// e.g. %b()
-		let b = at(p);
-		let e = at(next(p));
-		let mut cont = 1;
-		let mut s = next(s);
-		while s < self.src_end {
-			let ch = at(s);
-			if ch == e {
-				cont -= 1;
-				if cont == 0 {
-					return Ok(next(s));
-				}
-			} else if ch == b {
-				cont += 1;
-			}
-			s = next(s);
+		if p + 1 >= self.pat.len() {
+			return Err( Error::MissingBalanceArgs );
 		}
-		Ok(null()) /* string ends out of balance */
+		// e.g. %b()
+		Ok((balance_end(self.src, s, self.p_at(p) as u32, self.p_at(p + 1) as u32), p + 2))
 	}
 
-	fn max_expand(&mut self, s: CPtr, p: CPtr, ep: CPtr) -> Result<CPtr> {
-		let mut i = 0isize; /* counts maximum expand for item */
-		while self.singlematch(add(s, i as usize), p, ep) {
+	fn max_expand(&mut self, s: Pos, p: Pos, ep: Pos) -> Result<Option<Pos>> {
+		#[cfg(feature = "segmentation")]
+		if self.flags.graphemes && self.p_at(p) == b'.' {
+			return self.max_expand_clusters(s, ep);
+		}
+		let mut i = 0; /* counts maximum expand for item */
+		while self.singlematch(s + i, p, ep) {
 			i += 1;
 		}
 		/* keeps trying to match with the maximum repetitions */
-		while i >= 0 {
-			let res = self.patt_match(add(s, i as usize), next(ep))?;
-			if !res.is_null() {
+		loop {
+			let res = self.patt_match(s + i, ep + 1)?;
+			if res.is_some() {
 				return Ok(res);
 			}
+			if i == 0 {
+				return Ok(None);
+			}
 			i -= 1; /* else didn't match; reduce 1 repetition to try again */
 		}
-		Ok(null())
 	}
 
-	fn min_expand(&mut self, s: CPtr, p: CPtr, ep: CPtr) -> Result<CPtr> {
+	/// [MatchState::max_expand] for `.` over grapheme clusters, which aren't all one unit long
+	#[cfg(feature = "segmentation")]
+	fn max_expand_clusters(&mut self, s: Pos, ep: Pos) -> Result<Option<Pos>> {
+		let mut ends = vec![s];
+		let mut end = s;
+		while end < self.src.len() {
+			end += cluster_len(self.src, end);
+			ends.push(end);
+		}
+		for &end in ends.iter().rev() {
+			let res = self.patt_match(end, ep + 1)?;
+			if res.is_some() {
+				return Ok(res);
+			}
+		}
+		Ok(None)
+	}
+
+	fn min_expand(&mut self, s: Pos, p: Pos, ep: Pos) -> Result<Option<Pos>> {
 		let mut s = s;
 		loop {
-			let res = self.patt_match(s, next(ep))?;
-			if !res.is_null() {
+			let res = self.patt_match(s, ep + 1)?;
+			if res.is_some() {
 				return Ok(res);
 			} else if self.singlematch(s, p, ep) {
-				s = next(s);
+				s += self.single_len(s, p);
 			} else {
-				return Ok(null());
+				return Ok(None);
 			}
 		}
 	}
 
-	fn start_capture(&mut self, s: CPtr, p: CPtr, what: CapLen) -> Result<CPtr> {
+	fn start_capture(&mut self, s: Pos, p: Pos, what: CapLen) -> Result<Option<Pos>> {
 		let level = self.level;
 		if level >= MAXCAPTURES {
 			return Err(Error::TooManyCaptures);
@@ -287,172 +567,223 @@ impl<const MAXCAPTURES: usize> MatchState<MAXCAPTURES> {
 		self.capture[level].len = what;
 		self.level = level + 1;
 		let res = self.patt_match(s, p)?;
-		if res.is_null() {
+		if res.is_none() {
 			/* match failed? */
 			self.level -= 1; /* undo capture */
 		}
 		Ok(res)
 	}
 
-	fn end_capture(&mut self, s: CPtr, p: CPtr) -> Result<CPtr> {
+	fn end_capture(&mut self, s: Pos, p: Pos) -> Result<Option<Pos>> {
 		let l = self.capture_to_close()?;
-		self.capture[l].len = CapLen::Len(diff(s, self.capture[l].init)); /* close capture */
+		self.capture[l].len = CapLen::Len(s - self.capture[l].init); /* close capture */
 		let res = self.patt_match(s, p)?;
-		if res.is_null() {
+		if res.is_none() {
 			/* match failed? */
 			self.capture[l].len = CapLen::Unfinished;
 		}
 		Ok(res)
 	}
 
-	fn match_capture(&mut self, s: CPtr, l: usize) -> Result<CPtr> {
+	fn match_capture(&mut self, s: Pos, l: usize) -> Result<Option<Pos>> {
 		let l = self.check_capture(l)?;
 		let len = self.capture[l].len.size()?;
-		if diff(self.src_end, s) >= len {
-			unsafe {
-				s.copy_to_nonoverlapping(self.capture[l].init as *mut u8, len);
-			}
-			return Ok(add(s, len));
+		let init = self.capture[l].init;
+		if self.src.len() - s >= len && (0..len).all(|i| same_unit(self.s_at(init + i), self.s_at(s + i), self.flags)) {
+			return Ok(Some(s + len));
 		}
-		Ok(null())
+		Ok(None)
 	}
 
-	fn patt_match(&mut self, s: CPtr, p: CPtr) -> Result<CPtr> {
-		let mut s = s;
+	fn patt_match(&mut self, s: Pos, p: Pos) -> Result<Option<Pos>> {
+		let mut s = Some(s);
 		let mut p = p;
+		#[cfg(feature = "metrics")]
+		{
+			self.steps += 1;
+		}
 		self.matchdepth -= 1;
 		if self.matchdepth == 0 {
 			return Err( Error::TooComplex );
 		}
 
-		if p == self.p_end {
+		let p_end = self.pat.len();
+		let si = s.unwrap_or_default();
+		if (si, p) > self.furthest {
+			self.furthest = (si, p);
+		}
+		if p == p_end {
 			/* end of pattern? */
 			self.matchdepth += 1;
-			return Ok(s);
+			return Ok(self.finish(si));
 		}
-		match at(p) {
+		match self.p_at(p) {
 			b'(' => {
 				/* start capture */
-				if at(next(p)) == b')' {
+				if self.p_at(p + 1) == b')' {
 					/* position capture? */
-					s = self.start_capture(s, add(p, 2), CapLen::Position)?;
+					s = self.start_capture(si, p + 2, CapLen::Position)?;
 				} else {
-					s = self.start_capture(s, next(p), CapLen::Unfinished)?;
+					s = self.start_capture(si, p + 1, CapLen::Unfinished)?;
 				}
 			}
 			b')' => {
 				/* end capture */
-				s = self.end_capture(s, next(p))?;
+				s = self.end_capture(si, p + 1)?;
 			}
 			b'$' => {
-				if next(p) != self.p_end {
+				if p + 1 != p_end {
 					/* is the `$' the last char in pattern? */
 					/* no; go to default */
-					return self.patt_default_match(s, p);
+					return self.patt_default_match(si, p);
 				}
-				s = if s == self.src_end { s } else { null() }; /* check end of string */
+				/* check end of string (or line) */
+				s = if si == self.src.len() || (self.flags.multiline && self.s_at(si) == b'\n' as u32) {
+					self.finish(si)
+				} else {
+					None
+				};
 			}
-			L_ESC => {
+			c if c == self.flags.escape => {
 				/* escaped sequences not in the format class[*+?-]? */
-				match at(next(p)) {
+				match self.p_at(p + 1) {
 					b'b' => {
 						/* balanced string? */
-						s = self.matchbalance(s, add(p, 2))?;
-						if !s.is_null() {
+						let (end, next_p) = self.matchbalance(si, p + 2)?;
+						s = end;
+						if let Some(s) = s {
 							// e.g, after %b()
-							return self.patt_match(s, add(p, 4));
+							return self.patt_match(s, next_p);
 						}
 					}
 					b'f' => {
 						/* frontier? */
-						p = add(p, 2);
-						if at(p) != b'[' {
+						p += 2;
+						if self.p_at(p) != b'[' {
 							return Err( Error::MissingLBracketF );
 						}
 						let ep = self.classend(p)?; /* points to what is next */
-						let previous = if s == self.src_init {
-							b'\0'
-						} else {
-							at(sub(s, 1))
-						};
-						let epl = sub(ep, 1);
-						if !matchbracketclass(previous, p, epl) && matchbracketclass(at(s), p, epl)
-						{
-							return self.patt_match(s, ep);
+						let previous = if si == 0 { 0 } else { self.s_at(si - 1) };
+						let current = self.s_at(si); /* '\0' at the end */
+						if !self.in_set(previous, p, ep) && self.in_set(current, p, ep) {
+							return self.patt_match(si, ep);
 						}
-						s = null(); /* match failed */
+						s = None; /* match failed */
 					}
 					b'0'..=b'9' => {
 						/* capture results (%0-%9)? */
-						s = self.match_capture(s, at(next(p)) as usize)?;
-						if !s.is_null() {
-							return self.patt_match(s, add(p, 2));
+						s = self.match_capture(si, self.p_at(p + 1) as usize)?;
+						if let Some(s) = s {
+							return self.patt_match(s, p + 2);
 						}
 					}
-					_ => return self.patt_default_match(s, p),
+					_ => return self.patt_default_match(si, p),
 				}
 			}
-			_ => return self.patt_default_match(s, p),
+			_ => return self.patt_default_match(si, p),
 		}
 		self.matchdepth += 1;
 		Ok(s)
 	}
 
-	fn patt_default_match(&mut self, s: CPtr, p: CPtr) -> Result<CPtr> {
-		let mut s = s;
+	/// Longest match the pattern can have if it's anchored to the end of the source by a final `$`.
+	/// `None` if it isn't, or its length is unbounded
+	fn max_len_to_end(&self) -> Option<usize> {
+		#[cfg(feature = "segmentation")]
+		if self.flags.graphemes {
+			return None;
+		}
+		let p_end = self.pat.len();
+		if p_end == 0 || self.pat[p_end - 1] != b'$' {
+			return None;
+		}
+		let mut len = 0;
+		let mut p = 0;
+		while p < p_end - 1 {
+			match self.p_at(p) {
+				b'(' | b')' => p += 1,
+				c if c == self.flags.escape && matches!(self.p_at(p + 1), b'b' | b'0'..=b'9') => return None,
+				c if c == self.flags.escape && self.p_at(p + 1) == b'f' => p = self.classend(p + 2).ok()?,
+				_ => {
+					let ep = self.classend(p).ok()?;
+					len += 1;
+					p = match self.p_at(ep) {
+						b'*' | b'+' | b'-' => return None,
+						b'?' => ep + 1,
+						_ => ep,
+					};
+				}
+			}
+		}
+		/* otherwise the `$` was escaped, like `%$` */
+		if p == p_end - 1 { Some(len) } else { None }
+	}
+
+	/// Called with the end of a successful match. In longest mode this only remembers it and fails to keep backtracking
+	fn finish(&mut self, s: Pos) -> Option<Pos> {
+		if !self.flags.longest {
+			return Some(s);
+		}
+		if self.longest.is_none_or(|(end, ..)| s > end) {
+			self.longest = Some((s, self.level, self.capture));
+		}
+		None
+	}
+
+	fn patt_default_match(&mut self, s: Pos, p: Pos) -> Result<Option<Pos>> {
 		/* pattern class plus optional suffix */
 		let ep = self.classend(p)?; /* points to optional suffix */
+		let epc = self.p_at(ep);
+		let res;
 		/* does not match at least once? */
 		if !self.singlematch(s, p, ep) {
-			let epc = at(ep);
 			if epc == b'*' || epc == b'?' || epc == b'-' {
 				/* accept empty? */
-				return self.patt_match(s, next(ep));
+				return self.patt_match(s, ep + 1);
 			} else {
 				/* '+' or no suffix */
-				s = null(); /* fail */
+				res = None; /* fail */
 			}
 		} else {
 			/* matched once */
-			match at(ep) {
+			match epc {
 				/* handle optional suffix */
 				b'?' => {
-					let res = self.patt_match(next(s), next(ep))?;
-					if !res.is_null() {
-						s = res;
+					let r = self.patt_match(s + self.single_len(s, p), ep + 1)?;
+					if r.is_some() {
+						res = r;
 					} else {
-						return self.patt_match(s, next(ep));
+						return self.patt_match(s, ep + 1);
 					}
 				}
 				b'+' => {
 					/* 1 or more repetitions */
-					s = next(s);
-					s = self.max_expand(s, p, ep)?;
+					res = self.max_expand(s + self.single_len(s, p), p, ep)?;
 				}
 				b'*' => {
 					/* 0 or more repetitions */
-					s = self.max_expand(s, p, ep)?;
+					res = self.max_expand(s, p, ep)?;
 				}
 				b'-' => {
 					/* 0 or more repetitions (minimum) */
-					s = self.min_expand(s, p, ep)?;
+					res = self.min_expand(s, p, ep)?;
 				}
 				_ => {
 					/* no suffix */
-					return self.patt_match(next(s), ep);
+					return self.patt_match(s + self.single_len(s, p), ep);
 				}
 			}
 		}
 		self.matchdepth += 1;
-		Ok(s)
+		Ok(res)
 	}
 
-	fn push_onecapture(&mut self, i: usize, s: CPtr, e: CPtr, mm: &mut [LuaMatch]) -> Result<()> {
+	fn push_onecapture(&mut self, i: usize, s: Pos, e: Pos, mm: &mut [LuaMatch]) -> Result<()> {
 		if i >= self.level {
 			if i == 0 {
 				/* ms->level == 0, too */
-				mm[0].start = 0;
-				mm[0].end = diff(e, s);
+				mm[0].start = s;
+				mm[0].end = e;
 				Ok(())
 			} else {
 				Err( Error::InvalidCapture(None) )
@@ -462,55 +793,61 @@ impl<const MAXCAPTURES: usize> MatchState<MAXCAPTURES> {
 			match self.capture[i].len {
 				CapLen::Unfinished => Err( Error::UnfinishedCapture ),
 				CapLen::Position => {
-					mm[i].start = diff(init, next(self.src_init));
-					mm[i].end = mm[i].start;
+					/* an empty range where the position was */
+					mm[i].start = init;
+					mm[i].end = init;
 					Ok(())
 				}
 				CapLen::Len(l) => {
-					mm[i].start = diff(init, self.src_init);
-					mm[i].end = mm[i].start + l;
+					mm[i].start = init;
+					mm[i].end = init + l;
 					Ok(())
 				}
 			}
 		}
 	}
 
-	fn push_captures(&mut self, s: CPtr, e: CPtr, mm: &mut [LuaMatch]) -> Result<usize> {
-		let nlevels = if self.level == 0 && !s.is_null() {
+	fn push_captures(&mut self, s: Option<Pos>, e: Pos, mm: &mut [LuaMatch]) -> Result<usize> {
+		let nlevels = if self.level == 0 && s.is_some() {
 			1
 		} else {
 			self.level
 		};
 		for i in 0..nlevels {
-			self.push_onecapture(i, s, e, mm)?;
+			self.push_onecapture(i, s.unwrap_or_default(), e, mm)?;
 		}
 		Ok(nlevels) /* number of strings pushed */
 	}
 
-	pub fn str_match_check(&mut self, p: CPtr) -> Result<()> {
+	pub fn str_match_check(&mut self) -> Result<()> {
 		let mut level_stack = [0; MAXCAPTURES];
 		let mut stack_idx = 0;
-		let mut p = p;
-		while p < self.p_end {
-			let ch = at(p);
-			p = next(p);
+		let p_end = self.pat.len();
+		let mut p = 0;
+		while p < p_end {
+			let ch = self.p_at(p);
+			p += 1;
 			match ch {
-				L_ESC => {
-					//p = next(p);
-					let c = at(p);
+				c if c == self.flags.escape => {
+					if p >= p_end {
+						return Err( Error::ends_with_escape(c) );
+					}
+					let c = self.p_at(p);
+					p += 1; /* skip the escaped character */
 					match c {
+						#[cfg(feature = "extensions")]
+						b'b' if balance_seq(&self.pat[p..]).is_some() => {
+							p += balance_seq(&self.pat[p..]).map_or(0, |(_, _, len)| len);
+						}
 						b'b' => {
-							p = next(p);
-							if p >= self.p_end {
+							if p + 1 >= p_end {
 								return Err( Error::MissingBalanceArgs );
 							}
+							p += 2; /* skip the delimiters */
 						}
-						b'f' => {
-							p = next(p);
-							if at(p) != b'[' {
-								return Err( Error::MissingLBracketF );
-							}
-							p = sub(p, 1); // so we see [...]
+						/* otherwise p is left on the '[' so the set gets checked next */
+						b'f' if p >= p_end || self.p_at(p) != b'[' => {
+							return Err( Error::MissingLBracketF );
 						}
 						b'0'..=b'9' => {
 							let l = (c as i8) - (b'1' as i8);
@@ -520,35 +857,45 @@ impl<const MAXCAPTURES: usize> MatchState<MAXCAPTURES> {
 							{
 								return Err( Error::InvalidCapture( Some(l + 1) ) );
 							}
-							p = sub(p, 1);
 						}
 						_ => {}
 					}
 				}
 				b'[' => {
-					while at(p) != b']' {
-						if p == self.p_end {
+					if self.p_at(p) == b'^' {
+						p += 1;
+					}
+					// Like `classend`, a `]` right after the `[` or `[^` is a literal
+					loop {
+						if p >= p_end {
 							return Err( Error::MissingEndBracket );
 						}
-						if at(p) == L_ESC && p < self.p_end {
-							p = next(p);
+						let c = self.p_at(p);
+						p += 1;
+						if c == self.flags.escape && p < p_end {
+							p += 1;
+						}
+						if self.p_at(p) == b']' {
+							break;
 						}
-						p = next(p);
 					}
+					p += 1;
 				}
 				b'(' => {
-					if at(p) != b')' {
+					/* position captures take a slot too, and the whole match needs one */
+					if self.level + 1 >= MAXCAPTURES {
+						return Err( Error::TooManyCaptures );
+					}
+					if self.p_at(p) != b')' {
 						// not a position capture
 						level_stack[stack_idx] = self.level;
 						stack_idx += 1;
 						self.capture[self.level].len = CapLen::Unfinished;
-						self.level += 1;
-						if self.level >= MAXCAPTURES {
-							return Err( Error::TooManyCaptures );
-						}
 					} else {
-						p = next(p);
+						self.capture[self.level].len = CapLen::Position;
+						p += 1;
 					}
+					self.level += 1;
 				}
 				b')' => {
 					if stack_idx == 0 {
@@ -567,47 +914,151 @@ impl<const MAXCAPTURES: usize> MatchState<MAXCAPTURES> {
 	}
 }
 
-pub fn str_match<const MAXCAPTURES: usize>(s: &[u8], p: &[u8], mm: &mut [LuaMatch]) -> Result<usize> {
-	let mut lp = p.len();
-	let mut p = p.as_ptr();
+/// End of the balanced span starting at `s`, which has to be `b`, closed by `e`
+pub fn balance_end<H: Haystack + ?Sized>(src: &H, s: Pos, b: u32, e: u32) -> Option<Pos> {
+	if src.unit_at(s)?.to_u32() != b {
+		return None;
+	}
+	let mut cont = 1;
+	for i in s + 1..src.len() {
+		let ch = src.unit_at(i).map_or(0, Unit::to_u32);
+		if ch == e {
+			cont -= 1;
+			if cont == 0 {
+				return Some(i + 1);
+			}
+		} else if ch == b {
+			cont += 1;
+		}
+	}
+	None /* string ends out of balance */
+}
+
+/// Arguments of `%b` using the `{open}{close}` extension, from the pattern right after the `%b`.
+/// Both have to be non-empty and can't contain `}`, so stock patterns like `%b{}` keep their meaning.
+/// Returns them with the length of the arguments.
+#[cfg(feature = "extensions")]
+pub fn balance_seq(p: &[u8]) -> Option<(&[u8], &[u8], usize)> {
+	let rest = p.strip_prefix(b"{")?;
+	let open_len = rest.iter().position(|&c| c == b'}')?;
+	let (open, rest) = rest.split_at(open_len);
+	let rest = rest.strip_prefix(b"}{")?;
+	let close_len = rest.iter().position(|&c| c == b'}')?;
+	if open.is_empty() || close_len == 0 {
+		return None;
+	}
+	Some((open, &rest[..close_len], open_len + close_len + 4))
+}
+
+/// Like [balance_end], with sequences for delimiters
+#[cfg(feature = "extensions")]
+fn balance_seq_end<H: Haystack + ?Sized>(src: &H, s: Pos, open: &[u8], close: &[u8]) -> Option<Pos> {
+	let starts_with = |i: Pos, seq: &[u8]| seq.iter().enumerate().all(|(j, &c)| src.unit_at(i + j).is_some_and(|u| u.to_u32() == c as u32));
+	if !starts_with(s, open) {
+		return None;
+	}
+	let mut cont = 1;
+	let mut i = s + open.len();
+	while i < src.len() {
+		if starts_with(i, close) {
+			cont -= 1;
+			i += close.len();
+			if cont == 0 {
+				return Some(i);
+			}
+		} else if starts_with(i, open) {
+			cont += 1;
+			i += open.len();
+		} else {
+			i += 1;
+		}
+	}
+	None /* string ends out of balance */
+}
+
+/// Finds the first match of `p` in `s` at or after `init`, storing the ranges in `mm` and returning how many were filled.
+/// `anchor` forces the match to start at `init`, as if the pattern began with `^`
+pub fn str_match_with<H: Haystack + ?Sized, const MAXCAPTURES: usize>(s: &H, p: &[u8], mm: &mut [LuaMatch], init: usize, anchor: bool, flags: Flags, sets: &[SetBitmap]) -> Result<usize> {
+	str_match_traced::<H, MAXCAPTURES>(s, p, mm, init, anchor, flags, sets, None)
+}
+
+/// Like [str_match_with], also storing into `furthest` how far the attempt that got the furthest from its start went,
+/// as the source position and the pattern position it was stuck on. Every start is tried then
+#[allow(clippy::too_many_arguments)]
+pub fn str_match_traced<H: Haystack + ?Sized, const MAXCAPTURES: usize>(s: &H, p: &[u8], mm: &mut [LuaMatch], init: usize, anchor: bool, flags: Flags, sets: &[SetBitmap], mut furthest: Option<&mut (Pos, Pos)>) -> Result<usize> {
 	let ls = s.len();
-	let s = s.as_ptr();
-	let mut s1 = s;
-	let anchor = at(p) == b'^';
-	if anchor {
-		p = next(p);
-		lp -= 1; /* skip anchor character */
+	if init > ls {
+		return Ok(0);
+	}
+	let mut s1 = init;
+	let p_full_len = p.len();
+	let mut p = p;
+	let mut anchor = anchor;
+	let mut line_anchor = false;
+	if let Some((b'^', rest)) = p.split_first() {
+		if flags.multiline && !anchor {
+			line_anchor = true; /* only try at the start of each line */
+		} else {
+			anchor = true;
+		}
+		p = rest; /* skip anchor character */
 	}
 
-	let mut ms: MatchState<MAXCAPTURES> = MatchState::new(s, add(s, ls), add(p, lp));
+	let offset = p_full_len - p.len();
+	let mut ms: MatchState<H, MAXCAPTURES> = MatchState::new(s, p);
+	ms.flags = flags;
+	ms.sets = sets;
+	ms.matchdepth = flags.max_depth.map_or(MAXCCALLS, |depth| depth.get() as usize);
+	if !anchor && !flags.multiline && furthest.is_none() {
+		/* matches of a pattern like `%.tar%.gz$` can only start so far from the end */
+		if let Some(max) = ms.max_len_to_end() {
+			s1 = s1.max(ls.saturating_sub(max));
+		}
+	}
+	let mut progress = None;
 	loop {
-		let res = ms.patt_match(s1, p)?;
-		if !res.is_null() {
-			mm[0].start = diff(s1, s); /* start */
-			mm[0].end = diff(res, s); /* end */
+		if !line_anchor || s1 == 0 || ms.s_at(s1 - 1) == b'\n' as u32 {
+			ms.furthest = (s1, 0);
+			ms.longest = None;
+			let mut res = ms.patt_match(s1, 0);
+			if let (Ok(None), Some((end, level, capture))) = (&res, ms.longest) {
+				ms.level = level;
+				ms.capture = capture;
+				res = Ok(Some(end));
+			}
+			/* keep the attempt that got the furthest from where it started */
+			if let Some(ref mut furthest) = furthest {
+				if progress < Some((ms.furthest.0 - s1, ms.furthest.1)) {
+					progress = Some((ms.furthest.0 - s1, ms.furthest.1));
+					**furthest = (ms.furthest.0, ms.furthest.1 + offset);
+				}
+			}
+			if let Some(res) = res? {
+				mm[0].start = s1; /* start */
+				mm[0].end = res; /* end */
 
-			return Ok( ms.push_captures(null(), null(), &mut mm[1..])? + 1 );
+				return Ok( ms.push_captures(None, 0, &mut mm[1..])? + 1 );
+			}
 		}
-		s1 = next(s1);
-		if s1 >= ms.src_end || anchor {
+		s1 += 1;
+		/* the end is tried too, where only empty matches fit */
+		if s1 > ls || anchor {
 			break;
 		}
 	}
 	Ok(0)
 }
 
-pub fn str_check<const MAXCAPTURES: usize>(p: &[u8]) -> Result<()> {
-	let mut lp = p.len();
-	let mut p = p.as_ptr();
-	let anchor = at(p) == b'^';
-	if anchor {
-		p = next(p);
-		lp -= 1; /* skip anchor character */
-	}
-	let mut ms: MatchState<MAXCAPTURES> = MatchState::new(null(), null(), add(p, lp));
-	if at(sub(ms.p_end, 1)) == b'%' {
-		return Err( Error::EndsWithPercent );
+pub fn str_check<const MAXCAPTURES: usize>(p: &[u8], flags: Flags) -> Result<()> {
+	let p = match p.split_first() {
+		Some((b'^', rest)) => rest, /* skip anchor character */
+		_ => p,
+	};
+	if MAXCAPTURES == 0 {
+		/* no room for the whole match */
+		return Err( Error::TooManyCaptures );
 	}
-
-	ms.str_match_check(p)
+	let mut ms: MatchState<[u8], MAXCAPTURES> = MatchState::new(&[], p);
+	ms.flags = flags;
+	ms.str_match_check()
 }
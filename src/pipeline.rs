@@ -0,0 +1,77 @@
+use super::{char_range, Captures, LuaVersion, Pattern, Replacer};
+
+/// A pattern and what to replace its matches with
+pub type Rule<'a, 'r, const MAXCAPTURES: usize> = (Pattern<'a, MAXCAPTURES>, &'r dyn Replacer<MAXCAPTURES>);
+
+/// Applies several substitutions in a single pass over `text`.
+/// At every position the first rule that matches there wins, and scanning resumes after its match,
+/// so the output of one rule is never seen by another like with repeated [Pattern::gsub] calls.
+///
+/// ```
+/// use lupat::{gsub_pipeline, Captures, Pattern, Substitute};
+///
+/// let upper = |c: Captures<2>| c.get(1).to_uppercase();
+/// let swap = Substitute::new("%1").unwrap();
+/// let mut rules = [
+///     (Pattern::new("%$(%a+)").unwrap(), &upper as &dyn lupat::Replacer<2>),
+///     (Pattern::new("<(%a+)>").unwrap(), &swap),
+/// ];
+/// assert_eq!(gsub_pipeline("$foo <bar>", &mut rules), "FOO bar");
+/// ```
+pub fn gsub_pipeline<const MAXCAPTURES: usize>(text: &str, rules: &mut [Rule<MAXCAPTURES>]) -> String {
+	pipeline(text, rules, false)
+}
+
+/// Like [gsub_pipeline], but at every position the rule with the longest match wins, ties going to the earlier rule
+pub fn gsub_pipeline_longest<const MAXCAPTURES: usize>(text: &str, rules: &mut [Rule<MAXCAPTURES>]) -> String {
+	pipeline(text, rules, true)
+}
+
+fn pipeline<const MAXCAPTURES: usize>(text: &str, rules: &mut [Rule<MAXCAPTURES>], longest: bool) -> String {
+	let bytes = text.as_bytes();
+	let mut res = String::new();
+	let mut pos = 0;
+	// Where the last match ended, an empty match right there is skipped like Lua 5.4's gsub does
+	let mut last = None;
+	loop {
+		let mut best: Option<(usize, usize)> = None; /* rule index, end of match */
+		for (i, (patt, _)) in rules.iter_mut().enumerate() {
+			// `^` only anchors to the start of the text, or of every line when multiline
+			if pos > 0 && patt.patt.first() == Some(&b'^') && !(patt.flags.multiline && bytes[pos - 1] == b'\n') {
+				continue;
+			}
+			if patt.matches_bytes_at(bytes, pos, true) {
+				let end = patt.range().end;
+				if end == pos && last == Some(pos) && patt.flags.version >= LuaVersion::Lua54 {
+					continue;
+				}
+				if best.is_none_or(|(_, best_end)| end > best_end) {
+					best = Some((i, end));
+				}
+				if !longest {
+					break;
+				}
+			}
+		}
+
+		if let Some((i, end)) = best {
+			let (ref patt, repl) = rules[i];
+			res.push_str(&repl.replace(Captures { m: patt, text }));
+			if end > pos {
+				pos = char_range(text, pos..end).end;
+				last = Some(pos);
+				continue;
+			}
+			last = Some(pos);
+		}
+		// No match, or an empty one: keep the next character as is
+		match text[pos..].chars().next() {
+			Some(c) => {
+				res.push(c);
+				pos += c.len_utf8();
+			}
+			None => break,
+		}
+	}
+	res
+}
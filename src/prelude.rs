@@ -0,0 +1,25 @@
+//! The types most code needs, for `use lupat::prelude::*`.
+//!
+//! ```
+//! use lupat::prelude::*;
+//!
+//! let mut patt = Pattern::new("(%a+)=(%d+)").unwrap();
+//! assert_eq!(patt.captures("x=5"), ["x=5", "x", "5"]);
+//! assert_eq!(find_balanced("f(a(b))", b'(', b')', 0), Some(1..7));
+//! assert_eq!(is_match("%d", "a1"), Ok(true));
+//! ```
+
+use LUA_MAXCAPTURES;
+
+pub use error::Error;
+pub use {find, find_balanced, highlight, is_match, tokens, validate, Captures, LuaVersion, Match, PatternInfo, PatternOptions};
+#[cfg(feature = "glob")]
+pub use from_glob;
+#[cfg(feature = "gsub")]
+pub use {gsub, gsub_pipeline, Replacer, Substitute};
+#[cfg(feature = "iter")]
+pub use {FromCapture, FromCaptures};
+
+/// A [Pattern](crate::Pattern) with room for Lua's [LUA_MAXCAPTURES] captures.
+/// Unlike the default on the struct, this one also applies to `Pattern::new`, so there's no const generic to spell out.
+pub type Pattern<'a> = ::Pattern<'a, LUA_MAXCAPTURES>;
@@ -0,0 +1,86 @@
+use bindings::CompiledPattern;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use Error;
+#[cfg(feature = "gsub")]
+use {gsub_pipeline, Replacer, LUA_MAXCAPTURES};
+
+/// Compiled patterns shared under names like `"email"` or `"semver"`, safe to use from any thread.
+/// Pipelines can be built from the names with [Registry::gsub_pipeline], and luagrep looks them up with `--patterns`.
+///
+/// ```
+/// use lupat::Registry;
+///
+/// let registry = Registry::new();
+/// registry.register("semver", "^(%d+)%.(%d+)%.(%d+)$").unwrap();
+///
+/// let semver = registry.get("semver").unwrap();
+/// assert_eq!(semver.pattern().match_maybe_3("1.22.3"), Some(("1", "22", "3")));
+/// ```
+#[derive(Debug, Default)]
+pub struct Registry {
+	patterns: RwLock<HashMap<String, Arc<CompiledPattern>>>,
+}
+
+impl Registry {
+	pub fn new() -> Self {
+		Registry::default()
+	}
+
+	/// Compiles `source` and stores it under `name`, replacing any pattern already there
+	pub fn register(&self, name: &str, source: &str) -> Result<(), Error> {
+		self.insert(name, CompiledPattern::new(source)?);
+		Ok(())
+	}
+
+	/// Stores an already compiled pattern under `name`, returning the one it replaced
+	pub fn insert(&self, name: &str, patt: CompiledPattern) -> Option<Arc<CompiledPattern>> {
+		self.write().insert(name.to_owned(), Arc::new(patt))
+	}
+
+	pub fn get(&self, name: &str) -> Option<Arc<CompiledPattern>> {
+		self.read().get(name).cloned()
+	}
+
+	pub fn remove(&self, name: &str) -> Option<Arc<CompiledPattern>> {
+		self.write().remove(name)
+	}
+
+	/// [gsub_pipeline] with the pattern registered under each name.
+	/// Fails with [Error::UnknownPattern] if any of them isn't registered.
+	///
+	/// ```
+	/// use lupat::{Registry, Substitute};
+	///
+	/// let registry = Registry::new();
+	/// registry.register("amp", "&").unwrap();
+	/// registry.register("lt", "<").unwrap();
+	/// let (amp, lt) = (Substitute::new("&amp;").unwrap(), Substitute::new("&lt;").unwrap());
+	/// assert_eq!(registry.gsub_pipeline("a<b & c", &[("amp", &amp), ("lt", &lt)]).unwrap(), "a&lt;b &amp; c");
+	/// ```
+	#[cfg(feature = "gsub")]
+	pub fn gsub_pipeline(&self, text: &str, rules: &[(&str, &dyn Replacer<LUA_MAXCAPTURES>)]) -> Result<String, Error> {
+		let compiled = rules
+			.iter()
+			.map(|&(name, _)| self.get(name).ok_or_else(|| Error::UnknownPattern(name.to_owned())))
+			.collect::<Result<Vec<_>, _>>()?;
+		let mut rules: Vec<_> = compiled.iter().zip(rules).map(|(patt, &(_, repl))| (patt.pattern(), repl)).collect();
+		Ok(gsub_pipeline(text, &mut rules))
+	}
+
+	/// Names of every registered pattern, sorted
+	pub fn names(&self) -> Vec<String> {
+		let mut names: Vec<String> = self.read().keys().cloned().collect();
+		names.sort();
+		names
+	}
+
+	// A panic while holding the lock can't leave the map half updated, so poisoning is ignored
+	fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, Arc<CompiledPattern>>> {
+		self.patterns.read().unwrap_or_else(|e| e.into_inner())
+	}
+
+	fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, Arc<CompiledPattern>>> {
+		self.patterns.write().unwrap_or_else(|e| e.into_inner())
+	}
+}
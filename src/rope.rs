@@ -0,0 +1,111 @@
+//! Searching and substituting in [ropey::Rope]s without copying the whole document.
+//!
+//! The pattern runs over the rope's chunks as if they were one string, so matches can span lines and chunks,
+//! and `^` and `$` anchor at the start and end of the rope like with a `&str` (or at every line with [Pattern::multiline]).
+//! Only the text of each match is copied, to expand the replacement.
+
+use pattern::Haystack;
+use ropey::{Rope, RopeSlice};
+use std::cell::Cell;
+use std::ops;
+#[cfg(feature = "gsub")]
+use {generate_gsub_patterns, Error, MatchCursor};
+use Pattern;
+
+/// The bytes of a rope, remembering the chunk of the last lookup since the matcher mostly reads forward
+struct Chunks<'r> {
+	rope: RopeSlice<'r>,
+	/// The chunk and where it starts
+	chunk: Cell<(&'r [u8], usize)>,
+}
+
+impl<'r> Chunks<'r> {
+	fn new(rope: RopeSlice<'r>) -> Self {
+		Chunks { rope, chunk: Cell::new((&[], 0)) }
+	}
+}
+
+impl<'r> Haystack for Chunks<'r> {
+	type Unit = u8;
+
+	fn len(&self) -> usize {
+		self.rope.len_bytes()
+	}
+
+	fn unit_at(&self, i: usize) -> Option<u8> {
+		let (chunk, start) = self.chunk.get();
+		if let Some(&b) = i.checked_sub(start).and_then(|offset| chunk.get(offset)) {
+			return Some(b);
+		}
+		if i >= self.len() {
+			return None;
+		}
+		let (chunk, start, _, _) = self.rope.chunk_at_byte(i);
+		self.chunk.set((chunk.as_bytes(), start));
+		chunk.as_bytes().get(i - start).copied()
+	}
+}
+
+/// `range` widened to whole chars, like [char_range](crate::char_range) for a `&str`
+#[cfg(feature = "gsub")]
+fn char_range(rope: &Rope, range: ops::Range<usize>) -> ops::Range<usize> {
+	let start = rope.char_to_byte(rope.byte_to_char(range.start));
+	let end_char = rope.byte_to_char(range.end);
+	let end = match rope.char_to_byte(end_char) {
+		end if end < range.end => rope.char_to_byte(end_char + 1),
+		end => end,
+	};
+	start..end
+}
+
+impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
+	/// Byte range of the first match in `rope`, like [Pattern::find_in] over the whole text
+	pub fn find_rope(&mut self, rope: &Rope) -> Option<ops::Range<usize>> {
+		if self.run(&Chunks::new(rope.slice(..)), 0, false).unwrap_or(false) {
+			Some(self.range())
+		} else {
+			None
+		}
+	}
+
+	/// Replaces every match in `rope` like [Pattern::gsub], returning the number of replacements
+	#[cfg(feature = "gsub")]
+	pub fn gsub_rope(&mut self, rope: &mut Rope, repl: &str) -> Result<usize, Error> {
+		let repl = generate_gsub_patterns(repl)?;
+		self.check_substitutions(&repl)?;
+		let mut edits = Vec::new();
+		{
+			let chunks = Chunks::new(rope.slice(..));
+			let mut cursor = MatchCursor::new();
+			let mut last = 0;
+			while cursor.advance_haystack(self, &chunks) {
+				// The previous match may have ended inside the char this one starts in
+				let whole = char_range(rope, self.range());
+				let all = whole.start.max(last)..whole.end.max(last);
+				last = all.end;
+				// Captures are inside the whole match, so only that is copied, with them made relative to it
+				let text = rope.byte_slice(whole.clone()).to_string();
+				let offset = whole.start;
+				for m in &mut self.matches[..self.n_match] {
+					m.start -= offset;
+					m.end -= offset;
+				}
+				let captures = self.match_captures(&text);
+				let replacement: String = repl.iter().map(|r| r.expand(&captures)).collect();
+				edits.push((all, replacement));
+				for m in &mut self.matches[..self.n_match] {
+					m.start += offset;
+					m.end += offset;
+				}
+			}
+		}
+		// Backwards so edits don't shift the ones that haven't been made yet
+		for (range, replacement) in edits.iter().rev() {
+			let from = rope.byte_to_char(range.start);
+			let to = rope.byte_to_char(range.end);
+			rope.remove(from..to);
+			rope.insert(from, replacement);
+		}
+		Ok(edits.len())
+	}
+}
@@ -0,0 +1,49 @@
+use super::{char_range, Captures, Pattern};
+
+/// A cursor over a string for sequential parsing.
+/// Every pattern passed to it must match right at the cursor, which then advances past the match.
+pub struct Scanner<'t> {
+	text: &'t str,
+	pos: usize,
+}
+
+impl<'t> Scanner<'t> {
+	pub fn new(text: &'t str) -> Self {
+		Scanner { text, pos: 0 }
+	}
+
+	/// Matches `patt` at the cursor, advancing past it.
+	/// Captures are relative to [Scanner::rest] as it was before the call.
+	pub fn scan<'a, 'c, const MAXCAPTURES: usize>(&mut self, patt: &'c mut Pattern<'a, MAXCAPTURES>) -> Option<Captures<'a, 't, 'c, MAXCAPTURES>> {
+		let rest = self.rest();
+		if !patt.matches_bytes_at(rest.as_bytes(), 0, true) {
+			return None;
+		}
+		self.pos += char_range(rest, patt.range()).end;
+		Some(Captures { m: patt, text: rest })
+	}
+
+	/// Matches `patt` at the cursor and advances past it, returning the length skipped.
+	pub fn skip<const MAXCAPTURES: usize>(&mut self, patt: &mut Pattern<MAXCAPTURES>) -> Option<usize> {
+		if !patt.matches_bytes_at(self.rest().as_bytes(), 0, true) {
+			return None;
+		}
+		let len = char_range(self.rest(), patt.range()).end;
+		self.pos += len;
+		Some(len)
+	}
+
+	/// Byte offset of the cursor in the original text
+	pub fn pos(&self) -> usize {
+		self.pos
+	}
+
+	/// The text that hasn't been scanned yet
+	pub fn rest(&self) -> &'t str {
+		&self.text[self.pos..]
+	}
+
+	pub fn is_done(&self) -> bool {
+		self.pos >= self.text.len()
+	}
+}
@@ -0,0 +1,84 @@
+//! Reporting of slow calls, to find patterns and texts that make the matcher backtrack a lot in production.
+//! Nothing is timed until a hook is set.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// What took too long
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowKind {
+	/// A single search, including each one inside a gsub or an iterator
+	Match,
+	/// A whole gsub call
+	Gsub,
+}
+
+/// A call that went over the threshold given to [set_slow_match_hook]
+#[derive(Debug, Clone)]
+pub struct SlowMatch<'p> {
+	pub kind: SlowKind,
+	/// Source of the pattern
+	pub pattern: &'p [u8],
+	/// Length of the searched text, in bytes (or UTF-16 units)
+	pub haystack_len: usize,
+	pub elapsed: Duration,
+}
+
+type Hook = Box<dyn Fn(&SlowMatch) + Send + Sync>;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static HOOK: RwLock<Option<(Duration, Hook)>> = RwLock::new(None);
+
+/// Calls `hook` after every match or gsub that took `threshold` or longer, from any thread.
+/// Replaces the previous hook.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// lupat::set_slow_match_hook(Duration::from_millis(50), |slow| {
+///     eprintln!("{:?} of {} over {} bytes took {:?}", slow.kind, String::from_utf8_lossy(slow.pattern), slow.haystack_len, slow.elapsed);
+/// });
+/// ```
+pub fn set_slow_match_hook<F: Fn(&SlowMatch) + Send + Sync + 'static>(threshold: Duration, hook: F) {
+	let mut slot = HOOK.write().unwrap_or_else(|e| e.into_inner());
+	*slot = Some((threshold, Box::new(hook)));
+	ENABLED.store(true, Ordering::Release);
+}
+
+/// Removes the hook from [set_slow_match_hook]
+pub fn clear_slow_match_hook() {
+	let mut slot = HOOK.write().unwrap_or_else(|e| e.into_inner());
+	ENABLED.store(false, Ordering::Release);
+	*slot = None;
+}
+
+/// Reports the call it was started for when dropped, if it was slow
+pub(crate) struct Timer<'p> {
+	started: Option<Instant>,
+	kind: SlowKind,
+	pattern: &'p [u8],
+	haystack_len: usize,
+}
+
+impl<'p> Timer<'p> {
+	pub(crate) fn start(kind: SlowKind, pattern: &'p [u8], haystack_len: usize) -> Self {
+		let started = if ENABLED.load(Ordering::Acquire) { Some(Instant::now()) } else { None };
+		Timer { started, kind, pattern, haystack_len }
+	}
+}
+
+impl<'p> Drop for Timer<'p> {
+	fn drop(&mut self) {
+		let elapsed = match self.started {
+			Some(started) => started.elapsed(),
+			None => return,
+		};
+		let slot = HOOK.read().unwrap_or_else(|e| e.into_inner());
+		if let Some((threshold, ref hook)) = *slot {
+			if elapsed >= threshold {
+				hook(&SlowMatch { kind: self.kind, pattern: self.pattern, haystack_len: self.haystack_len, elapsed });
+			}
+		}
+	}
+}
@@ -0,0 +1,746 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+use std::ops;
+use pattern::LuaMatch;
+use slow::Timer;
+use {char_range, Captures, Error, MatchCursor, Pattern, SlowKind, LUA_MAXCAPTURES};
+
+impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
+	/// Replaces every match with what `lookup` returns for its captures.
+	/// Captures are relative to the whole of `text`, so [Captures::range] gives where they are in it.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("%a+").unwrap();
+	/// let res = patt.gsub_with("ab cd", |captures| format!("{:?}", captures.range(0).unwrap()));
+	/// assert_eq!(res, "0..2 3..5");
+	/// ```
+	pub fn gsub_with<F>(&mut self, text: &str, lookup: F) -> String
+	where
+		F: Fn(Captures<MAXCAPTURES>) -> String,
+	{
+		let _timer = Timer::start(SlowKind::Gsub, self.patt, text.len());
+		let mut cursor = MatchCursor::new();
+		let mut res = String::new();
+		let mut last = 0;
+		while cursor.advance_str(self, text) {
+			// The previous match may have ended inside the char this one starts in
+			let all = char_range(text, self.range());
+			res.push_str(&text[last..all.start.max(last)]);
+			res.push_str(&lookup(self.match_captures(text)));
+			last = all.end.max(last);
+		}
+		res.push_str(&text[last..]);
+		res
+	}
+
+	/// Like [Pattern::gsub_with], also telling `lookup` which match it's replacing and where it is in `text`.
+	/// Captures are relative to the whole of `text` as well.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("TODO").unwrap();
+	/// let numbered = patt.gsub_indexed("TODO, TODO", |occurrence, _| format!("TODO#{}@{}", occurrence.index + 1, occurrence.range.start));
+	/// assert_eq!(numbered, "TODO#1@0, TODO#2@6");
+	/// ```
+	pub fn gsub_indexed<F>(&mut self, text: &str, mut lookup: F) -> String
+	where
+		F: FnMut(Occurrence, Captures<MAXCAPTURES>) -> String,
+	{
+		let _timer = Timer::start(SlowKind::Gsub, self.patt, text.len());
+		let mut cursor = MatchCursor::new();
+		let mut res = String::new();
+		let mut last = 0;
+		let mut index = 0;
+		while cursor.advance_str(self, text) {
+			// The previous match may have ended inside the char this one starts in
+			let all = char_range(text, self.range());
+			res.push_str(&text[last..all.start.max(last)]);
+			res.push_str(&lookup(Occurrence { index, range: all.clone() }, self.match_captures(text)));
+			last = all.end.max(last);
+			index += 1;
+		}
+		res.push_str(&text[last..]);
+		res
+	}
+
+	/// Like [Pattern::gsub_with], but the text between matches goes through `on_gap` instead of being copied as is.
+	/// `on_gap` sees every non-empty stretch before, between and after the matches, so both can be transformed in one pass.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("%*(%w+)%*").unwrap();
+	/// let html = patt.gsub_with_gaps(
+	///     "a<b *bold* & c",
+	///     |captures| format!("<b>{}</b>", captures.get(1)),
+	///     |gap| gap.replace('&', "&amp;").replace('<', "&lt;"),
+	/// );
+	/// assert_eq!(html, "a&lt;b <b>bold</b> &amp; c");
+	/// ```
+	pub fn gsub_with_gaps<F, G>(&mut self, text: &str, mut on_match: F, mut on_gap: G) -> String
+	where
+		F: FnMut(Captures<MAXCAPTURES>) -> String,
+		G: FnMut(&str) -> String,
+	{
+		let _timer = Timer::start(SlowKind::Gsub, self.patt, text.len());
+		let mut cursor = MatchCursor::new();
+		let mut res = String::new();
+		let mut last = 0;
+		while cursor.advance_str(self, text) {
+			// The previous match may have ended inside the char this one starts in
+			let all = char_range(text, self.range());
+			if all.start > last {
+				res.push_str(&on_gap(&text[last..all.start]));
+			}
+			res.push_str(&on_match(self.match_captures(text)));
+			last = all.end.max(last);
+		}
+		if last < text.len() {
+			res.push_str(&on_gap(&text[last..]));
+		}
+		res
+	}
+
+	pub fn gsub(&mut self, text: &str, repl: &str) -> Result<String, Error> {
+		self.gsub_capped(text, repl, usize::MAX)
+	}
+
+	/// Like [Pattern::gsub], but fails with [Error::OutputTooLong] instead of building a result longer than `max_len` bytes
+	///
+	/// ```
+	/// use lupat::{error::Error, Pattern};
+	///
+	/// let mut patt: Pattern = Pattern::new("a").unwrap();
+	/// assert_eq!(patt.gsub_capped("aaa", "%0%0", 6).unwrap(), "aaaaaa");
+	/// assert_eq!(patt.gsub_capped("aaaa", "%0%0", 6), Err(Error::OutputTooLong(6)));
+	/// ```
+	pub fn gsub_capped(&mut self, text: &str, repl: &str, max_len: usize) -> Result<String, Error> {
+		let repl = generate_gsub_patterns(repl)?;
+		self.check_substitutions(&repl)?;
+		let _timer = Timer::start(SlowKind::Gsub, self.patt, text.len());
+		let mut cursor = MatchCursor::new();
+		let mut res = String::new();
+		let mut last = 0;
+		while cursor.advance_str(self, text) {
+			// The previous match may have ended inside the char this one starts in
+			let all = char_range(text, self.range());
+			push_capped(&mut res, &text[last..all.start.max(last)], max_len)?;
+			let captures = self.match_captures(text);
+			for r in &repl {
+				push_capped(&mut res, &r.expand(&captures), max_len)?;
+			}
+			last = all.end.max(last);
+		}
+		push_capped(&mut res, &text[last..], max_len)?;
+		Ok(res)
+	}
+
+	/// Applies [Pattern::gsub] to its own output until it stops changing, at most `max_iters` times.
+	/// Stops early on a cycle, where the output goes back to an earlier one. The [Fixpoint] tells which of these happened.
+	///
+	/// ```
+	/// use lupat::{Fixpoint, Pattern};
+	///
+	/// let mut patt: Pattern = Pattern::new("%(%)").unwrap();
+	/// assert_eq!(patt.gsub_fixpoint("((()))()", "", 10).unwrap(), ("".to_owned(), Fixpoint::Converged));
+	/// assert_eq!(patt.gsub_fixpoint("((()))()", "", 1).unwrap(), ("(())".to_owned(), Fixpoint::Capped));
+	/// ```
+	pub fn gsub_fixpoint(&mut self, text: &str, repl: &str, max_iters: usize) -> Result<(String, Fixpoint), Error> {
+		// Every earlier output, compared in full so a hash collision can't pass for a cycle
+		let mut seen = HashSet::new();
+		let mut res = text.to_owned();
+		for _ in 0..max_iters {
+			let next = self.gsub(&res, repl)?;
+			if next == res {
+				return Ok((next, Fixpoint::Converged));
+			}
+			if seen.contains(&next) {
+				return Ok((next, Fixpoint::Cycle));
+			}
+			seen.insert(std::mem::replace(&mut res, next));
+		}
+		Ok((res, Fixpoint::Capped))
+	}
+
+	/// Like [Pattern::gsub], but the replacement takes after the casing of each match:
+	/// upper case if the match is, capitalized if the match is, and lower case if the match is.
+	/// Matches with mixed or no casing get the replacement as is.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::builder("color").case_insensitive(true).build().unwrap();
+	/// assert_eq!(patt.gsub_smart_case("color, Color, COLOR", "colour").unwrap(), "colour, Colour, COLOUR");
+	/// ```
+	pub fn gsub_smart_case(&mut self, text: &str, repl: &str) -> Result<String, Error> {
+		let repl = Substitute::new(repl)?;
+		self.check_substitutions(&repl.repl)?;
+		Ok(self.gsub_with(text, |captures| {
+			let model = captures.get(0);
+			match_case(model, &repl.replace(captures))
+		}))
+	}
+
+	/// Fails like Lua's gsub does if `repl` refers to a capture the pattern doesn't have.
+	/// `%0` is the whole match, as is `%1` when there are no captures.
+	pub(crate) fn check_substitutions(&self, repl: &[Subst]) -> Result<(), Error> {
+		let captures = self.ast().map(|ast| ast.num_captures()).unwrap_or(0);
+		for r in repl {
+			if let Some(i) = r.capture() {
+				if i > captures.max(1) {
+					return Err( Error::InvalidCapture( Some(i as i8) ) );
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Replaces only the first match, like [str::replace] with a [Pattern::gsub] template
+	pub fn replace(&mut self, text: &str, repl: &str) -> Result<String, Error> {
+		self.replacen(text, repl, 1)
+	}
+
+	/// Replaces the first `n` matches, like [str::replacen] with a [Pattern::gsub] template
+	pub fn replacen(&mut self, text: &str, repl: &str, n: usize) -> Result<String, Error> {
+		let repl = Substitute::new(repl)?;
+		self.check_substitutions(&repl.repl)?;
+		let mut cursor = MatchCursor::new();
+		let mut res = String::new();
+		let mut last = 0;
+		for _ in 0..n {
+			if !cursor.advance_str(self, text) {
+				break;
+			}
+			// The previous match may have ended inside the char this one starts in
+			let all = char_range(text, self.range());
+			res.push_str(&text[last..all.start.max(last)]);
+			res.push_str(&repl.replace(self.match_captures(text)));
+			last = all.end.max(last);
+		}
+		res.push_str(&text[last..]);
+		Ok(res)
+	}
+
+	/// Like [Pattern::gsub], but also returns where each replacement came from and went, as `(input_range, output_range)` byte ranges.
+	/// Pairs are in order, so offsets in the output can be mapped back to the input.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	///
+	/// let mut patt: Pattern = Pattern::new("%$(%w+)").unwrap();
+	/// let (res, spans) = patt.gsub_spans("a $x b $yy", "<%1>").unwrap();
+	/// assert_eq!(res, "a <x> b <yy>");
+	/// assert_eq!(spans, [(2..4, 2..5), (7..10, 8..12)]);
+	/// ```
+	pub fn gsub_spans(&mut self, text: &str, repl: &str) -> Result<(String, Vec<Span>), Error> {
+		let repl = Substitute::new(repl)?;
+		self.check_substitutions(&repl.repl)?;
+		let _timer = Timer::start(SlowKind::Gsub, self.patt, text.len());
+		let mut cursor = MatchCursor::new();
+		let mut res = String::new();
+		let mut spans = Vec::new();
+		let mut last = 0;
+		while cursor.advance_str(self, text) {
+			// The previous match may have ended inside the char this one starts in
+			let all = char_range(text, self.range());
+			res.push_str(&text[last..all.start.max(last)]);
+			let out_start = res.len();
+			res.push_str(&repl.replace(self.match_captures(text)));
+			spans.push((all.start.max(last)..all.end.max(last), out_start..res.len()));
+			last = all.end.max(last);
+		}
+		res.push_str(&text[last..]);
+		Ok((res, spans))
+	}
+
+	/// Computes what [Pattern::gsub] would replace, without building the output.
+	/// Edits are in order, don't overlap and their ranges are offsets into `text`.
+	pub fn edits(&mut self, text: &str, repl: &str) -> Result<Vec<Edit>, Error> {
+		let repl = Substitute::new(repl)?;
+		self.check_substitutions(&repl.repl)?;
+		let mut cursor = MatchCursor::new();
+		let mut edits = Vec::new();
+		let mut last = 0;
+		while cursor.advance_str(self, text) {
+			// Same widening as gsub, so the ranges are always on char boundaries
+			let all = char_range(text, self.range());
+			let range = all.start.max(last)..all.end.max(last);
+			last = range.end;
+			edits.push(Edit {
+				range,
+				replacement: repl.replace(self.match_captures(text)),
+			});
+		}
+		Ok(edits)
+	}
+
+	pub fn gsub_bytes_with<F>(&mut self, bytes: &[u8], lookup: F) -> Vec<u8>
+	where
+		F: Fn(ByteCaptures<MAXCAPTURES>) -> Vec<u8>,
+	{
+		// Nothing is longer than usize::MAX, so this can't fail
+		self.gsub_bytes_with_capped(bytes, usize::MAX, lookup).unwrap_or_default()
+	}
+
+	/// Like [Pattern::gsub_bytes_with], but fails with [Error::OutputTooLong] instead of building a result longer than `max_len` bytes
+	pub fn gsub_bytes_with_capped<F>(&mut self, bytes: &[u8], max_len: usize, lookup: F) -> Result<Vec<u8>, Error>
+	where
+		F: Fn(ByteCaptures<MAXCAPTURES>) -> Vec<u8>,
+	{
+		let _timer = Timer::start(SlowKind::Gsub, self.patt, bytes.len());
+		let mut cursor = MatchCursor::new();
+		let mut res = Vec::new();
+		let mut last = 0;
+		while cursor.advance(self, bytes) {
+			let all = self.range();
+			extend_capped(&mut res, &bytes[last..all.start], max_len)?;
+			let captures = ByteCaptures { m: self, bytes };
+			let repl = lookup(captures);
+			extend_capped(&mut res, &repl, max_len)?;
+			last = all.end;
+		}
+		extend_capped(&mut res, &bytes[last..], max_len)?;
+		Ok(res)
+	}
+
+	/// Replaces matches by looking up the first capture (or the whole match without captures), like gsub with a table in Lua.
+	/// Matches `lookup` returns `None` for are left as they were.
+	///
+	/// ```
+	/// use lupat::Pattern;
+	/// use std::collections::HashMap;
+	///
+	/// let mut renames: HashMap<&[u8], &[u8]> = HashMap::new();
+	/// renames.insert(b"old_fn", b"new_fn");
+	/// let mut patt: Pattern = Pattern::new("[%a_][%w_]*").unwrap();
+	/// let res = patt.gsub_bytes_map(b"old_fn(x)\0old_fn", |ident| renames.get(ident).map(|&to| to.into()));
+	/// assert_eq!(res, b"new_fn(x)\0new_fn");
+	/// ```
+	pub fn gsub_bytes_map<F>(&mut self, bytes: &[u8], lookup: F) -> Vec<u8>
+	where
+		F: Fn(&[u8]) -> Option<Cow<[u8]>>,
+	{
+		let _timer = Timer::start(SlowKind::Gsub, self.patt, bytes.len());
+		let mut cursor = MatchCursor::new();
+		let mut res = Vec::new();
+		let mut last = 0;
+		while cursor.advance(self, bytes) {
+			let all = self.range();
+			res.extend_from_slice(&bytes[last..all.start]);
+			let key = ByteCaptures { m: self, bytes }.get(1);
+			match lookup(key) {
+				Some(repl) => res.extend_from_slice(&repl),
+				None => res.extend_from_slice(&bytes[all.clone()]),
+			}
+			last = all.end;
+		}
+		res.extend_from_slice(&bytes[last..]);
+		res
+	}
+}
+
+/// Appends `piece` unless that would make `res` longer than `max_len`
+fn push_capped(res: &mut String, piece: &str, max_len: usize) -> Result<(), Error> {
+	if res.len() + piece.len() > max_len {
+		return Err( Error::OutputTooLong(max_len) );
+	}
+	res.push_str(piece);
+	Ok(())
+}
+
+/// Like [push_capped], for bytes
+fn extend_capped(res: &mut Vec<u8>, piece: &[u8], max_len: usize) -> Result<(), Error> {
+	if res.len() + piece.len() > max_len {
+		return Err( Error::OutputTooLong(max_len) );
+	}
+	res.extend_from_slice(piece);
+	Ok(())
+}
+
+#[derive(Debug)]
+pub enum Subst {
+	Text(String),
+	Capture(usize),
+	/// `%u1`, a capture in upper case, with the `extensions` feature
+	#[cfg(feature = "extensions")]
+	Upper(usize),
+	/// `%l1`, a capture in lower case, with the `extensions` feature
+	#[cfg(feature = "extensions")]
+	Lower(usize),
+}
+
+impl Subst {
+	fn new_text(text: &str) -> Subst {
+		Subst::Text(text.to_string())
+	}
+
+	/// The capture this refers to, if any
+	fn capture(&self) -> Option<usize> {
+		match *self {
+			Subst::Text(_) => None,
+			Subst::Capture(i) => Some(i),
+			#[cfg(feature = "extensions")]
+			Subst::Upper(i) | Subst::Lower(i) => Some(i),
+		}
+	}
+
+	/// The text this stands for in the replacement of a match
+	pub(crate) fn expand<'r, 'b: 'r, const MAXCAPTURES: usize>(&'r self, captures: &Captures<'_, 'b, '_, MAXCAPTURES>) -> Cow<'r, str> {
+		match *self {
+			Subst::Text(ref s) => Cow::Borrowed(s),
+			Subst::Capture(i) => Cow::Borrowed(captures.get(i)),
+			#[cfg(feature = "extensions")]
+			Subst::Upper(i) => Cow::Owned(captures.get(i).to_uppercase()),
+			#[cfg(feature = "extensions")]
+			Subst::Lower(i) => Cow::Owned(captures.get(i).to_lowercase()),
+		}
+	}
+
+	/// Like [Subst::expand], with `default` standing in for the capture if it's empty
+	fn expand_or<'r, 'b: 'r, const MAXCAPTURES: usize>(&'r self, captures: &Captures<'_, 'b, '_, MAXCAPTURES>, default: Option<&'r str>) -> Cow<'r, str> {
+		match (self.capture(), default) {
+			(Some(i), Some(default)) if captures.get(i).is_empty() => match *self {
+				#[cfg(feature = "extensions")]
+				Subst::Upper(_) => Cow::Owned(default.to_uppercase()),
+				#[cfg(feature = "extensions")]
+				Subst::Lower(_) => Cow::Owned(default.to_lowercase()),
+				_ => Cow::Borrowed(default),
+			},
+			_ => self.expand(captures),
+		}
+	}
+}
+
+/// Substitutes every line of `reader` into `writer` like [Pattern::gsub], returning how many matches were replaced.
+/// Only one line is held at a time, and patterns see lines without their `\n`, so `$` matches at the end of each.
+/// Lines that aren't UTF-8 fail with [io::ErrorKind::InvalidData], and a bad `repl` with [io::ErrorKind::InvalidInput].
+///
+/// ```
+/// use lupat::{gsub_lines, Pattern};
+///
+/// let mut patt: Pattern = Pattern::new("^(%d+) ").unwrap();
+/// let mut out = Vec::new();
+/// let n = gsub_lines(&b"1 GET /\n2 POST /a\nnone\n"[..], &mut out, &mut patt, "[%1] ").unwrap();
+/// assert_eq!((n, &out[..]), (2, &b"[1] GET /\n[2] POST /a\nnone\n"[..]));
+/// ```
+pub fn gsub_lines<R: BufRead, W: Write, const MAXCAPTURES: usize>(mut reader: R, mut writer: W, patt: &mut Pattern<MAXCAPTURES>, repl: &str) -> io::Result<usize> {
+	let invalid = |e: Error| io::Error::new(io::ErrorKind::InvalidInput, e);
+	let repl = Substitute::new(repl).map_err(invalid)?;
+	patt.check_substitutions(&repl.repl).map_err(invalid)?;
+	let mut count = 0;
+	let mut line = String::new();
+	let mut res = String::new();
+	loop {
+		line.clear();
+		if reader.read_line(&mut line)? == 0 {
+			return Ok(count);
+		}
+		let text = line.strip_suffix('\n').unwrap_or(&line);
+		res.clear();
+		let mut cursor = MatchCursor::new();
+		let mut last = 0;
+		while cursor.advance_str(patt, text) {
+			// The previous match may have ended inside the char this one starts in
+			let all = char_range(text, patt.range());
+			res.push_str(&text[last..all.start.max(last)]);
+			res.push_str(&repl.replace(patt.match_captures(text)));
+			last = all.end.max(last);
+			count += 1;
+		}
+		res.push_str(&text[last..]);
+		res.push_str(&line[text.len()..]);
+		writer.write_all(res.as_bytes())?;
+	}
+}
+
+/// Changes the casing of `s` to that of `model`, see [Pattern::gsub_smart_case]
+fn match_case(model: &str, s: &str) -> String {
+	let mut letters = model.chars().filter(|c| c.is_alphabetic());
+	let first = match letters.next() {
+		Some(c) => c,
+		None => return s.to_owned(),
+	};
+	let rest: Vec<char> = letters.collect();
+	let rest_lower = rest.iter().all(|c| c.is_lowercase());
+	if first.is_uppercase() && rest_lower {
+		// Capitalized, which a single upper case letter is taken as too
+		let lower = s.to_lowercase();
+		let mut chars = lower.chars();
+		chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+	} else if first.is_uppercase() && rest.iter().all(|c| c.is_uppercase()) {
+		s.to_uppercase()
+	} else if first.is_lowercase() && rest_lower {
+		s.to_lowercase()
+	} else {
+		s.to_owned()
+	}
+}
+
+pub fn generate_gsub_patterns(repl: &str) -> Result<Vec<Subst>, Error> {
+	let mut m: Pattern<'_, 1> = Pattern::new("%%.?")?;
+
+	let mut res = Vec::new();
+	let mut slice = repl;
+	while m.matches(slice) {
+		let all = m.range();
+		let before = &slice[0..all.start];
+		if !before.is_empty() {
+			res.push(Subst::new_text(before));
+		}
+		let (subst, end) = match slice.as_bytes().get(all.start + 1) {
+			// escaped literal '%'
+			Some(b'%') => (Subst::new_text("%"), all.end),
+			Some(&d) if d.is_ascii_digit() => (Subst::Capture((d - b'0') as usize), all.end),
+			// %u1 and %l1
+			#[cfg(feature = "extensions")]
+			Some(&c @ (b'u' | b'l')) if slice.as_bytes().get(all.end).is_some_and(u8::is_ascii_digit) => {
+				let i = (slice.as_bytes()[all.end] - b'0') as usize;
+				(if c == b'u' { Subst::Upper(i) } else { Subst::Lower(i) }, all.end + 1)
+			}
+			// anything else, or nothing at all
+			_ => return Err( Error::InvalidReplacement ),
+		};
+		res.push(subst);
+		slice = &slice[end..];
+	}
+	res.push(Subst::new_text(slice));
+	Ok(res)
+}
+
+/// A single replacement from [Pattern::edits]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+	/// What to replace, in the original text
+	pub range: ops::Range<usize>,
+	pub replacement: String,
+}
+
+/// Where a match replaced by [Pattern::gsub_spans] was in the input, and where its replacement is in the output
+pub type Span = (ops::Range<usize>, ops::Range<usize>);
+
+/// Translates byte offsets between the input and output of [Pattern::gsub_spans]
+///
+/// ```
+/// use lupat::{Pattern, SourceMap};
+///
+/// let mut patt: Pattern = Pattern::new("%$(%w+)").unwrap();
+/// let (res, spans) = patt.gsub_spans("$x = $long", "%1").unwrap();
+/// assert_eq!(res, "x = long");
+/// let map = SourceMap::new(spans);
+/// assert_eq!(map.to_input(res.find('=').unwrap()), 3);
+/// assert_eq!(map.to_output(5), 4);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+	spans: Vec<Span>,
+}
+
+impl SourceMap {
+	/// `spans` have to be in order, as [Pattern::gsub_spans] gives them
+	pub fn new(spans: Vec<Span>) -> Self {
+		SourceMap { spans }
+	}
+
+	pub fn spans(&self) -> &[Span] {
+		&self.spans
+	}
+
+	/// Where `pos` in the input ended up in the output.
+	/// Positions inside a replaced match map to the start of its replacement.
+	pub fn to_output(&self, pos: usize) -> usize {
+		translate(self.spans.iter().map(|(input, output)| (input, output)), pos)
+	}
+
+	/// Where `pos` in the output came from in the input.
+	/// Positions inside a replacement map to the start of the match it replaced.
+	pub fn to_input(&self, pos: usize) -> usize {
+		translate(self.spans.iter().map(|(input, output)| (output, input)), pos)
+	}
+}
+
+/// Maps `pos` from the first range of each pair to the second, going by the last pair starting at or before it
+fn translate<'a, I>(pairs: I, pos: usize) -> usize
+where
+	I: Iterator<Item = (&'a ops::Range<usize>, &'a ops::Range<usize>)>,
+{
+	match pairs.take_while(|(from, _)| from.start <= pos).last() {
+		Some((from, to)) if pos < from.end => to.start,
+		Some((from, to)) => to.end + (pos - from.end),
+		None => pos,
+	}
+}
+
+/// How [Pattern::gsub_fixpoint] stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixpoint {
+	/// Another round wouldn't change the text
+	Converged,
+	/// The text went back to an earlier one, so it would never stop changing
+	Cycle,
+	/// It was still changing after `max_iters` rounds
+	Capped,
+}
+
+/// Which match [Pattern::gsub_indexed] is replacing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Occurrence {
+	/// How many matches came before this one
+	pub index: usize,
+	/// Where the match is in the original text
+	pub range: ops::Range<usize>,
+}
+
+pub struct Substitute {
+	repl: Vec<Subst>,
+	/// Text for empty captures, by capture number
+	defaults: Vec<Option<String>>,
+}
+
+impl Substitute {
+	pub fn new(repl: &str) -> Result<Self, Error> {
+		Ok(Substitute {
+			repl: generate_gsub_patterns(repl)?,
+			defaults: Vec::new(),
+		})
+	}
+
+	/// Uses `default` wherever capture `i` is empty or missing, instead of nothing
+	///
+	/// ```
+	/// use lupat::{Pattern, Substitute};
+	///
+	/// let fill = Substitute::new("%1: %2").unwrap().with_default(2, "N/A");
+	/// let mut patt: Pattern = Pattern::new("(%a+)=(%w*)").unwrap();
+	/// assert!(patt.matches("b="));
+	/// assert_eq!(fill.subst(&patt, "b="), "b: N/A");
+	/// ```
+	pub fn with_default(mut self, i: usize, default: &str) -> Self {
+		if self.defaults.len() <= i {
+			self.defaults.resize(i + 1, None);
+		}
+		self.defaults[i] = Some(default.to_owned());
+		self
+	}
+
+	/// Compiles `repl` for use with `patt` only, checking every capture it refers to up front.
+	/// The result carries its own copy of the pattern, so the two can't drift apart.
+	///
+	/// ```
+	/// use lupat::{Pattern, Substitute};
+	///
+	/// let patt: Pattern = Pattern::new("(%a+)=(%d+)").unwrap();
+	/// assert!(Substitute::for_pattern(&patt, "%3").is_err());
+	/// let mut swap = Substitute::for_pattern(&patt, "%2=%1").unwrap();
+	/// assert_eq!(swap.num_captures(), 2);
+	/// assert_eq!(swap.gsub("a=1 b=2"), "1=a 2=b");
+	/// ```
+	pub fn for_pattern<'a, const MAXCAPTURES: usize>(patt: &Pattern<'a, MAXCAPTURES>, repl: &str) -> Result<BoundSubstitute<'a, MAXCAPTURES>, Error> {
+		let subst = Substitute::new(repl)?;
+		patt.check_substitutions(&subst.repl)?;
+		Ok(BoundSubstitute {
+			patt: Pattern {
+				patt: patt.patt,
+				matches: [LuaMatch { start: 0, end: 0 }; MAXCAPTURES],
+				n_match: 0,
+				flags: patt.flags,
+				sets: patt.sets.clone(),
+			},
+			subst,
+			captures: patt.ast().map(|ast| ast.num_captures()).unwrap_or(0),
+		})
+	}
+
+	pub fn subst<const MAXCAPTURES: usize>(&self, patt: &Pattern<MAXCAPTURES>, text: &str) -> String {
+		self.expand_all(&patt.match_captures(text))
+	}
+
+	fn expand_all<const MAXCAPTURES: usize>(&self, captures: &Captures<MAXCAPTURES>) -> String {
+		let mut res = String::new();
+		for r in &self.repl {
+			let default = r.capture().and_then(|i| self.defaults.get(i)?.as_deref());
+			res.push_str(&r.expand_or(captures, default));
+		}
+		res
+	}
+}
+
+/// A [Substitute] checked against the pattern it's used with, from [Substitute::for_pattern]
+pub struct BoundSubstitute<'a, const MAXCAPTURES: usize = LUA_MAXCAPTURES> {
+	patt: Pattern<'a, MAXCAPTURES>,
+	subst: Substitute,
+	captures: usize,
+}
+
+impl<'a, const MAXCAPTURES: usize> BoundSubstitute<'a, MAXCAPTURES> {
+	/// Number of captures the pattern has, not counting the whole match
+	pub fn num_captures(&self) -> usize {
+		self.captures
+	}
+
+	pub fn pattern(&self) -> &Pattern<'a, MAXCAPTURES> {
+		&self.patt
+	}
+
+	/// See [Substitute::with_default]
+	pub fn with_default(mut self, i: usize, default: &str) -> Self {
+		self.subst = self.subst.with_default(i, default);
+		self
+	}
+
+	/// Like [Pattern::gsub], which can't fail as the template was checked already
+	pub fn gsub(&mut self, text: &str) -> String {
+		let subst = &self.subst;
+		self.patt.gsub_with(text, |captures| subst.expand_all(&captures))
+	}
+}
+
+/// Produces the replacement for a match, for [gsub_pipeline](crate::gsub_pipeline).
+/// Implemented by [Substitute] templates and `Fn(Captures) -> String` closures.
+pub trait Replacer<const MAXCAPTURES: usize = LUA_MAXCAPTURES> {
+	fn replace(&self, captures: Captures<MAXCAPTURES>) -> String;
+}
+
+impl<const MAXCAPTURES: usize> Replacer<MAXCAPTURES> for Substitute {
+	fn replace(&self, captures: Captures<MAXCAPTURES>) -> String {
+		self.expand_all(&captures)
+	}
+}
+
+impl<F, const MAXCAPTURES: usize> Replacer<MAXCAPTURES> for F
+where
+	F: Fn(Captures<MAXCAPTURES>) -> String,
+{
+	fn replace(&self, captures: Captures<MAXCAPTURES>) -> String {
+		self(captures)
+	}
+}
+
+pub struct ByteCaptures<'a, 'b, const MAXCAPTURES: usize = LUA_MAXCAPTURES> {
+	m: &'a Pattern<'a, MAXCAPTURES>,
+	bytes: &'b [u8],
+}
+
+impl<'a, 'b, const MAXCAPTURES: usize> ByteCaptures<'a, 'b, MAXCAPTURES> {
+	/// The capture, or an empty slice if there's no such capture
+	pub fn get(&self, i: usize) -> &'b [u8] {
+		self.try_get(i).unwrap_or(&[])
+	}
+
+	/// Like [ByteCaptures::get], but `None` if there's no such capture
+	pub fn try_get(&self, i: usize) -> Option<&'b [u8]> {
+		let range = match self.m.try_capture(i) {
+			Some(range) => range,
+			None if i == 1 && self.m.n_match == 1 => self.m.range(),
+			None => return None,
+		};
+		self.bytes.get(range)
+	}
+
+	pub fn num_matches(&self) -> usize {
+		self.m.n_match
+	}
+}
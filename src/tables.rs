@@ -0,0 +1,51 @@
+//! Lookup tables for the character classes, indexed by byte. These are what `%a`, `%d` and the rest match with.
+
+macro_rules! table {
+	($test:ident) => {{
+		let mut table = [false; 256];
+		let mut i = 0;
+		while i < 256 {
+			table[i] = (i as u8).$test();
+			i += 1;
+		}
+		table
+	}};
+}
+
+/// `%a`
+pub const ALPHA: [bool; 256] = table!(is_ascii_alphabetic);
+/// `%c`
+pub const CONTROL: [bool; 256] = table!(is_ascii_control);
+/// `%d`
+pub const DIGIT: [bool; 256] = table!(is_ascii_digit);
+/// `%g`
+pub const GRAPHIC: [bool; 256] = table!(is_ascii_graphic);
+/// `%l`
+pub const LOWER: [bool; 256] = table!(is_ascii_lowercase);
+/// `%p`
+pub const PUNCT: [bool; 256] = table!(is_ascii_punctuation);
+/// `%s`, which unlike C's `isspace` doesn't include `\v`
+pub const SPACE: [bool; 256] = table!(is_ascii_whitespace);
+/// `%u`
+pub const UPPER: [bool; 256] = table!(is_ascii_uppercase);
+/// `%w`
+pub const ALNUM: [bool; 256] = table!(is_ascii_alphanumeric);
+/// `%x`
+pub const HEX: [bool; 256] = table!(is_ascii_hexdigit);
+
+/// Table for a class letter (either case), like `b'a'` for [ALPHA]
+pub const fn for_class(class: u8) -> Option<&'static [bool; 256]> {
+	Some(match class.to_ascii_lowercase() {
+		b'a' => &ALPHA,
+		b'c' => &CONTROL,
+		b'd' => &DIGIT,
+		b'g' => &GRAPHIC,
+		b'l' => &LOWER,
+		b'p' => &PUNCT,
+		b's' => &SPACE,
+		b'u' => &UPPER,
+		b'w' => &ALNUM,
+		b'x' => &HEX,
+		_ => return None,
+	})
+}
@@ -0,0 +1,155 @@
+use std::ops;
+
+#[cfg(feature = "extensions")]
+use pattern::balance_seq;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+	/// A plain character
+	Literal,
+	/// `%` followed by a non-letter, like `%.`
+	Escape,
+	/// `.` or `%a`-style classes
+	Class,
+	/// `[...]`, including the brackets
+	Set,
+	/// `?`, `*`, `+` or `-` after a single character item
+	Quantifier,
+	/// `(`
+	CaptureOpen,
+	/// `)`
+	CaptureClose,
+	/// `()`
+	Position,
+	/// `^` at the start or `$` at the end
+	Anchor,
+	/// `%1` through `%9`
+	BackRef,
+	/// `%bxy`
+	Balance,
+	/// `%f`, the set following it is its own token
+	Frontier,
+	/// Malformed, like a trailing `%` or an unclosed set
+	Invalid,
+}
+
+/// A classified span of pattern source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+	pub kind: TokenKind,
+	pub span: ops::Range<usize>,
+}
+
+/// Splits pattern source into classified tokens for syntax highlighting.
+/// This never fails, malformed parts come out as [TokenKind::Invalid] so it works on half-typed patterns.
+///
+/// ```
+/// use lupat::TokenKind;
+///
+/// let kinds: Vec<TokenKind> = lupat::tokens("^%a+").map(|t| t.kind).collect();
+/// assert_eq!(kinds, [TokenKind::Anchor, TokenKind::Class, TokenKind::Quantifier]);
+/// ```
+pub fn tokens<S: AsRef<[u8]> + ?Sized>(pattern: &S) -> Tokens<'_> {
+	Tokens {
+		p: pattern.as_ref(),
+		pos: 0,
+		quantifiable: false,
+		frontier: false,
+	}
+}
+
+pub struct Tokens<'p> {
+	p: &'p [u8],
+	pos: usize,
+	/// Whether the previous token can take a quantifier
+	quantifiable: bool,
+	/// Whether the previous token was `%f`
+	frontier: bool,
+}
+
+impl<'p> Tokens<'p> {
+	/// Length of the set starting at `start`, if it's closed
+	fn set_len(&self, start: usize) -> Option<usize> {
+		let mut i = start + 1;
+		if self.p.get(i) == Some(&b'^') {
+			i += 1;
+		}
+		// The first character is always part of the set, even a `]`
+		loop {
+			i += if *self.p.get(i)? == b'%' { 2 } else { 1 };
+			if *self.p.get(i)? == b']' {
+				return Some(i + 1 - start);
+			}
+		}
+	}
+}
+
+impl<'p> Iterator for Tokens<'p> {
+	type Item = Token;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let start = self.pos;
+		let c = *self.p.get(start)?;
+		let rest = self.p.len() - start;
+		let quantifiable = self.quantifiable;
+		let frontier = self.frontier;
+		self.quantifiable = false;
+		self.frontier = false;
+
+		let (kind, len) = match c {
+			b'^' if start == 0 => (TokenKind::Anchor, 1),
+			b'$' if rest == 1 => (TokenKind::Anchor, 1),
+			b'?' | b'*' | b'+' | b'-' if quantifiable => (TokenKind::Quantifier, 1),
+			b'(' if self.p.get(start + 1) == Some(&b')') => (TokenKind::Position, 2),
+			b'(' => (TokenKind::CaptureOpen, 1),
+			b')' => (TokenKind::CaptureClose, 1),
+			b'.' => {
+				self.quantifiable = true;
+				(TokenKind::Class, 1)
+			}
+			b'[' => match self.set_len(start) {
+				Some(len) => {
+					// Sets after %f can't be quantified
+					self.quantifiable = !frontier;
+					(TokenKind::Set, len)
+				}
+				None => (TokenKind::Invalid, rest),
+			},
+			b'%' => match self.p.get(start + 1) {
+				None => (TokenKind::Invalid, 1),
+				Some(b'b') => {
+					#[cfg(feature = "extensions")]
+					let seq = balance_seq(&self.p[start + 2..]);
+					#[cfg(not(feature = "extensions"))]
+					let seq: Option<(&[u8], &[u8], usize)> = None;
+					if let Some((_, _, len)) = seq {
+						(TokenKind::Balance, 2 + len)
+					} else if rest >= 4 {
+						(TokenKind::Balance, 4)
+					} else {
+						(TokenKind::Invalid, rest)
+					}
+				}
+				Some(b'f') => {
+					self.frontier = true;
+					(TokenKind::Frontier, 2)
+				}
+				Some(b'0'..=b'9') => (TokenKind::BackRef, 2),
+				Some(c) => {
+					self.quantifiable = true;
+					(if c.is_ascii_alphabetic() { TokenKind::Class } else { TokenKind::Escape }, 2)
+				}
+			},
+			_ => {
+				self.quantifiable = true;
+				(TokenKind::Literal, 1)
+			}
+		};
+
+		self.pos += len;
+		Some(Token {
+			kind,
+			span: start..self.pos,
+		})
+	}
+}
@@ -0,0 +1,98 @@
+use std::marker::PhantomData;
+use {MatchCursor, Pattern};
+
+/// Converts a single capture, for [FromCaptures]
+pub trait FromCapture<'t>: Sized {
+	fn from_capture(s: &'t str) -> Option<Self>;
+}
+
+impl<'t> FromCapture<'t> for &'t str {
+	fn from_capture(s: &'t str) -> Option<Self> {
+		Some(s)
+	}
+}
+
+macro_rules! from_str_capture {
+	($($ty:ty),*) => {$(
+		impl<'t> FromCapture<'t> for $ty {
+			fn from_capture(s: &'t str) -> Option<Self> {
+				s.parse().ok()
+			}
+		}
+	)*};
+}
+
+from_str_capture!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool, char, String);
+
+/// Converts the captures of a match into a tuple, see [Pattern::gmatch_scan]
+pub trait FromCaptures<'t>: Sized {
+	fn from_captures(captures: &[&'t str]) -> Option<Self>;
+}
+
+macro_rules! tuple_captures {
+	($($name:ident $var:ident),*) => {
+		impl<'t, $($name: FromCapture<'t>),*> FromCaptures<'t> for ($($name,)*) {
+			fn from_captures(captures: &[&'t str]) -> Option<Self> {
+				match *captures {
+					[$($var),*] => Some(($($name::from_capture($var)?,)*)),
+					_ => None,
+				}
+			}
+		}
+	};
+}
+
+tuple_captures!(A a);
+tuple_captures!(A a, B b);
+tuple_captures!(A a, B b, C c);
+tuple_captures!(A a, B b, C c, D d);
+tuple_captures!(A a, B b, C c, D d, E e);
+tuple_captures!(A a, B b, C c, D d, E e, F f);
+
+impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
+	/// Iterates matches converted into a tuple, one element per capture (or the whole match if there are none).
+	/// Matches that don't convert, like a capture that isn't a number, are skipped.
+	///
+	/// ```
+	/// let mut patt = lupat::Pattern::<3>::new("(%a+)=(%d+)").unwrap();
+	/// let pairs: Vec<(&str, u32)> = patt.gmatch_scan("a=1 b=x c=30").collect();
+	/// assert_eq!(pairs, [("a", 1), ("c", 30)]);
+	/// ```
+	pub fn gmatch_scan<'b, 'c, T: FromCaptures<'b>>(&'c mut self, text: &'b str) -> GMatchScan<'a, 'b, 'c, T, MAXCAPTURES> {
+		GMatchScan {
+			m: self,
+			text,
+			cursor: MatchCursor::new(),
+			ty: PhantomData,
+		}
+	}
+}
+
+/// Iterator for converted matches from `gmatch_scan`
+pub struct GMatchScan<'a, 'b, 'c, T, const MAXCAPTURES: usize>
+where
+	'a: 'c,
+{
+	m: &'c mut Pattern<'a, MAXCAPTURES>,
+	text: &'b str,
+	cursor: MatchCursor,
+	ty: PhantomData<T>,
+}
+
+impl<'a, 'b, 'c, T: FromCaptures<'b>, const MAXCAPTURES: usize> Iterator for GMatchScan<'a, 'b, 'c, T, MAXCAPTURES> {
+	type Item = T;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		while self.cursor.advance_str(self.m, self.text) {
+			let captures = self.m.match_captures(self.text);
+			let all: Vec<&'b str> = match captures.num_matches() {
+				1 => vec![captures.get(0)],
+				n => (1..n).map(|i| captures.get(i)).collect(),
+			};
+			if let Some(item) = T::from_captures(&all) {
+				return Some(item);
+			}
+		}
+		None
+	}
+}
@@ -0,0 +1,195 @@
+//! NFC normalization, so text that looks the same matches the same however its accents were encoded.
+//! Ranges found in the normalized text are mapped back to the original.
+//!
+//! Literals in the pattern have to be normalized too, with [nfc]:
+//!
+//! ```
+//! use lupat::unicode::nfc;
+//! use lupat::Pattern;
+//!
+//! // "café" with a decomposed é, e followed by a combining acute accent
+//! let source = nfc("caf\u{65}\u{301}");
+//! let mut patt: Pattern = Pattern::new(&source).unwrap();
+//! assert_eq!(patt.find_nfc("un café"), Some(3..8));
+//! assert_eq!(patt.find_nfc("un cafe\u{301}"), Some(3..9));
+//! ```
+
+use nfc_tables::{COMBINING_CLASS, COMPOSES_BACKWARDS, COMPOSITION, DECOMPOSED, DECOMPOSITION};
+use std::ops;
+use {char_range, Pattern};
+
+const S_BASE: u32 = 0xAC00;
+const L_BASE: u32 = 0x1100;
+const V_BASE: u32 = 0x1161;
+const T_BASE: u32 = 0x11A7;
+const L_COUNT: u32 = 19;
+const V_COUNT: u32 = 21;
+const T_COUNT: u32 = 28;
+const S_COUNT: u32 = L_COUNT * V_COUNT * T_COUNT;
+
+/// `text` in Normalization Form C
+pub fn nfc(text: &str) -> String {
+	Normalized::new(text).text
+}
+
+/// Text normalized to NFC, remembering where each part came from
+#[derive(Debug, Clone)]
+pub struct Normalized {
+	text: String,
+	/// Span of the original each byte of `text` was normalized from
+	starts: Vec<usize>,
+	ends: Vec<usize>,
+	len: usize,
+}
+
+impl Normalized {
+	pub fn new(text: &str) -> Self {
+		let mut normalized = Normalized { text: String::with_capacity(text.len()), starts: Vec::new(), ends: Vec::new(), len: text.len() };
+		// Chars before a boundary never interact with the ones after, so each part is normalized on its own
+		let mut start = 0;
+		for (i, c) in text.char_indices() {
+			if i > start && is_boundary(c) {
+				normalized.push_part(text, start..i);
+				start = i;
+			}
+		}
+		if start < text.len() {
+			normalized.push_part(text, start..text.len());
+		}
+		normalized
+	}
+
+	fn push_part(&mut self, text: &str, range: ops::Range<usize>) {
+		let mut chars = Vec::new();
+		for c in text[range.clone()].chars() {
+			decompose(c, &mut chars);
+		}
+		reorder(&mut chars);
+		self.text.extend(compose(chars));
+		self.starts.resize(self.text.len(), range.start);
+		self.ends.resize(self.text.len(), range.end);
+	}
+
+	pub fn as_str(&self) -> &str {
+		&self.text
+	}
+
+	/// Maps a range of the normalized text to the original, widened to whole parts of it
+	pub fn original_range(&self, range: ops::Range<usize>) -> ops::Range<usize> {
+		let range = char_range(&self.text, range);
+		if range.is_empty() {
+			let at = self.starts.get(range.start).copied().unwrap_or(self.len);
+			return at..at;
+		}
+		self.starts[range.start]..self.ends[range.end - 1]
+	}
+}
+
+fn combining_class(c: char) -> u8 {
+	match COMBINING_CLASS.binary_search_by_key(&(c as u32), |&(c, _)| c) {
+		Ok(i) => COMBINING_CLASS[i].1,
+		Err(_) => 0,
+	}
+}
+
+/// Whether nothing before `c` can reorder or compose with it
+fn is_boundary(c: char) -> bool {
+	let mut first = Vec::new();
+	decompose(c, &mut first);
+	let first = first[0];
+	combining_class(first) == 0
+		&& COMPOSES_BACKWARDS.binary_search(&(first as u32)).is_err()
+		&& !(V_BASE..V_BASE + V_COUNT).contains(&(first as u32))
+		&& !(T_BASE + 1..T_BASE + T_COUNT).contains(&(first as u32))
+}
+
+fn decompose(c: char, out: &mut Vec<char>) {
+	let cp = c as u32;
+	if (S_BASE..S_BASE + S_COUNT).contains(&cp) {
+		let s = cp - S_BASE;
+		out.extend(char::from_u32(L_BASE + s / (V_COUNT * T_COUNT)));
+		out.extend(char::from_u32(V_BASE + (s % (V_COUNT * T_COUNT)) / T_COUNT));
+		if !s.is_multiple_of(T_COUNT) {
+			out.extend(char::from_u32(T_BASE + s % T_COUNT));
+		}
+		return;
+	}
+	match DECOMPOSITION.binary_search_by_key(&cp, |&(c, _, _)| c) {
+		Ok(i) => {
+			let (_, start, len) = DECOMPOSITION[i];
+			let start = start as usize;
+			out.extend(DECOMPOSED[start..start + len as usize].iter().filter_map(|&c| char::from_u32(c)));
+		}
+		Err(_) => out.push(c),
+	}
+}
+
+/// Sorts every run of combining marks by combining class, keeping the order of equal ones
+fn reorder(chars: &mut [char]) {
+	let mut i = 0;
+	while i < chars.len() {
+		let end = i + chars[i..].iter().take_while(|&&c| combining_class(c) != 0).count();
+		chars[i..end].sort_by_key(|&c| combining_class(c));
+		i = end + 1;
+	}
+}
+
+fn compose_pair(first: char, second: char) -> Option<char> {
+	let (a, b) = (first as u32, second as u32);
+	if (L_BASE..L_BASE + L_COUNT).contains(&a) && (V_BASE..V_BASE + V_COUNT).contains(&b) {
+		return char::from_u32(S_BASE + ((a - L_BASE) * V_COUNT + (b - V_BASE)) * T_COUNT);
+	}
+	if (S_BASE..S_BASE + S_COUNT).contains(&a) && (a - S_BASE).is_multiple_of(T_COUNT) && (T_BASE + 1..T_BASE + T_COUNT).contains(&b) {
+		return char::from_u32(a + (b - T_BASE));
+	}
+	let i = COMPOSITION.binary_search_by_key(&(a, b), |&(a, b, _)| (a, b)).ok()?;
+	char::from_u32(COMPOSITION[i].2)
+}
+
+/// Canonical composition of decomposed, reordered chars
+fn compose(chars: Vec<char>) -> Vec<char> {
+	let mut out: Vec<char> = Vec::with_capacity(chars.len());
+	let mut starter = None;
+	// Combining class of the last char kept since the starter, `None` right after it
+	let mut last_class = None;
+	for c in chars {
+		let class = combining_class(c);
+		if let Some(i) = starter {
+			let blocked = last_class.is_some_and(|last| last == 0 || last >= class);
+			if !blocked {
+				if let Some(composed) = compose_pair(out[i], c) {
+					out[i] = composed;
+					continue;
+				}
+			}
+		}
+		if class == 0 {
+			starter = Some(out.len());
+			last_class = None;
+		} else {
+			last_class = Some(class);
+		}
+		out.push(c);
+	}
+	out
+}
+
+impl<'a, const MAXCAPTURES: usize> Pattern<'a, MAXCAPTURES> {
+	/// The first match in `text` after normalizing it to NFC, as offsets into `text`
+	pub fn find_nfc(&mut self, text: &str) -> Option<ops::Range<usize>> {
+		let normalized = Normalized::new(text);
+		if !self.matches(normalized.as_str()) {
+			return None;
+		}
+		Some(normalized.original_range(self.range()))
+	}
+
+	/// Like [Pattern::find_nfc], with the ranges of the whole match and then every capture
+	pub fn captures_nfc(&mut self, text: &str) -> Option<Vec<ops::Range<usize>>> {
+		let normalized = Normalized::new(text);
+		if !self.matches(normalized.as_str()) {
+			return None;
+		}
+		Some((0..self.n_match).map(|i| normalized.original_range(self.capture(i))).collect())
+	}
+}
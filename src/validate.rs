@@ -0,0 +1,132 @@
+use ast::Ast;
+use error::Error;
+use pattern::{str_check, Flags, LUA_MAXCAPTURES};
+use std::ops;
+use tokens::{tokens, TokenKind};
+
+/// What [validate] found out about a pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternInfo {
+	/// Number of captures, not counting the whole match
+	pub captures: usize,
+	/// Starts with `^`
+	pub anchored_start: bool,
+	/// Ends with `$`
+	pub anchored_end: bool,
+	/// Shortest length in bytes any match can have
+	pub min_len: usize,
+	/// Longest length in bytes any match can have, `None` if it's unbounded
+	pub max_len: Option<usize>,
+}
+
+/// Checks `pattern` the way [Pattern::new](crate::Pattern::new) does, without keeping a [Pattern](crate::Pattern) around.
+/// For rejecting bad patterns early, like when loading a config.
+///
+/// ```
+/// use lupat::error::Error;
+///
+/// let info = lupat::validate("^(%a+)=(%d+)$").unwrap();
+/// assert_eq!(info.captures, 2);
+/// assert!(info.anchored_start && info.anchored_end);
+/// assert_eq!(info.min_len, 3);
+/// assert_eq!(lupat::validate("(%a+").err(), Some(Error::UnfinishedCapture));
+/// ```
+pub fn validate<S: AsRef<[u8]> + ?Sized>(pattern: &S) -> Result<PatternInfo, Error> {
+	let pattern = pattern.as_ref();
+	str_check::<LUA_MAXCAPTURES>(pattern, Flags::DEFAULT)?;
+	let ast = Ast::parse(pattern)?;
+	Ok(PatternInfo {
+		captures: ast.num_captures(),
+		anchored_start: ast.anchor_start,
+		anchored_end: ast.anchor_end,
+		min_len: ast.min_len(),
+		max_len: ast.max_len(),
+	})
+}
+
+/// An error from [validate_all], with the part of the pattern it's about
+#[derive(Debug, PartialEq)]
+pub struct SyntaxError {
+	pub error: Error,
+	/// Byte range in the pattern source
+	pub span: ops::Range<usize>,
+}
+
+/// Like [validate], but carries on after an error to report every one in the pattern, in the order they appear.
+/// For config editors that want to point out everything wrong at once rather than one round trip per mistake.
+///
+/// ```
+/// use lupat::error::Error;
+///
+/// let errors = lupat::validate_all("(%d+)%2 [a").err().unwrap();
+/// assert_eq!(errors[0].error, Error::InvalidCapture(Some(2)));
+/// assert_eq!(errors[0].span, 5..7);
+/// assert_eq!(errors[1].error, Error::MissingEndBracket);
+/// assert_eq!(errors[1].span, 8..10);
+/// ```
+pub fn validate_all<S: AsRef<[u8]> + ?Sized>(pattern: &S) -> Result<PatternInfo, Vec<SyntaxError>> {
+	let pattern = pattern.as_ref();
+	let mut errors = Vec::new();
+	// Start of every capture's `(`, with whether it was closed yet
+	let mut captures: Vec<(usize, bool)> = Vec::new();
+	let mut open = Vec::new();
+	let mut frontier = None;
+	for token in tokens(pattern) {
+		let span = token.span.clone();
+		if let Some(at) = frontier.take() {
+			if token.kind != TokenKind::Set && pattern[span.start] != b'[' {
+				errors.push(SyntaxError { error: Error::MissingLBracketF, span: at });
+			}
+		}
+		let error = match token.kind {
+			TokenKind::Invalid => Some(match pattern[span.start] {
+				b'[' => Error::MissingEndBracket,
+				_ if span.len() == 1 => Error::EndsWithPercent,
+				_ => Error::MissingBalanceArgs,
+			}),
+			TokenKind::Frontier => {
+				frontier = Some(span.clone());
+				None
+			}
+			TokenKind::BackRef => {
+				let l = pattern[span.start + 1] as i8 - b'1' as i8;
+				match captures.get(l as usize) {
+					Some(&(_, true)) => None,
+					_ => Some(Error::InvalidCapture(Some(l + 1))),
+				}
+			}
+			TokenKind::CaptureOpen | TokenKind::Position if captures.len() + 1 >= LUA_MAXCAPTURES => Some(Error::TooManyCaptures),
+			TokenKind::CaptureOpen => {
+				open.push(captures.len());
+				captures.push((span.start, false));
+				None
+			}
+			TokenKind::Position => {
+				captures.push((span.start, true));
+				None
+			}
+			TokenKind::CaptureClose => match open.pop() {
+				Some(i) => {
+					captures[i].1 = true;
+					None
+				}
+				None => Some(Error::NoOpenCapture),
+			},
+			_ => None,
+		};
+		if let Some(error) = error {
+			errors.push(SyntaxError { error, span });
+		}
+	}
+	if let Some(at) = frontier {
+		errors.push(SyntaxError { error: Error::MissingLBracketF, span: at });
+	}
+	for i in open {
+		let start = captures[i].0;
+		errors.push(SyntaxError { error: Error::UnfinishedCapture, span: start..start + 1 });
+	}
+	if !errors.is_empty() {
+		return Err(errors);
+	}
+	validate(pattern).map_err(|error| vec![SyntaxError { error, span: 0..pattern.len() }])
+}
@@ -1,4 +1,12 @@
 extern crate lupat;
+#[cfg(feature = "ropey")]
+extern crate ropey;
+#[cfg(feature = "nom")]
+extern crate nom;
+#[cfg(feature = "allocator-api2")]
+extern crate allocator_api2;
+#[cfg(feature = "serde")]
+extern crate serde_core;
 use lupat::{Pattern, error::Error};
 
 #[test]
@@ -9,6 +17,8 @@ fn bad_patterns() {
 		("[%a%[",           Error::MissingEndBracket),
 		("(()",             Error::UnfinishedCapture),
 		("[%A",             Error::MissingEndBracket),
+		("[]",              Error::MissingEndBracket),
+		("[^]",             Error::MissingEndBracket),
 		("(1) (2(3)%2)%1",  Error::InvalidCapture(Some(2))),
 	];
 
@@ -25,6 +35,7 @@ fn bad_patterns() {
 
 
 #[test]
+#[cfg(feature="iter")]
 fn basic() {
 	let patterns = ["%w+", "(%w+)", "([%w]+)",];
 	let test_str = "test foo bar"; // All patterns should match 3 items, (test), (foo), (bar)
@@ -41,6 +52,1933 @@ fn stack() {
 	let mut pattern: Pattern<'_, 51> = Pattern::new("(((((((((((((((((((((((((((((((((((((((((((((((((())))))))))))))))))))))))))))))))))))))))))))))))))").unwrap();
 	pattern.matches("foo bar");
 
-	assert_eq!( std::mem::size_of::<Pattern<'_, 0>>(), 24 );
-	assert_eq!( std::mem::size_of::<Pattern<'_, 50>>(), 24 + ( /* LuaMatch is u8 x 2 */ 16 * 50) );
-}
\ No newline at end of file
+	// 32 for the source and flags, 24 for the precomputed sets
+	assert_eq!( std::mem::size_of::<Pattern<'_, 0>>(), 56 );
+	assert_eq!( std::mem::size_of::<Pattern<'_, 50>>(), 56 + ( /* LuaMatch is u8 x 2 */ 16 * 50) );
+}
+#[test]
+fn scanner() {
+	let mut ident: Pattern<'_, 2> = Pattern::new("([%a_][%w_]*)").unwrap();
+	let mut ws: Pattern<'_, 1> = Pattern::new("%s+").unwrap();
+	let mut eq: Pattern<'_, 1> = Pattern::new("=").unwrap();
+
+	let mut scanner = lupat::Scanner::new("foo = bar");
+	assert_eq!( scanner.scan(&mut ident).map(|c| c.get(1)), Some("foo") );
+	assert_eq!( scanner.skip(&mut ws), Some(1) );
+	// Must match at the cursor, not further along
+	assert!( scanner.scan(&mut ident).is_none() );
+	assert_eq!( scanner.skip(&mut eq), Some(1) );
+	scanner.skip(&mut ws);
+	assert_eq!( scanner.pos(), 6 );
+	assert_eq!( scanner.rest(), "bar" );
+	assert_eq!( scanner.scan(&mut ident).map(|c| c.get(1)), Some("bar") );
+	assert!( scanner.is_done() );
+}
+
+#[test]
+fn find_in() {
+	let text = "foo bar baz";
+	let mut pattern: Pattern<'_, 2> = Pattern::new("^(%a+)$").unwrap();
+	assert_eq!( pattern.find_in(text, 4..7), Some(4..7) );
+	assert_eq!( &text[pattern.capture(1)], "bar" );
+	assert_eq!( pattern.find_in(text, 4..8), None );
+
+	let mut pattern: Pattern<'_, 1> = Pattern::new("%a+").unwrap();
+	assert_eq!( pattern.find_in(text, 5..11), Some(5..7) );
+	assert!( !pattern.matches_in(text, 3..4) );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn multiline() {
+	let text = "local x = 1\n  # comment\ny = 2 # not a comment\n#last";
+
+	let mut pattern: Pattern<'_, 1> = Pattern::new("^%s*#[^\n]*").unwrap().multiline(true);
+	let lines: Vec<&str> = pattern.gmatch(text).collect();
+	assert_eq!( lines, ["  # comment", "#last"] );
+
+	let mut pattern: Pattern<'_, 2> = Pattern::new("(%d)$").unwrap().multiline(true);
+	assert_eq!( pattern.gmatch(text).collect::<Vec<_>>(), ["1"] );
+
+	// Lua behavior by default
+	let mut pattern: Pattern<'_, 1> = Pattern::new("^%s*#").unwrap();
+	assert!( !pattern.matches(text) );
+}
+
+#[test]
+#[cfg(feature = "utf16")]
+fn utf16() {
+	let text: Vec<u16> = "héllo wörld 42".encode_utf16().collect();
+	let mut pattern: Pattern<'_, 2> = Pattern::new("(%d+)").unwrap();
+	assert!( pattern.matches_utf16(&text) );
+	assert_eq!( pattern.capture(1), 12..14 );
+
+	let mut pattern: Pattern<'_, 1> = Pattern::new("[^%s]+").unwrap();
+	let words: Vec<String> = pattern.gmatch_utf16(&text).map(String::from_utf16_lossy).collect();
+	assert_eq!( words, ["héllo", "wörld", "42"] );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn paths() {
+	use std::ffi::OsStr;
+	use std::path::Path;
+
+	let mut pattern: Pattern<'_, 1> = Pattern::new("%.tar%.gz$").unwrap();
+	assert!( pattern.match_path(Path::new("dist/release.tar.gz")) );
+	assert!( !pattern.match_path(Path::new("dist/release.tar")) );
+
+	let mut pattern: Pattern<'_, 1> = Pattern::new("[^/]+").unwrap();
+	let parts: Vec<&[u8]> = pattern.gmatch_os(OsStr::new("usr/local/bin")).collect();
+	assert_eq!( parts, [&b"usr"[..], b"local", b"bin"] );
+}
+
+#[test]
+#[cfg(feature="glob")]
+fn glob() {
+	let tests = [
+		("*.tar.gz",      "^.*%.tar%.gz$"),
+		("file?.[ch]",    "^file.%.[ch]$"),
+		("[!a-z]_[]]",    "^[^a-z]%_[%]]$"),
+		("100%[",         "^100%%%[$"),
+		("\\*",           "^%*$"),
+	];
+
+	for (glob, expected) in tests {
+		assert_eq!( lupat::from_glob(glob), expected );
+	}
+
+	let glob = lupat::from_glob("*.tar.gz");
+	let mut pattern: Pattern<'_, 1> = Pattern::new(&glob).unwrap();
+	assert!( pattern.matches("release-1.0.tar.gz") );
+	assert!( !pattern.matches("release-1.0.tar.gz.sig") );
+	assert!( !pattern.matches("release-1.0_tar.gz") );
+
+	// Path globs don't let `*` cross directories, only `**`
+	let glob = lupat::from_path_glob("src/*.rs");
+	let mut pattern: Pattern<'_, 1> = Pattern::new(&glob).unwrap();
+	assert!( pattern.matches("src/lib.rs") );
+	assert!( !pattern.matches("src/bin/luagrep.rs") );
+	let glob = lupat::from_path_glob("src/**.rs");
+	let mut pattern: Pattern<'_, 1> = Pattern::new(&glob).unwrap();
+	assert!( pattern.matches("src/bin/luagrep.rs") );
+}
+
+#[test]
+fn builder() {
+	use lupat::{Class, PatternBuilder, ast::Ast};
+
+	let key = PatternBuilder::new().set("_").class(Class::Letter).one_or_more();
+	let built = PatternBuilder::new()
+		.anchor_start()
+		.capture(key)
+		.class(Class::Space).zero_or_more()
+		.literal("=(%)")
+		.then(PatternBuilder::new().capture(PatternBuilder::new().not_set("]").lazy()))
+		.anchor_end()
+		.build();
+	assert_eq!( built, "^([_]%a+)%s*=%(%%%)([^%]]-)$" );
+
+	let mut pattern: Pattern<'_, 3> = Pattern::new(&built).unwrap();
+	assert_eq!( pattern.match_maybe_2("_foo =(%)bar"), Some(("_foo", "bar")) );
+
+	for source in ["^(%a+)%s*=%s*(.-)$", "%b()%f[%w]()[^a-z%d]?", "(a)%1[%]x]"] {
+		let ast = Ast::parse(source).unwrap();
+		assert_eq!( ast.to_string(), source );
+	}
+	assert_eq!( Ast::parse("(1) (2(3)%2)%1"), Err(Error::InvalidCapture(Some(2))) );
+	assert_eq!( Ast::parse("[]"), Err(Error::MissingEndBracket) );
+	assert_eq!( Ast::parse("[]x]").unwrap().to_string(), "[%]x]" );
+	assert_eq!( Ast::parse("[^]-a]").unwrap().to_string(), "[^%]^-a]" );
+	// The empty `a-%` range is written with ends that can't be misread
+	assert_eq!( Ast::parse("[a-%]]").unwrap().to_string(), "[b-a%]]" );
+	assert_eq!( Ast::parse("[%]-z]").unwrap().to_string(), "[%]%-z]" );
+	for source in ["[b-a%]]", "[%]%-z]"] {
+		assert_eq!( Ast::parse(source).unwrap().to_string(), source );
+	}
+}
+
+#[test]
+fn good_patterns() {
+	for p in ["%(", "100%%", "(a)%1", "%b)(", "%f[%w]", "[]]"] {
+		let pattern: Result<Pattern<'_, 4>, _> = Pattern::new(p);
+		assert!( pattern.is_ok(), "pattern {} should have parsed", p );
+	}
+}
+
+#[test]
+fn with_captures() {
+	let pattern: Pattern<'_, 8> = Pattern::new("(%a+)=(%a+)").unwrap();
+	let mut pattern: Pattern<'_, 3> = pattern.with_captures().unwrap();
+	assert_eq!( pattern.match_maybe_2("foo=bar"), Some(("foo", "bar")) );
+
+	let too_small: Result<Pattern<'_, 2>, _> = pattern.with_captures();
+	assert_eq!( too_small.err(), Some(Error::TooManyCaptures) );
+}
+
+#[test]
+#[cfg(feature="build")]
+fn build_module() {
+	use lupat::build::{generate_module, BuildError};
+
+	let module = generate_module("# comment\n\nKEY_VALUE = (%w+)=(%w+)\nQUOTED = \"[^\"]*\"\n").unwrap();
+	assert!( module.contains("pub const KEY_VALUE: &str = \"(%w+)=(%w+)\";\npub const KEY_VALUE_CAPTURES: usize = 3;\n") );
+	assert!( module.contains("pub const QUOTED: &str = \"\\\"[^\\\"]*\\\"\";\npub const QUOTED_CAPTURES: usize = 1;\n") );
+
+	assert_eq!( generate_module("BAD = (foo"), Err(BuildError::Pattern { line: 1, name: "BAD".to_owned(), error: Error::UnfinishedCapture }) );
+	assert_eq!( generate_module("\nnot an ident = x"), Err(BuildError::Syntax { line: 2 }) );
+}
+
+#[test]
+#[cfg(feature = "conformance")]
+fn conformance() {
+	use lupat::conformance::{Harness, Operation};
+
+	let harness = Harness::new().unwrap();
+	assert_eq!( harness.compare("(%w+)=(%w+)", "foo=bar baz=qux", "%2=%1").unwrap(), [] );
+	assert_eq!( harness.compare("%b()", "f(a(b)c) (d)", "<%0>").unwrap(), [] );
+	assert_eq!( harness.compare("(foo", "foo", "%1").unwrap(), [] );
+
+	// Error messages match too
+	assert_eq!( harness.compare("(%a)", "ab", "%x").unwrap(), [] );
+	assert_eq!( harness.compare("(%a)", "ab", "%2").unwrap(), [] );
+	assert_eq!( harness.compare("a%", "ab", "").unwrap(), [] );
+
+	// Empty matches are stepped over like Lua does
+	assert_eq!( harness.compare("x*", "ab", "-").unwrap(), [] );
+
+	// Results are compared as bytes, so Lua stepping into a char isn't lost. gmatch runs over bytes like Lua,
+	// while gsub over a str steps whole chars
+	let divergences = harness.compare("x*", "é", "-").unwrap();
+	assert_eq!( divergences.len(), 1 );
+	assert_eq!( divergences[0].operation, Operation::GSub );
+	assert_eq!( divergences[0].lua, Ok(vec![b"-\xC3-\xA9-".to_vec()]) );
+	assert_eq!( divergences[0].ours, Ok(vec!["-é-".as_bytes().to_vec()]) );
+}
+
+#[test]
+fn suggestions() {
+	let tests = [
+		("100%",       Some("use %% for a literal %")),
+		("\\d+)",      Some("Lua escapes with % instead of \\, like %d instead of \\d")),
+		("(?:foo",     Some("Lua has no non-capturing groups or lookarounds, use a plain (...) or remove the group")),
+		("%bx",        Some("%b needs an opening and closing character, like %b()")),
+		("(1) (2)%3",  None),
+	];
+
+	for (pattern, suggestion) in tests {
+		let err = Pattern::<'_, 4>::new(pattern).err().expect("pattern should fail");
+		assert_eq!( err.suggestion(pattern), suggestion );
+	}
+}
+
+#[test]
+fn normalize() {
+	let tests = [
+		("%=[ba]+[%d]",      "=[ab]+%d"),
+		("^[x-xa%a]%.$",     "^[%aax]%.$"),
+		("([_]%_)%1",        "(__)%1"),
+		("%f[%w%w]",         "%f[%w]"),
+	];
+
+	for (source, normalized) in tests {
+		let pattern: Pattern<'_, 2> = Pattern::new(source).unwrap();
+		assert_eq!( pattern.normalize(), normalized );
+	}
+
+	// The normalized pattern has to match exactly what the original does
+	for source in ["[a^-z]", "[^a^-z]", "[x^-^]", "[%%-z]", "[%]-a]", "[z-a^]", "%f[b^-c]", "[^-]", "[a-%]]", "[%]-z]", "[a-%%a]", "[^a-%]-]"] {
+		let mut original: Pattern<'_, 1> = Pattern::new(source).unwrap();
+		let normalized = original.normalize();
+		let mut round_trip: Pattern<'_, 1> = Pattern::new(&normalized).unwrap_or_else(|e| panic!("{} -> {}: {:?}", source, normalized, e));
+		for c in 0..128u8 {
+			let text = [b' ', c];
+			assert_eq!( original.matches_bytes(&text), round_trip.matches_bytes(&text), "{} -> {} on {:?}", source, normalized, c as char );
+		}
+	}
+
+	let a: Pattern<'_, 2> = Pattern::new("[%s,]+").unwrap();
+	let b: Pattern<'_, 4> = Pattern::new("[,%s]+").unwrap();
+	assert!( a.equivalent(&b) );
+	assert!( !a.equivalent(&b.multiline(true)) );
+}
+
+#[test]
+fn min_match_len() {
+	let tests = [
+		("",                0),
+		("%a+%d*x?y-",      1),
+		("^(ab)()%1$",      4),
+		("%b()%f[%w]..",    4),
+		("[abc]%%",         2),
+	];
+
+	for (source, len) in tests {
+		let pattern: Pattern<'_, 4> = Pattern::new(source).unwrap();
+		assert_eq!( pattern.min_match_len(), len, "{}", source );
+	}
+}
+
+#[test]
+fn literal_affixes() {
+	let tests = [
+		("^http(s?)://",        &b"http"[..], &b"://"[..]),
+		("(foo)%.()bar+",       b"foo.bar",   b"r"),
+		("%d+%.tar%.gz$",       b"",          b".tar.gz"),
+		("ab*c",                b"a",         b"c"),
+	];
+
+	for (source, prefix, suffix) in tests {
+		let pattern: Pattern<'_, 4> = Pattern::new(source).unwrap();
+		assert_eq!( pattern.literal_prefix(), prefix, "{}", source );
+		assert_eq!( pattern.literal_suffix(), suffix, "{}", source );
+	}
+
+	// Matches of a case insensitive pattern can start and end with other bytes
+	let caseless: Pattern = Pattern::builder("abc").case_insensitive(true).build().unwrap();
+	assert_eq!( caseless.literal_prefix(), b"" );
+	assert_eq!( caseless.literal_suffix(), b"" );
+}
+
+#[test]
+fn capture_spans() {
+	let source = "^(%a+)=((%d)())$";
+	let pattern: Pattern<'_, 5> = Pattern::new(source).unwrap();
+	let spans = pattern.capture_spans();
+	let groups: Vec<&str> = spans.iter().map(|s| &source[s.clone()]).collect();
+	assert_eq!( groups, [source, "(%a+)", "((%d)())", "(%d)", "()"] );
+}
+
+#[test]
+fn tokens() {
+	use lupat::TokenKind::*;
+
+	let source = "^(%a+)%.[^%]]*%f[%w]*()%1%b()x-$";
+	let tokens: Vec<(lupat::TokenKind, &str)> = lupat::tokens(source).map(|t| (t.kind, &source[t.span])).collect();
+	assert_eq!( tokens, [
+		(Anchor, "^"), (CaptureOpen, "("), (Class, "%a"), (Quantifier, "+"), (CaptureClose, ")"),
+		(Escape, "%."), (Set, "[^%]]"), (Quantifier, "*"), (Frontier, "%f"), (Set, "[%w]"), (Literal, "*"),
+		(Position, "()"), (BackRef, "%1"), (Balance, "%b()"), (Literal, "x"), (Quantifier, "-"), (Anchor, "$"),
+	] );
+
+	let kinds: Vec<lupat::TokenKind> = lupat::tokens("a[b%").map(|t| t.kind).collect();
+	assert_eq!( kinds, [Literal, Invalid] );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn gsub_pipeline() {
+	use lupat::{Captures, Replacer, Substitute};
+
+	let amp = Substitute::new("&amp;").unwrap();
+	let lt = Substitute::new("&lt;").unwrap();
+	let tag = |c: Captures<2>| format!("<b>{}</b>", c.get(1));
+	let mut rules: [(Pattern<'_, 2>, &dyn Replacer<2>); 3] = [
+		(Pattern::new("%*(%w+)%*").unwrap(), &tag),
+		(Pattern::new("&").unwrap(), &amp),
+		(Pattern::new("<").unwrap(), &lt),
+	];
+	// Sequential gsubs would escape the tags inserted by the first rule
+	assert_eq!( lupat::gsub_pipeline("*a* & <b", &mut rules), "<b>a</b> &amp; &lt;b" );
+
+	let short = Substitute::new("S").unwrap();
+	let long = Substitute::new("L").unwrap();
+	let mut rules: [(Pattern<'_, 2>, &dyn Replacer<2>); 2] = [
+		(Pattern::new("%a").unwrap(), &short),
+		(Pattern::new("^%a+").unwrap(), &long),
+	];
+	assert_eq!( lupat::gsub_pipeline("ab cd", &mut rules), "SS SS" );
+	assert_eq!( lupat::gsub_pipeline_longest("ab cd", &mut rules), "L SS" );
+
+	// A single rule gives the same as gsub, empty matches included
+	let dash = Substitute::new("-").unwrap();
+	for text in ["xa", "", "axxb", "é"] {
+		let mut rules: [(Pattern<'_, 2>, &dyn Replacer<2>); 1] = [(Pattern::new("x*").unwrap(), &dash)];
+		let expected = rules[0].0.gsub(text, "-").unwrap();
+		assert_eq!( lupat::gsub_pipeline(text, &mut rules), expected, "{:?}", text );
+	}
+
+	// In multiline mode `^` rules are tried after every newline too
+	let quote = Substitute::new("> %0").unwrap();
+	let mut rules: [(Pattern<'_, 2>, &dyn Replacer<2>); 1] = [(Pattern::new("^%a").unwrap().multiline(true), &quote)];
+	let expected = rules[0].0.gsub("ab\ncd\n\nef", "> %0").unwrap();
+	assert_eq!( expected, "> ab\n> cd\n\n> ef" );
+	assert_eq!( lupat::gsub_pipeline("ab\ncd\n\nef", &mut rules), expected );
+	let mut rules: [(Pattern<'_, 2>, &dyn Replacer<2>); 1] = [(Pattern::new("^%a").unwrap(), &quote)];
+	assert_eq!( lupat::gsub_pipeline("ab\ncd", &mut rules), "> ab\ncd" );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn edits() {
+	use lupat::Edit;
+
+	let mut pattern: Pattern<'_, 3> = Pattern::new("(%w+)=(%w+)").unwrap();
+	assert_eq!( pattern.edits("a=1, bb=22", "%2=%1").unwrap(), [
+		Edit { range: 0..3, replacement: "1=a".to_owned() },
+		Edit { range: 5..10, replacement: "22=bb".to_owned() },
+	] );
+
+	// Empty matches step over one character, like Lua 5.4
+	let mut pattern: Pattern<'_, 1> = Pattern::new("x*").unwrap();
+	let ranges: Vec<_> = pattern.edits("axxé", "-").unwrap().into_iter().map(|e| e.range).collect();
+	assert_eq!( ranges, [0..0, 1..3, 5..5] );
+
+	let mut pattern: Pattern<'_, 1> = Pattern::new("^%a").unwrap();
+	assert_eq!( pattern.edits("abc", "").unwrap().len(), 1 );
+
+	// Matches inside a char are widened to the whole char, like gsub does
+	let mut pattern: Pattern<'_, 1> = Pattern::new("[^a]").unwrap();
+	let edits = pattern.edits("aéa", "X").unwrap();
+	assert_eq!( edits, [Edit { range: 1..3, replacement: "X".to_owned() }] );
+	let mut text = "aéa".to_owned();
+	for edit in edits.iter().rev() {
+		text.replace_range(edit.range.clone(), &edit.replacement);
+	}
+	assert_eq!( text, pattern.gsub("aéa", "X").unwrap() );
+}
+
+#[test]
+#[cfg(all(feature = "ropey", feature = "gsub"))]
+fn rope() {
+	use ropey::RopeBuilder;
+
+	// Force a line to be split across chunks
+	let mut builder = RopeBuilder::new();
+	builder.append("first line\nsec");
+	builder.append("ond = 2\nthird = 3\n");
+	let mut rope = builder.finish();
+
+	let mut pattern: Pattern<'_, 3> = Pattern::new("^(%a+) = (%d)$").unwrap().multiline(true);
+	assert_eq!( pattern.find_rope(&rope), Some(11..21) );
+	assert_eq!( pattern.gsub_rope(&mut rope, "%2 => %1").unwrap(), 2 );
+	assert_eq!( rope.to_string(), "first line\n2 => second\n3 => third\n" );
+
+	// Same as matching the whole text as a str, across lines and chunks
+	let mut builder = RopeBuilder::new();
+	builder.append("a\n");
+	builder.append("\nb é x*");
+	let mut rope = builder.finish();
+	let text = rope.to_string();
+	let mut pattern: Pattern<'_, 1> = Pattern::new("a%s+b").unwrap();
+	assert_eq!( pattern.find_rope(&rope), pattern.find_in(&text, 0..text.len()) );
+	assert_eq!( pattern.find_rope(&rope), Some(0..4) );
+	let mut pattern: Pattern<'_, 1> = Pattern::new("^a").unwrap();
+	assert_eq!( pattern.find_rope(&"x\na".into()), None );
+	let mut pattern: Pattern<'_, 1> = Pattern::new("x*").unwrap();
+	let expected = pattern.gsub(&text, "-").unwrap();
+	assert_eq!( pattern.gsub_rope(&mut rope, "-").unwrap(), 9 );
+	assert_eq!( rope.to_string(), expected );
+
+	// Matches cutting through a char take the whole char
+	let mut rope: ropey::Rope = "aéb".into();
+	let mut cont = Pattern::<'_, 1>::new(&b"[\x80-\xBF]"[..]).unwrap();
+	assert_eq!( cont.gsub_rope(&mut rope, "").unwrap(), 1 );
+	assert_eq!( rope.to_string(), cont.gsub("aéb", "").unwrap() );
+}
+
+#[test]
+fn highlight() {
+	let text = "local s = \"a -- b\" -- 10";
+	let mut rules = [
+		(Pattern::<'_, 1>::new("\"[^\"]*\"").unwrap(), "string"),
+		(Pattern::new("%-%-.*").unwrap(), "comment"),
+		(Pattern::new("%d+").unwrap(), "number"),
+		(Pattern::new("%a+").unwrap(), "word"),
+	];
+	let spans: Vec<(&str, &str)> = lupat::highlight(text, &mut rules).into_iter().map(|(r, tag)| (&text[r], tag)).collect();
+	// The comment matched from inside the string, so it was clipped
+	assert_eq!( spans, [("local", "word"), ("s", "word"), ("\"a -- b\"", "string"), (" -- 10", "comment")] );
+
+	// Later rules fill in around earlier ones
+	let mut rules = [
+		(Pattern::<'_, 1>::new("b").unwrap(), 1),
+		(Pattern::new("%a+").unwrap(), 2),
+	];
+	assert_eq!( lupat::highlight("abc", &mut rules), [(0..1, 2), (1..2, 1), (2..3, 2)] );
+}
+
+#[test]
+fn line_index() {
+	let text = "first\nsecond line\n\nfourth x";
+	let index = lupat::LineIndex::new(text);
+	let mut patt = Pattern::<'_, 1>::new("x").unwrap();
+	let m = patt.find_match(text).unwrap();
+	assert_eq!( m.as_str(), "x" );
+	assert_eq!( m.position(&index), (4, 8) );
+
+	assert_eq!( index.position(0), (1, 1) );
+	assert_eq!( index.position(5), (1, 6) );
+	assert_eq!( index.position(6), (2, 1) );
+	assert_eq!( index.position(18), (3, 1) );
+}
+
+#[test]
+#[cfg(feature="nom")]
+fn nom_combinator() {
+	use lupat::combinator::{pattern, recognize};
+	use nom::multi::separated_list1;
+	use nom::character::complete::char;
+
+	let mut list = separated_list1(char(','), pattern::<(), 3>(Pattern::new("(%a+):(%d+)").unwrap()));
+	assert_eq!( list("a:1,bc:23 rest"), Ok((" rest", vec![vec!["a", "1"], vec!["bc", "23"]])) );
+
+	// Anchored to the start of the input
+	let mut ident = recognize::<(), 1>(Pattern::new("[%a_][%w_]*").unwrap());
+	assert_eq!( ident("foo_1 bar"), Ok((" bar", "foo_1")) );
+	assert!( ident(" foo").is_err() );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn rematch() {
+	let old = "local x = 10 -- one\nlocal yy = 200\nprint(x + yy)";
+	let edits = [(6..7, "xyz"), (0..0, "  "), (10..12, ""), (20..25, "foo bar"), (old.len()..old.len(), " 5")];
+	for src in ["%a+", "%d%d?", "(%w)%1", "%f[%w]%w", "l?", "^%a+"] {
+		let mut patt = Pattern::<'_, 2>::new(src).unwrap();
+		let previous = patt.find_all(old);
+		for (range, replacement) in edits.iter().cloned() {
+			let mut text = old.to_owned();
+			text.replace_range(range.clone(), replacement);
+			let edit = lupat::Edit { range, replacement: replacement.to_owned() };
+			assert_eq!( patt.rematch(&text, &previous, &edit), patt.find_all(&text), "{} with {:?}", src, edit );
+		}
+	}
+
+	assert_eq!( Pattern::<'_, 2>::new("a(b?)%1c").unwrap().max_match_len(), Some(4) );
+	assert_eq!( Pattern::<'_, 2>::new("a%d+").unwrap().max_match_len(), None );
+}
+
+#[test]
+fn match_many() {
+	let mut patt = Pattern::<'_, 2>::new("(%d+)").unwrap();
+	let results = patt.match_many(["a1", "none", "22b"]);
+	assert_eq!( results, [vec!["1", "1"], vec![], vec!["22", "22"]] );
+
+	let results = patt.match_many_bytes([&b"x9"[..], b"\xFF"]);
+	assert_eq!( results, [vec![&b"9"[..], b"9"], vec![]] );
+}
+
+#[test]
+#[cfg(feature="allocator-api2")]
+fn allocator() {
+	use allocator_api2::alloc::Global;
+
+	let mut patt = Pattern::<'_, 3>::new("(%w+)=(%w+)").unwrap();
+	assert_eq!( patt.captures_in("a=b", Global).as_slice(), ["a=b", "a", "b"] );
+	let out = patt.gsub_in("a=b, c=d", "%2=%1", Global).unwrap();
+	assert_eq!( std::str::from_utf8(&out), Ok("b=a, d=c") );
+
+	// Matches cutting through a char take the whole char, like gsub
+	let mut cont = Pattern::<'_, 1>::new(&b"[\x80-\xBF]"[..]).unwrap();
+	let out = cont.gsub_in("éa", "", Global).unwrap();
+	assert_eq!( std::str::from_utf8(&out), Ok(cont.gsub("éa", "").unwrap().as_str()) );
+
+	let source = String::from("(%d+)");
+	let compiled: lupat::allocator::PatternIn<Global, 2> = lupat::allocator::PatternIn::new_in(&source, Global).unwrap();
+	drop(source);
+	let mut patt = compiled.pattern();
+	assert_eq!( patt.captures("a42"), ["42", "42"] );
+	assert_eq!( compiled.source(), b"(%d+)" );
+	assert!( lupat::allocator::PatternIn::<Global, 2>::new_in("(", Global).is_err() );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn backrefs() {
+	let text = String::from("say \"hi\" and 'bye'");
+	let mut patt = Pattern::<'_, 3>::new("([\"'])(.-)%1").unwrap();
+	let quoted: Vec<&str> = patt.gmatch(&text).collect();
+	assert_eq!( quoted, ["\"", "'"] );
+	// The text is compared against, never written to
+	assert_eq!( text, "say \"hi\" and 'bye'" );
+
+	let mut patt = Pattern::<'_, 2>::new("(%a)%1").unwrap();
+	assert!( !patt.matches("abc") );
+	assert_eq!( patt.find_in("aabb", 1..4), Some(2..4) );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn gmatch_scan() {
+	let config = "width = 80\nname = lupat\nratio = 0.5\nbroken = x1";
+	let mut patt = Pattern::<'_, 3>::new("(%w+) = ([%w.]+)").unwrap();
+	let pairs: Vec<(&str, String)> = patt.gmatch_scan(config).collect();
+	assert_eq!( pairs.len(), 4 );
+	let floats: Vec<(&str, f64)> = patt.gmatch_scan(config).collect();
+	assert_eq!( floats, [("width", 80.0), ("ratio", 0.5)] );
+
+	// Without captures the whole match is used
+	let mut patt = Pattern::<'_, 1>::new("%-?%d+").unwrap();
+	let numbers: Vec<(i32,)> = patt.gmatch_scan("1, -20, 300").collect();
+	assert_eq!( numbers, [(1,), (-20,), (300,)] );
+}
+
+#[test]
+fn affixes() {
+	let mut patt = Pattern::<'_, 2>::new("/(%a+)").unwrap();
+	assert_eq!( patt.is_prefix_of("/users/1"), Some(6) );
+	assert_eq!( &"/users/1"[patt.capture(1)], "users" );
+	assert_eq!( patt.is_prefix_of("x/users"), None );
+
+	let mut ext = Pattern::<'_, 1>::new("%.%a+").unwrap();
+	assert_eq!( ext.is_suffix_of("archive.tar.gz"), Some(3) );
+	assert_eq!( ext.range(), 11..14 );
+	assert_eq!( ext.is_suffix_of("file.rs1"), None );
+	// Already anchored patterns work the same
+	assert_eq!( Pattern::<'_, 1>::new("^a*$").unwrap().is_suffix_of("aaa"), Some(3) );
+	assert_eq!( Pattern::<'_, 1>::new("a*").unwrap().is_suffix_of("b"), Some(0) );
+	// Options carry over to the anchored pattern
+	let mut caseless: Pattern = Pattern::builder("abc").case_insensitive(true).build().unwrap();
+	assert_eq!( caseless.is_suffix_of("XABC"), Some(3) );
+	#[cfg(feature="extensions")]
+	{
+		let mut escaped: Pattern = Pattern::builder("#.#d+").escape(b'#').build().unwrap();
+		assert_eq!( escaped.is_suffix_of("v1.25"), Some(3) );
+	}
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn replacen() {
+	let mut patt = Pattern::<'_, 2>::new("(%d+)").unwrap();
+	assert_eq!( patt.replace("1 2 3", "<%1>").unwrap(), "<1> 2 3" );
+	assert_eq!( patt.replacen("1 2 3", "<%1>", 2).unwrap(), "<1> <2> 3" );
+	assert_eq!( patt.replacen("1 2 3", "<%1>", 0).unwrap(), "1 2 3" );
+	assert_eq!( patt.replace("none", "x").unwrap(), "none" );
+}
+
+#[test]
+fn find_all() {
+	let mut patt = Pattern::<'_, 1>::new("%a+").unwrap();
+	assert_eq!( patt.find_all("one two  three"), [0..3, 4..7, 9..14] );
+	assert_eq!( patt.find_all_bytes(b"\xFFab\xFFc"), [1..3, 4..5] );
+	// Empty matches don't get stuck
+	assert_eq!( Pattern::<'_, 1>::new("x*").unwrap().find_all("axb"), [0..0, 1..2, 3..3] );
+}
+
+#[test]
+fn match_ranges() {
+	let ranges = {
+		let text = String::from("key = value");
+		let mut patt = Pattern::<'_, 3>::new("(%w+) = (%w+)").unwrap();
+		patt.match_ranges(&text).unwrap()
+	};
+	// Outlives both the text and the pattern
+	assert_eq!( &ranges[..], [0..11, 0..3, 6..11] );
+
+	let mut patt = Pattern::<'_, 3>::new("%d").unwrap();
+	let ranges = patt.match_ranges_bytes(b"ab1").unwrap();
+	assert_eq!( (ranges.len(), ranges[0].clone()), (1, 2..3) );
+	assert!( patt.match_ranges("abc").is_none() );
+}
+
+#[test]
+#[cfg(feature="tables")]
+fn tables() {
+	use lupat::tables;
+
+	let mut patt = Pattern::<'_, 1>::new("%p").unwrap();
+	for b in 0..=255u8 {
+		assert_eq!( patt.matches_bytes(&[b]), tables::PUNCT[b as usize] );
+	}
+	assert_eq!( tables::for_class(b'D'), Some(&tables::DIGIT) );
+	assert!( !tables::SPACE[0x0B] );
+}
+
+#[test]
+#[cfg(all(feature="gsub", feature="iter"))]
+fn no_panics() {
+	// Too deep for the matcher
+	let deep = "a".repeat(300);
+	let mut patt = Pattern::<'_, 1>::new(&deep).unwrap();
+	assert_eq!( patt.try_matches(&deep), Err(Error::TooComplex) );
+	assert!( !patt.matches(&deep) );
+
+	// Matches that split a char get widened to the whole char
+	let mut any = Pattern::<'_, 1>::new(".").unwrap();
+	assert_eq!( any.captures("é"), ["é"] );
+	assert_eq!( any.gmatch("éa").collect::<Vec<_>>(), ["é", "a"] );
+	assert_eq!( any.gsub("é", "x").unwrap(), "x" );
+
+	// Captures the pattern doesn't have
+	let mut patt = Pattern::<'_, 2>::new("(a)").unwrap();
+	assert!( patt.matches("a") );
+	assert_eq!( patt.try_capture(1), Some(0..1) );
+	assert_eq!( patt.try_capture(5), None );
+	assert_eq!( patt.match_captures("a").try_get(9), None );
+	assert_eq!( patt.gsub("a", "%2"), Err(Error::InvalidCapture(Some(2))) );
+	// Without captures %1 is the whole match, like in Lua
+	assert_eq!( Pattern::<'_, 1>::new("%d").unwrap().gsub("a1", "<%1>").unwrap(), "a<1>" );
+
+	assert!( !patt.matches_in("abc", 2..10) );
+	assert_eq!( Pattern::<'_, 0>::new("a").err(), Some(Error::TooManyCaptures) );
+	assert_eq!( Pattern::<'_, 1>::new("()").err(), Some(Error::TooManyCaptures) );
+}
+
+#[test]
+fn options() {
+	use lupat::LuaVersion;
+
+	let mut patt = Pattern::builder("hello [a-c]%l+ (%u)%1").captures::<2>().case_insensitive(true).build().unwrap();
+	assert!( patt.matches("HeLLo Bob xX") );
+	assert!( !patt.matches("hello dave xx") );
+	let mut negated = Pattern::builder("[^a]").captures::<1>().case_insensitive(true).build().unwrap();
+	assert!( !negated.matches("A") );
+
+	// %g is only a class from 5.2 on
+	let mut patt = Pattern::builder("%g").captures::<1>().version(LuaVersion::Lua51).build().unwrap();
+	assert!( !patt.matches("x") && patt.matches("g") );
+
+	// Before 5.4 an empty match right after another one counts
+	let mut patt = Pattern::builder("%w*").captures::<1>().version(LuaVersion::Lua53).build().unwrap();
+	assert_eq!( patt.find_all("ab cd"), [0..2, 2..2, 3..5, 5..5] );
+	let mut patt = Pattern::builder("%w*").captures::<1>().build().unwrap();
+	assert_eq!( patt.find_all("ab cd"), [0..2, 3..5] );
+
+	let deep = "a".repeat(50);
+	let mut patt = Pattern::builder(&deep).captures::<1>().max_depth(20).build().unwrap();
+	assert_eq!( patt.try_matches(&deep), Err(Error::TooComplex) );
+	assert!( Pattern::builder("(a)(b)").captures::<2>().build().is_err() );
+}
+
+#[test]
+fn find_balanced() {
+	let text = "if (a and (b or c)) then (";
+	assert_eq!( lupat::find_balanced(text, b'(', b')', 0), Some(3..19) );
+	assert_eq!( lupat::find_balanced(text, b'(', b')', 4), Some(10..18) );
+	// The last one never closes
+	assert_eq!( lupat::find_balanced(text, b'(', b')', 19), None );
+	assert_eq!( lupat::find_balanced("[[x]", b'[', b']', 0), Some(1..4) );
+	assert_eq!( lupat::find_balanced("ab", b'(', b')', 10), None );
+}
+
+#[test]
+#[cfg(feature="extensions")]
+fn balance_sequences() {
+	let mut patt: Pattern<'_, 1> = Pattern::new("%b{<<}{>>}").unwrap();
+	assert_eq!( patt.gmatch("a << b << c >> d >> e").collect::<Vec<_>>(), ["<< b << c >> d >>"] );
+
+	let mut comment: Pattern<'_, 2> = Pattern::new("%-%-(%b{[[}{]]})").unwrap();
+	assert_eq!( comment.captures("x = 1 --[[ note [[nested]] ]] y"), ["--[[ note [[nested]] ]]", "[[ note [[nested]] ]]"] );
+
+	// Stock %b{} is untouched
+	assert_eq!( Pattern::<'_, 1>::new("%b{}").unwrap().gmatch("a {b {c}} d").collect::<Vec<_>>(), ["{b {c}}"] );
+	assert!( !Pattern::<'_, 1>::new("%b{<<}{>>}").unwrap().matches("<< unclosed") );
+	assert_eq!( lupat::ast::Ast::parse("%b{<<}{>>}x").unwrap().to_string(), "%b{<<}{>>}x" );
+}
+
+#[test]
+fn words() {
+	let src = Pattern::word("the").unwrap();
+	let mut patt: Pattern<'_, 1> = Pattern::new(&src).unwrap();
+	assert_eq!( patt.find_all("the other theme, the end"), [0..3, 17..20] );
+
+	// Anchors stay outside the frontiers
+	assert_eq!( Pattern::word("^%d+$").unwrap(), "^%f[%w]%d+%f[%W]$" );
+	assert_eq!( Pattern::word("(oops"), Err(Error::UnfinishedCapture) );
+
+	let built = lupat::PatternBuilder::new().word(lupat::PatternBuilder::new().literal("go")).build();
+	assert_eq!( built, "%f[%w]go%f[%W]" );
+}
+
+#[test]
+fn explain_failure() {
+	let mut patt: Pattern<'_, 1> = Pattern::new("%d+%.%d+").unwrap();
+	let failure = patt.explain_failure("version 12.x").unwrap();
+	assert_eq!( failure.position, 11 );
+	assert_eq!( failure.element, 5..8 );
+	assert!( patt.explain_failure("version 1.2").is_none() );
+
+	let mut patt: Pattern<'_, 1> = Pattern::new("%f[%w]end$").unwrap();
+	let failure = patt.explain_failure("ended").unwrap();
+	assert_eq!( (failure.position, failure.element), (3, 9..10) );
+	let failure = patt.explain_failure("bend").unwrap();
+	assert_eq!( (failure.position, failure.element), (0, 6..7) );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn longest() {
+	let mut lazy = Pattern::builder("<(.-)>").captures::<2>().longest(true).build().unwrap();
+	assert_eq!( lazy.captures("x <a> <b> y"), ["<a> <b>", "a> <b"] );
+
+	// The first start that matches still wins
+	let mut num = Pattern::builder("%d+%.?%d-").captures::<1>().longest(true).build().unwrap();
+	assert_eq!( num.gmatch("1.25 and 3").collect::<Vec<_>>(), ["1.25", "3"] );
+
+	let mut plain: Pattern<'_, 2> = Pattern::new("<(.-)>").unwrap();
+	assert_eq!( plain.captures("x <a> <b> y"), ["<a>", "a"] );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn smart_case() {
+	let mut patt = Pattern::builder("(%a+)_id").case_insensitive(true).captures::<2>().build().unwrap();
+	assert_eq!( patt.gsub_smart_case("user_id User_id USER_ID uSer_id", "%1 key").unwrap(), "user key User key USER KEY uSer key" );
+	assert_eq!( patt.gsub_smart_case("x", "%2"), Err(Error::InvalidCapture(Some(2))) );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn gsub_fixpoint() {
+	use lupat::Fixpoint;
+
+	let mut spaces: Pattern<'_, 1> = Pattern::new("  ").unwrap();
+	assert_eq!( spaces.gsub_fixpoint("a      b", " ", 100).unwrap(), ("a b".to_owned(), Fixpoint::Converged) );
+	// One round only halves the spaces
+	assert_eq!( spaces.gsub_fixpoint("a        b", " ", 1).unwrap(), ("a    b".to_owned(), Fixpoint::Capped) );
+	assert_eq!( spaces.gsub_fixpoint("a b", " ", 0).unwrap(), ("a b".to_owned(), Fixpoint::Capped) );
+
+	// ab -> ba -> ab would go on forever
+	let mut swap: Pattern<'_, 3> = Pattern::new("(%a)(%a)").unwrap();
+	assert_eq!( swap.gsub_fixpoint("ab", "%2%1", 1000).unwrap(), ("ab".to_owned(), Fixpoint::Cycle) );
+	assert_eq!( swap.gsub_fixpoint("ab", "%3", 10), Err(Error::InvalidCapture(Some(3))) );
+}
+
+#[test]
+fn end_anchored() {
+	let mut patt: Pattern<'_, 2> = Pattern::new("(%a+)%.tar%.gz$").unwrap();
+	assert_eq!( patt.captures("dir/archive.tar.gz"), ["archive.tar.gz", "archive"] );
+
+	let mut patt: Pattern<'_, 2> = Pattern::new("%.(%a%a?%a?)$").unwrap();
+	let long = "x".repeat(10_000) + ".rs";
+	assert_eq!( patt.captures(&long), [".rs", "rs"] );
+	assert!( !patt.matches("file.rs.bak2") );
+	assert_eq!( patt.find_in("a.md b", 0..4), Some(1..4) );
+
+	// An escaped $ isn't an anchor
+	let mut patt: Pattern<'_, 1> = Pattern::new("%d%$").unwrap();
+	assert_eq!( patt.find_all("1$ 2$"), [0..2, 3..5] );
+	let mut patt: Pattern<'_, 2> = Pattern::new("()$").unwrap();
+	assert_eq!( patt.match_ranges("abc").unwrap()[1], 3..3 );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn bindings() {
+	use lupat::bindings::{CompiledPattern, Span};
+
+	let patt = CompiledPattern::new("(%w+)=(%w+)").unwrap();
+	assert_eq!( patt.source(), "(%w+)=(%w+)" );
+	assert!( patt.is_match("a=1") );
+	assert_eq!( patt.find("x a=1"), Some(Span { start: 2, end: 5, text: "a=1".to_owned() }) );
+	assert_eq!( patt.match_captures("a=1"), Some(vec!["a".to_owned(), "1".to_owned()]) );
+	assert_eq!( patt.gmatch("a=1, b=2").len(), 2 );
+	assert_eq!( patt.find_all("a=1, b=2").len(), 2 );
+	assert_eq!( patt.gsub("a=1, b=2", "%2=%1").unwrap(), "1=a, 2=b" );
+
+	let words = CompiledPattern::new("%a+").unwrap();
+	assert_eq!( words.gmatch("hi there"), [["hi"], ["there"]] );
+	assert!( CompiledPattern::new("(").is_err() );
+	let upper = CompiledPattern::with_options(Pattern::builder("abc").case_insensitive(true)).unwrap();
+	assert!( upper.is_match("ABC") );
+
+	let text = "😀 x=1";
+	let span = patt.find(text).unwrap();
+	assert_eq!( (span.start, span.end), (5, 8) );
+	let span = span.to_utf16(text);
+	assert_eq!( (span.start, span.end, span.text.as_str()), (3, 6, "x=1") );
+
+	let text = "a=1 😀 b=2 😀";
+	let spans = patt.find_all(text);
+	let one_by_one: Vec<Span> = spans.iter().map(|span| span.to_utf16(text)).collect();
+	assert_eq!( lupat::bindings::spans_to_utf16(text, &spans), one_by_one );
+	assert_eq!( (one_by_one[1].start, one_by_one[1].end), (7, 10) );
+}
+
+#[test]
+#[cfg(feature="extensions")]
+fn escape_char() {
+	let mut patt = Pattern::builder("(\\d+)\\.(\\d+)%").escape(b'\\').captures::<3>().build().unwrap();
+	assert_eq!( patt.captures("at 12.5%"), ["12.5%", "12", "5"] );
+	assert_eq!( patt.min_match_len(), 4 );
+	assert_eq!( patt.normalize(), "(%d+)%.(%d+)%%" );
+
+	let mut set = Pattern::builder("[\\]x]+").escape(b'\\').captures::<1>().build().unwrap();
+	assert_eq!( set.gmatch("a]x]b").collect::<Vec<_>>(), ["]x]"] );
+
+	let err = Pattern::builder("abc\\").escape(b'\\').build().err().unwrap();
+	assert_eq!( err, Error::EndsWithEscape(b'\\') );
+	assert_eq!( err.to_string(), "malformed pattern (ends with '\\')" );
+	assert_eq!( Pattern::builder("abc%").build().err(), Some(Error::EndsWithPercent) );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn registry() {
+	let registry = std::sync::Arc::new(lupat::Registry::new());
+	registry.register("ipv4", "^%d+%.%d+%.%d+%.%d+$").unwrap();
+	assert_eq!( registry.register("bad", "[a-"), Err(Error::MissingEndBracket) );
+
+	let shared = registry.clone();
+	std::thread::spawn(move || {
+		shared.register("word", "%a+").unwrap();
+		assert!( shared.get("ipv4").unwrap().is_match("127.0.0.1") );
+	}).join().unwrap();
+
+	assert_eq!( registry.names(), ["ipv4", "word"] );
+	assert_eq!( registry.get("word").unwrap().pattern().gmatch("hi there").count(), 2 );
+	assert!( registry.remove("word").is_some() );
+	assert!( registry.get("word").is_none() );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn registry_pipeline() {
+	use lupat::{Captures, Registry, Replacer, Substitute};
+
+	let registry = Registry::new();
+	registry.register("var", "%$(%a+)").unwrap();
+	registry.register("tag", "<(%a+)>").unwrap();
+	let upper = |c: Captures| c.get(1).to_uppercase();
+	let inner = Substitute::new("%1").unwrap();
+	let rules: [(&str, &dyn Replacer); 2] = [("var", &upper), ("tag", &inner)];
+	assert_eq!( registry.gsub_pipeline("$foo <bar>", &rules), Ok("FOO bar".to_owned()) );
+
+	let err = registry.gsub_pipeline("x", &[("missing", &inner)]).unwrap_err();
+	assert_eq!( err, Error::UnknownPattern("missing".to_owned()) );
+	assert!( !err.is_syntax() && !err.is_runtime() );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn lua_errors() {
+	let mut patt: Pattern<'_, 2> = Pattern::new("(%a)").unwrap();
+	assert_eq!( patt.gsub("ab", "%x"), Err(Error::InvalidReplacement) );
+	assert_eq!( patt.gsub("ab", "50%"), Err(Error::InvalidReplacement) );
+	assert_eq!( patt.gsub("ab", "%%%1%0").unwrap(), "%aa%bb" );
+
+	assert_eq!( Error::NoOpenCapture.to_string(), "invalid pattern capture" );
+	assert_eq!( Error::InvalidReplacement.to_string(), "invalid use of '%' in replacement string" );
+	assert_eq!( Error::UnfinishedCapture.with_location("input", 3), "input:3: unfinished capture" );
+}
+
+#[test]
+fn owned_matches() {
+	use lupat::{CapturesOwned, MatchOwned};
+
+	let owned = {
+		let text = String::from("key = value");
+		let mut patt: Pattern<'_, 3> = Pattern::new("(%w+) = (%w+)").unwrap();
+		patt.matches(&text);
+		CapturesOwned::from(patt.match_captures(&text))
+	};
+	assert_eq!( owned.len(), 3 );
+	assert_eq!( owned.get(2), Some("value") );
+	assert_eq!( owned.range(1), Some(0..3) );
+	assert_eq!( owned.get(3), None );
+
+	let m = std::thread::spawn(move || {
+		let mut patt: Pattern<'_, 1> = Pattern::new("%d+").unwrap();
+		patt.find_match("abc 123").map(MatchOwned::from)
+	}).join().unwrap();
+	assert_eq!( m, Some(MatchOwned { text: "123".to_owned(), range: 4..7 }) );
+}
+
+#[test]
+#[cfg(feature="serde")]
+fn owned_serde() {
+	use serde_core::de::value::{Error as DeError, SeqDeserializer};
+	use serde_core::{Deserialize, Serialize};
+
+	fn serde<T: Serialize + for<'de> Deserialize<'de>>() {}
+	serde::<lupat::MatchOwned>();
+	serde::<lupat::CapturesOwned>();
+
+	let empty = lupat::CapturesOwned::deserialize(SeqDeserializer::<_, DeError>::new(std::iter::empty::<u8>())).unwrap();
+	assert!( empty.is_empty() );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn gsub_indexed() {
+	let mut patt: Pattern<'_, 2> = Pattern::new("(%a+)").unwrap();
+	// Skip the first match and keep offsets into the whole text
+	let out = patt.gsub_indexed("one two three", |occurrence, caps| {
+		if occurrence.index == 0 {
+			caps.get(1).to_owned()
+		} else {
+			format!("{}:{}", occurrence.range.start, caps.get(1).len())
+		}
+	});
+	assert_eq!( out, "one 4:3 8:5" );
+
+	let mut empty: Pattern<'_, 1> = Pattern::new("x*").unwrap();
+	assert_eq!( empty.gsub_indexed("ab", |o, _| o.index.to_string()), "0a1b2" );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn remainder() {
+	let mut patt: Pattern<'_, 1> = Pattern::new("%d+").unwrap();
+	let mut iter = patt.gmatch("1 22 333");
+	assert_eq!( iter.next(), Some("1") );
+	assert_eq!( iter.remainder(), " 22 333" );
+
+	let mut sep: Pattern<'_, 1> = Pattern::new(";").unwrap();
+	let mut split = sep.split("head;a;b");
+	assert_eq!( split.next(), Some("head") );
+	assert_eq!( split.remainder(), Some("a;b") );
+	assert_eq!( split.by_ref().count(), 2 );
+	assert_eq!( split.remainder(), None );
+
+	let mut patt: Pattern<'_, 1> = Pattern::new("%d+").unwrap();
+	let mut bytes = patt.gmatch_bytes(b"1 22");
+	assert_eq!( bytes.next(), Some(&b"1"[..]) );
+	assert_eq!( bytes.remainder(), b" 22" );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn split_with_delimiters() {
+	use lupat::Piece::{Sep, Text};
+
+	let mut patt: Pattern<'_, 1> = Pattern::new("%s*[=,]%s*").unwrap();
+	let pieces: Vec<_> = patt.split_with_delimiters("a = 1, b").collect();
+	assert_eq!( pieces, [Text("a"), Sep(" = "), Text("1"), Sep(", "), Text("b")] );
+
+	let pieces: Vec<_> = patt.split_with_delimiters(",").collect();
+	assert_eq!( pieces, [Text(""), Sep(","), Text("")] );
+	assert_eq!( patt.split_with_delimiters("").collect::<Vec<_>>(), [Text("")] );
+}
+
+#[test]
+#[cfg(feature="extensions")]
+fn case_escapes() {
+	let mut patt: Pattern<'_, 3> = Pattern::new("(%a+) (%a+)").unwrap();
+	assert_eq!( patt.gsub("Hello World", "%u1 %l2 %1").unwrap(), "HELLO world Hello" );
+	assert_eq!( patt.gsub("a b", "%u3"), Err(Error::InvalidCapture(Some(3))) );
+	assert_eq!( patt.gsub("a b", "%u"), Err(Error::InvalidReplacement) );
+	assert!( patt.matches("A B") );
+	assert_eq!( lupat::Substitute::new("<%l0>").unwrap().subst(&patt, "A B"), "<a b>" );
+}
+
+#[test]
+fn raw_matches() {
+	use lupat::LuaMatch;
+
+	let mut patt: Pattern<'_, 3> = Pattern::new("(%a+)=()").unwrap();
+	assert!( patt.matches("x key=1") );
+	assert_eq!( patt.raw_matches(), [LuaMatch { start: 2, end: 6 }, LuaMatch { start: 2, end: 5 }, LuaMatch { start: 6, end: 6 }] );
+	assert_eq!( patt.raw_slots().len(), 3 );
+	assert!( !patt.matches("nope") );
+	assert!( patt.raw_matches().is_empty() );
+	assert_eq!( std::mem::size_of::<LuaMatch>(), 2 * std::mem::size_of::<usize>() );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn slow_match_hook() {
+	use lupat::{SlowKind, SlowMatch};
+	use std::sync::Mutex;
+	use std::time::Duration;
+
+	static SEEN: Mutex<Vec<(SlowKind, usize)>> = Mutex::new(Vec::new());
+	// Other tests run alongside, so only this pattern is recorded
+	lupat::set_slow_match_hook(Duration::ZERO, |slow: &SlowMatch| {
+		if slow.pattern == b"slow(%d)" {
+			SEEN.lock().unwrap().push((slow.kind, slow.haystack_len));
+		}
+	});
+	let mut patt: Pattern<'_, 2> = Pattern::new("slow(%d)").unwrap();
+	assert!( patt.matches("a slow1") );
+	assert_eq!( patt.gsub("slow2", "%1").unwrap(), "2" );
+	lupat::clear_slow_match_hook();
+	assert!( patt.matches("slow3") );
+
+	let seen = SEEN.lock().unwrap();
+	assert_eq!( seen[0], (SlowKind::Match, 7) );
+	assert!( seen.contains(&(SlowKind::Gsub, 5)) );
+	assert_eq!( seen.len(), 4 );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn gsub_capped() {
+	let mut patt: Pattern<'_, 1> = Pattern::new("x").unwrap();
+	let hostile = "x".repeat(100);
+	assert_eq!( patt.gsub_capped(&hostile, "%0%0%0", 299), Err(Error::OutputTooLong(299)) );
+	assert_eq!( patt.gsub_capped(&hostile, "%0%0%0", 300).unwrap().len(), 300 );
+	assert_eq!( patt.gsub_capped("abc", "", 2), Err(Error::OutputTooLong(2)) );
+	assert_eq!( Error::OutputTooLong(2).to_string(), "resulting string too large" );
+
+	let doubled = patt.gsub_bytes_with_capped(b"xyx", 4, |c| c.get(0).repeat(2));
+	assert_eq!( doubled, Err(Error::OutputTooLong(4)) );
+	assert_eq!( patt.gsub_bytes_with_capped(b"xyx", 5, |c| c.get(0).repeat(2)).unwrap(), b"xxyxx" );
+}
+
+#[test]
+#[cfg(feature="encoding")]
+fn encoded_haystacks() {
+	use lupat::encoding::Encoding;
+
+	let mut patt: Pattern<'_, 2> = Pattern::new("caf(.+)!").unwrap();
+	// "café!" in Latin-1, where é is one byte but two once decoded
+	assert_eq!( patt.captures_encoded(b"caf\xe9!", Encoding::Latin1), Some(vec![0..5, 3..4]) );
+
+	let be: Vec<u8> = "x😀café!".encode_utf16().flat_map(u16::to_be_bytes).collect();
+	assert_eq!( patt.captures_encoded(&be, Encoding::Utf16Be), Some(vec![6..16, 12..14]) );
+	assert_eq!( patt.find_encoded(b"\x00c", Encoding::Utf16Be), None );
+
+	let decoded = Encoding::Utf16Le.decode(b"a\x00\x00\xd8b");
+	assert_eq!( decoded.as_str(), "a\u{fffd}\u{fffd}" );
+	assert_eq!( decoded.original_range(1..7), 2..5 );
+}
+
+#[test]
+#[cfg(feature="unicode")]
+fn nfc_matching() {
+	use lupat::unicode::{nfc, Normalized};
+
+	assert_eq!( nfc("e\u{301}"), "\u{e9}" );
+	// Marks are reordered before composing, so the dot below goes on first
+	assert_eq!( nfc("q\u{307}\u{323}"), "q\u{323}\u{307}" );
+	assert_eq!( nfc("s\u{307}\u{323}"), "\u{1e69}" );
+	assert_eq!( nfc("\u{1100}\u{1161}\u{11a8}"), "\u{ac01}" );
+	assert_eq!( nfc("\u{212b}"), "\u{c5}" );
+
+	let mut patt: Pattern<'_, 2> = Pattern::new("(caf\u{e9})!").unwrap();
+	assert_eq!( patt.captures_nfc("x cafe\u{301}!"), Some(vec![2..9, 2..8]) );
+	assert_eq!( patt.find_nfc("cafe!"), None );
+
+	let normalized = Normalized::new("a\u{30a}b");
+	assert_eq!( normalized.as_str(), "\u{e5}b" );
+	assert_eq!( normalized.original_range(0..1), 0..3 );
+	assert_eq!( normalized.original_range(3..3), 4..4 );
+}
+
+#[test]
+#[cfg(feature="segmentation")]
+fn grapheme_dots() {
+	let mut patt = Pattern::builder("^(.)(.-)(.)$").graphemes(true).captures::<4>().build().unwrap();
+	assert_eq!( patt.captures("🇫🇷abc👨‍👩‍👧"), ["🇫🇷abc👨‍👩‍👧", "🇫🇷", "abc", "👨‍👩‍👧"] );
+	assert_eq!( patt.captures("e\u{301}x\r\n"), ["e\u{301}x\r\n", "e\u{301}", "x", "\r\n"] );
+
+	let mut patt = Pattern::builder("<(.+)>").graphemes(true).build().unwrap();
+	assert_eq!( patt.match_maybe("<\u{1100}\u{1161}\u{11a8}> <x>"), Some("\u{1100}\u{1161}\u{11a8}> <x") );
+
+	let mut patt = Pattern::builder("..?").graphemes(true).build().unwrap();
+	assert_eq!( patt.gmatch("👍🏽🇯🇵🇺").collect::<Vec<_>>(), ["👍🏽🇯🇵", "🇺"] );
+	// Without the option `.` is a byte
+	let mut patt: Pattern<'_, 1> = Pattern::new("^.$").unwrap();
+	assert!( !patt.matches("é") );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn pattern_arena() {
+	use lupat::PatternArena;
+
+	let mut arena = PatternArena::with_capacity(3, 32);
+	let digits = arena.add("%d+").unwrap();
+	let word = arena.add_with_options(Pattern::builder("^HELLO").case_insensitive(true)).unwrap();
+	assert_eq!( arena.add("(oops"), Err(Error::UnfinishedCapture) );
+	assert_eq!( arena.len(), 2 );
+	assert_eq!( word.index(), 1 );
+
+	assert_eq!( arena.matching("hello 42"), [digits, word] );
+	assert_eq!( arena.matching("say hello"), [] );
+	assert_eq!( arena.source(word), Some(&b"^HELLO"[..]) );
+	assert_eq!( arena.ids().collect::<Vec<_>>(), [digits, word] );
+	let mut patt = arena.get(digits).unwrap();
+	assert_eq!( patt.gsub("a1b22", "#").unwrap(), "a#b#" );
+}
+
+#[test]
+#[cfg(feature="extensions")]
+fn fragments() {
+	use lupat::Fragments;
+
+	let mut fragments = Fragments::new();
+	fragments.define("num", "%d+").unwrap();
+	fragments.define("pair", "%{num},%{num}").unwrap();
+	assert_eq!( fragments.define("bad", "(%{num}"), Err(Error::UnfinishedCapture) );
+	assert_eq!( fragments.define("not a name", "x"), Err(Error::UnknownFragment("not a name".to_owned())) );
+
+	assert_eq!( fragments.expand("^%{pair}$").unwrap(), "^%d+,%d+$" );
+	assert_eq!( fragments.expand("%%{num} [%{}] %").unwrap(), "%%{num} [%{}] %" );
+	assert_eq!( fragments.expand("%{nope}"), Err(Error::UnknownFragment("nope".to_owned())) );
+	assert_eq!( Error::UnknownFragment("nope".to_owned()).to_string(), "unknown fragment '%{nope}'" );
+	assert!( fragments.compile("^%{pair}$").unwrap().is_match("1,22") );
+}
+
+#[test]
+fn utf8_safe() {
+	let safe = |p: &str| Pattern::<4>::new(p).unwrap().utf8_safe();
+	assert!( safe("^(%w+)=(%d*)$") );
+	assert!( safe("naïve%s+[a-z]+") );
+	assert!( safe("%b()%f[%a]()") );
+	assert!( safe("^") );
+	assert!( !safe("%a*") );
+	assert!( !safe("a.") );
+	assert!( !safe("%S+") );
+	assert!( !safe("[^x]") );
+	assert!( !safe("\u{e9}+") );
+	assert!( !Pattern::<1>::new(&b"x\xc3"[..]).unwrap().utf8_safe() );
+	assert!( !lupat::ast::Ast::parse("(%W)").unwrap().utf8_safe() );
+}
+
+#[test]
+fn find_into() {
+	use lupat::LuaMatch;
+
+	let mut patt: Pattern<'_, 3> = Pattern::new("(%a)(%d)").unwrap();
+	let mut slots = [LuaMatch { start: 0, end: 0 }; 3];
+	assert_eq!( patt.find_into(b"..x1", &mut slots, 0, false), Ok(3) );
+	assert_eq!( slots, [LuaMatch { start: 2, end: 4 }, LuaMatch { start: 2, end: 3 }, LuaMatch { start: 3, end: 4 }] );
+	assert_eq!( patt.find_into(b"..x1", &mut slots, 0, true), Ok(0) );
+	assert_eq!( patt.find_into(b"x1", &mut slots[..2], 0, false), Err(Error::TooManyCaptures) );
+
+	// Custom iteration, leaving the pattern's own match alone
+	assert!( patt.matches("z9") );
+	let mut init = 0;
+	let mut found = Vec::new();
+	while patt.find_into(b"a1b2c3", &mut slots, init, false) == Ok(3) {
+		found.push(slots[0].start);
+		init = slots[0].end;
+	}
+	assert_eq!( found, [0, 2, 4] );
+	assert_eq!( patt.range(), 0..2 );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn gmatch_bytes_short_borrow() {
+	fn numbers(data: &[u8]) -> Vec<Vec<u8>> {
+		let source = String::from("%d+");
+		let mut patt: Pattern<'_, 1> = Pattern::new(&source).unwrap();
+		let found = patt.gmatch_bytes(data).map(<[u8]>::to_vec).collect();
+		// The pattern can be used again once the iterator is gone
+		assert!( patt.matches_bytes(b"7") );
+		found
+	}
+	assert_eq!( numbers(b"a1 b22 \xff333"), [b"1".to_vec(), b"22".to_vec(), b"333".to_vec()] );
+
+	let mut patt: Pattern<'_, 2> = Pattern::new("(%a+)/").unwrap();
+	let path = std::ffi::OsStr::new("usr/local/bin");
+	let dirs: Vec<_> = patt.gmatch_os(path).collect();
+	assert_eq!( dirs, [&b"usr"[..], &b"local"[..]] );
+	let mut again = patt.gmatch_os(path);
+	assert_eq!( again.next(), Some(&b"usr"[..]) );
+	assert_eq!( again.remainder(), b"local/bin" );
+}
+
+#[test]
+fn matches_at_exactly() {
+	let mut patt: Pattern<'_, 2> = Pattern::new("(%a+)").unwrap();
+	assert!( patt.matches_at_exactly("12 abc", 3) );
+	assert_eq!( patt.captures("12 abc"), ["abc", "abc"] );
+	assert!( patt.matches_at_exactly("12 abc", 4) );
+	assert_eq!( patt.range(), 4..6 );
+	// A search would find "abc" later on
+	assert!( !patt.matches_at_exactly("12 abc", 0) );
+	assert!( !patt.matches_at_exactly("12 abc", 7) );
+
+	let mut empty: Pattern<'_, 1> = Pattern::new("x*").unwrap();
+	assert!( empty.matches_at_exactly("ab", 2) );
+	assert_eq!( empty.range(), 2..2 );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn arena_split() {
+	use lupat::PatternArena;
+
+	let mut arena = PatternArena::new();
+	let pipe = arena.add("|").unwrap();
+	let arrow = arena.add("%->").unwrap();
+	let dash = arena.add("%-").unwrap();
+	let spaces = arena.add("%s*").unwrap();
+	let fields: Vec<_> = arena.split("a->b-c|d e").collect();
+	assert_eq!( fields, [("a", Some(arrow)), ("b", Some(dash)), ("c", Some(pipe)), ("d", Some(spaces)), ("e", None)] );
+	assert_eq!( arena.split("").collect::<Vec<_>>(), [("", None)] );
+	assert_eq!( arena.split("|").collect::<Vec<_>>(), [("", Some(pipe)), ("", None)] );
+	assert_eq!( PatternArena::new().split("abc").collect::<Vec<_>>(), [("abc", None)] );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn gsub_bytes_map() {
+	use std::borrow::Cow;
+
+	let mut patt: Pattern<'_, 2> = Pattern::new("%$(%w+)").unwrap();
+	let res = patt.gsub_bytes_map(b"$a $bb $c\xff", |name| match name {
+		b"a" => Some(Cow::Borrowed(&b"1"[..])),
+		b"bb" => Some(Cow::Owned(name.repeat(2))),
+		_ => None,
+	});
+	assert_eq!( res, b"1 bbbb $c\xff" );
+
+	let mut whole: Pattern<'_, 1> = Pattern::new("%d").unwrap();
+	assert_eq!( whole.gsub_bytes_map(b"a1b2", |d| if d == b"1" { Some(Cow::Borrowed(b"one")) } else { None }), b"aoneb2" );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn empty_matches_step_bytes() {
+	// Byte haystacks step over single bytes like Lua, str ones over whole chars
+	let mut patt: Pattern<'_, 1> = Pattern::new("").unwrap();
+	assert_eq!( patt.gsub_bytes_with("é".as_bytes(), |_| b"-".to_vec()), b"-\xC3-\xA9-" );
+	assert_eq!( patt.gsub("é", "-").unwrap(), "-é-" );
+
+	let mut patt: Pattern<'_, 1> = Pattern::new("x*").unwrap();
+	assert_eq!( patt.find_all_bytes(b"\xC3\xA9a"), [0..0, 1..1, 2..2, 3..3] );
+	assert_eq!( patt.find_all("éa"), [0..0, 2..2, 3..3] );
+	assert_eq!( patt.gmatch_bytes(b"\xC3\xA9").count(), 3 );
+}
+
+#[test]
+fn captures_is_set() {
+	let mut patt: Pattern<'_, 3> = Pattern::new("x(y*)()").unwrap();
+	assert!( patt.matches("ax") );
+	let captures = patt.match_captures("ax");
+	assert!( captures.is_set(0) && captures.is_set(1) && captures.is_set(2) );
+	assert_eq!( (captures.get(1), captures.get(2)), ("", "") );
+
+	assert!( !patt.matches("a") );
+	assert!( !patt.match_captures("a").is_set(0) );
+
+	// %1 stands in for the whole match, but isn't a slot of its own
+	let mut plain: Pattern<'_, 2> = Pattern::new("x").unwrap();
+	assert!( plain.matches("x") );
+	assert_eq!( plain.match_captures("x").get(1), "x" );
+	assert!( !plain.match_captures("x").is_set(1) );
+}
+
+#[test]
+fn prelude() {
+	use lupat::prelude::{Pattern as P, *};
+
+	let mut patt = P::new("%d+").unwrap();
+	assert_eq!( patt.match_maybe("abc 123"), Some("123") );
+	assert_eq!( P::new("(").err(), Some(Error::UnfinishedCapture) );
+	let kinds: Vec<_> = tokens("%d").map(|t| t.kind).collect();
+	assert_eq!( kinds.len(), 1 );
+}
+
+#[test]
+fn prelude_oneshot() {
+	use lupat::prelude::*;
+
+	assert_eq!( is_match("%d+", "abc 123"), Ok(true) );
+	assert_eq!( find("%d+", "abc 123"), Ok(Some(4..7)) );
+	assert_eq!( find("(", "abc"), Err(Error::UnfinishedCapture) );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn prelude_oneshot_gsub() {
+	use lupat::prelude::*;
+
+	assert_eq!( gsub("(%a+)=(%d+)", "x=1 y=2", "%2=%1"), Ok("1=x 2=y".to_owned()) );
+}
+
+#[test]
+fn chained_haystacks() {
+	use std::collections::VecDeque;
+
+	let mut patt: Pattern<'_, 3> = Pattern::new("(%a+)=(%b())").unwrap();
+	let parts = (&b"..key=(a(b"[..], &b"))c"[..]);
+	assert!( patt.matches_haystack(&parts) );
+	assert_eq!( (patt.range(), patt.capture(1), patt.capture(2)), (2..12, 2..5, 6..12) );
+
+	// Wrapped around, so the deque's two halves split "abcabc"
+	let mut ring: VecDeque<u8> = VecDeque::with_capacity(6);
+	ring.extend(b"xxxabc");
+	ring.drain(..3);
+	ring.extend(b"abc");
+	assert!( !ring.as_slices().1.is_empty() );
+	let mut repeated: Pattern<'_, 2> = Pattern::new("^(%a+)%1$").unwrap();
+	assert!( repeated.matches_haystack(&ring) );
+	assert_eq!( repeated.capture(1), 0..3 );
+
+	let mut end: Pattern<'_, 1> = Pattern::new("c$").unwrap();
+	assert!( end.matches_haystack(&(&b"ab"[..], &b"c"[..])) );
+	assert!( end.matches_haystack(&(&b"c"[..], &b""[..])) );
+	assert!( !end.matches_haystack(&(&b""[..], &b""[..])) );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn gmatch_with_offsets() {
+	let mut patt: Pattern<'_, 2> = Pattern::new("(%a)%a*").unwrap();
+	let text = "one two  three";
+	for m in patt.gmatch_with_offsets(text) {
+		assert_eq!( &text[m.range()], m.as_str() );
+	}
+	let starts: Vec<_> = patt.gmatch_with_offsets(text).map(|m| m.range().start).collect();
+	assert_eq!( starts, [0, 4, 9] );
+
+	// Empty matches don't loop forever
+	let mut empty: Pattern<'_, 1> = Pattern::new("x*").unwrap();
+	let found: Vec<_> = empty.gmatch_with_offsets("axb").map(|m| m.range()).collect();
+	assert_eq!( found, [0..0, 1..2, 3..3] );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn gsub_lines() {
+	use std::io::{BufReader, ErrorKind};
+
+	let log = "ts=1 level=warn\nts=2 level=info\r\nlast level=warn";
+	let mut patt: Pattern<'_, 2> = Pattern::new("level=(%a+)$").unwrap();
+	let mut out = Vec::new();
+	let n = lupat::gsub_lines(BufReader::with_capacity(4, log.as_bytes()), &mut out, &mut patt, "[%1]").unwrap();
+	assert_eq!( n, 2 );
+	// The \r is part of the line, so `$` doesn't match before it
+	assert_eq!( String::from_utf8(out).unwrap(), "ts=1 [warn]\nts=2 level=info\r\nlast [warn]" );
+
+	let mut empty: Pattern<'_, 1> = Pattern::new("x*").unwrap();
+	let mut out = Vec::new();
+	assert_eq!( lupat::gsub_lines(&b"ab\n\n"[..], &mut out, &mut empty, "-").unwrap(), 4 );
+	assert_eq!( out, b"-a-b-\n-\n" );
+
+	let err = lupat::gsub_lines(&b"a"[..], Vec::new(), &mut empty, "%2").unwrap_err();
+	assert_eq!( err.kind(), ErrorKind::InvalidInput );
+	let err = lupat::gsub_lines(&b"\xff\n"[..], Vec::new(), &mut empty, "").unwrap_err();
+	assert_eq!( err.kind(), ErrorKind::InvalidData );
+}
+
+#[test]
+fn frontier_introspection() {
+	use lupat::ast::{Ast, Set, SetItem};
+
+	let ast = Ast::parse("(%f[%a]%a+)%f[^%s]").unwrap();
+	let sets = ast.frontiers();
+	assert_eq!( sets.len(), 2 );
+	assert!( sets[0].contains(b'q') && !sets[0].contains(b'1') );
+	assert!( sets[0].is_frontier(b"1x", 1) && !sets[0].is_frontier(b"xx", 1) );
+	assert!( sets[0].is_frontier(b"x", 0) );
+	assert!( sets[1].is_frontier(b" a", 1) && !sets[1].is_frontier(b"ab", 1) );
+	// The start counts as \0, which isn't a space, so it's already in the set
+	assert!( !sets[1].is_frontier(b"a", 0) );
+
+	let empty = Set { negated: false, items: vec![] };
+	assert!( empty.matches_nothing() );
+	let everything = Set { negated: true, items: vec![] };
+	assert!( !everything.matches_nothing() );
+	assert!( Set { negated: true, items: vec![SetItem::Range(0, 255)] }.matches_nothing() );
+
+	// The helper agrees with the matcher
+	let mut patt: Pattern<'_, 1> = Pattern::new("%f[%d]").unwrap();
+	let digit_ast = Ast::parse("%f[%d]").unwrap();
+	let digits = digit_ast.frontiers()[0];
+	for (i, text) in ["a1", "11", "1", "", "x"].iter().enumerate() {
+		let expected = (0..=text.len()).find(|&pos| digits.is_frontier(text.as_bytes(), pos));
+		assert_eq!( patt.matches(text).then(|| patt.range().start), expected, "case {}", i );
+	}
+}
+
+#[test]
+fn capture_bounds() {
+	let patt: Pattern<'_, 5> = Pattern::new("(a)(.*)(b%1)()").unwrap();
+	assert_eq!( patt.capture_bounds(0), (3, None) );
+	assert_eq!( patt.capture_bounds(1), (1, Some(1)) );
+	assert_eq!( patt.capture_bounds(2), (0, None) );
+	// Still known after an unbounded capture
+	assert_eq!( patt.capture_bounds(3), (2, Some(2)) );
+	assert_eq!( patt.capture_bounds(4), (0, Some(0)) );
+	assert_eq!( patt.capture_bounds(9), (0, Some(0)) );
+
+	let nested: Pattern<'_, 3> = Pattern::new("((x+)y)").unwrap();
+	assert_eq!( (nested.capture_bounds(1), nested.capture_bounds(2)), ((2, None), (1, None)) );
+	assert_eq!( Pattern::<'_, 2>::new("(%b())").unwrap().capture_bounds(1), (2, None) );
+}
+
+#[test]
+fn error_classes() {
+	use std::io;
+
+	let syntax = Pattern::<'_, 2>::new("(a").err().unwrap();
+	assert!( syntax.is_syntax() && !syntax.is_runtime() );
+	assert!( Error::InvalidReplacement.is_syntax() );
+	assert!( Error::TooComplex.is_runtime() && Error::OutputTooLong(5).is_runtime() );
+	assert!( !Error::TooComplex.is_syntax() && !Error::HaystackTooLong(5).is_syntax() && !Error::CapLen.is_syntax() );
+	assert!( Error::MissingEndBracket.is_syntax() && Error::UnknownFragment("x".to_owned()).is_syntax() );
+
+	let io_err: io::Error = syntax.into();
+	assert_eq!( io_err.kind(), io::ErrorKind::InvalidData );
+	assert_eq!( io_err.to_string(), "unfinished capture" );
+	assert_eq!( io_err.into_inner().unwrap().downcast::<Error>().ok().map(|e| *e), Some(Error::UnfinishedCapture) );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn substitute_defaults() {
+	use lupat::Substitute;
+
+	let mut patt: Pattern<'_, 3> = Pattern::new("(%w*),(%w*)").unwrap();
+	let template = Substitute::new("[%1|%2|%9]").unwrap().with_default(1, "N/A").with_default(9, "-");
+	assert!( patt.matches(",x") );
+	assert_eq!( template.subst(&patt, ",x"), "[N/A|x|-]" );
+	assert!( patt.matches("y,") );
+	assert_eq!( template.subst(&patt, "y,"), "[y||-]" );
+	assert!( patt.matches(",,") );
+	assert_eq!( template.with_default(1, "?").subst(&patt, ",,"), "[?||-]" );
+}
+
+#[test]
+fn builder_concat() {
+	use lupat::{Class, PatternBuilder};
+
+	let ident = || PatternBuilder::new().capture(PatternBuilder::new().class(Class::Letter).one_or_more());
+	let ws = || PatternBuilder::new().class(Class::Space).zero_or_more();
+	let repeat = || PatternBuilder::new().capture(PatternBuilder::new().class(Class::Digit)).backref(1);
+	let built = (ident() + ws() + repeat() + ws() + repeat()).build();
+	assert_eq!( built, "(%a+)%s*(%d)%2%s*(%d)%3" );
+
+	let mut patt: lupat::Pattern = lupat::Pattern::new(&built).unwrap();
+	assert!( !patt.matches("x 12 22") );
+	assert_eq!( patt.captures("x 11 22"), ["x 11 22", "x", "1", "2"] );
+
+	let nine = (0..8).fold(repeat(), |acc, _| acc + repeat());
+	assert_eq!( nine.clone().try_concat(ws()).map(|b| b.build()), Ok(nine.build() + "%s*") );
+	assert_eq!( nine.try_concat(repeat()).map(|b| b.build()), Err(Error::TooManyCaptures) );
+}
+
+#[test]
+fn builder_rejects_invalid() {
+	use lupat::PatternBuilder;
+	use std::panic::catch_unwind;
+
+	// Each of these would render a pattern that doesn't compile
+	assert!( catch_unwind(|| PatternBuilder::new().set("")).is_err() );
+	assert!( catch_unwind(|| PatternBuilder::new().not_set("")).is_err() );
+	assert!( catch_unwind(|| PatternBuilder::new().backref(0)).is_err() );
+	assert!( catch_unwind(|| PatternBuilder::new().capture(PatternBuilder::new().any()).backref(2)).is_err() );
+
+	let built = PatternBuilder::new().capture(PatternBuilder::new().any()).backref(1).build();
+	assert!( Pattern::<'_, 2>::new(&built).is_ok() );
+}
+
+#[cfg(feature="gsub")]
+#[test]
+fn gsub_spans() {
+	let mut patt: lupat::Pattern = lupat::Pattern::new("%s*").unwrap();
+	let (res, spans) = patt.gsub_spans("ab  c", "_").unwrap();
+	assert_eq!( res, "_a_b_c_" );
+	assert_eq!( spans, [(0..0, 0..1), (1..1, 2..3), (2..4, 4..5), (5..5, 6..7)] );
+	for (input, output) in &spans {
+		assert!( input.start <= output.start );
+	}
+
+	let mut patt: lupat::Pattern = lupat::Pattern::new("é").unwrap();
+	let (res, spans) = patt.gsub_spans("éte", "e").unwrap();
+	assert_eq!( res, "ete" );
+	assert_eq!( spans, [(0..2, 0..1)] );
+}
+
+#[cfg(feature="gsub")]
+#[test]
+fn source_map() {
+	use lupat::SourceMap;
+
+	let mut patt: lupat::Pattern = lupat::Pattern::new("\t").unwrap();
+	let input = "a\tb\t\tc";
+	let (output, spans) = patt.gsub_spans(input, "    ").unwrap();
+	let map = SourceMap::new(spans);
+	for c in ['a', 'b', 'c'].iter() {
+		assert_eq!( map.to_output(input.find(*c).unwrap()), output.find(*c).unwrap() );
+		assert_eq!( map.to_input(output.find(*c).unwrap()), input.find(*c).unwrap() );
+	}
+	assert_eq!( map.to_input(7), 3 );
+	assert_eq!( map.to_output(input.len()), output.len() );
+
+	let map = SourceMap::default();
+	assert_eq!( map.to_output(3), 3 );
+	assert_eq!( map.to_input(3), 3 );
+}
+
+#[cfg(feature="gsub")]
+#[test]
+fn gsub_with_ranges() {
+	let mut patt: lupat::Pattern = lupat::Pattern::new("(%d)()").unwrap();
+	let text = "a1 b2 c3";
+	let res = patt.gsub_with(text, |captures| {
+		let digit = captures.range(1).unwrap();
+		assert_eq!( &text[digit.clone()], captures.get(1) );
+		assert_eq!( captures.range(2), Some(digit.end..digit.end) );
+		format!("@{}", digit.start)
+	});
+	assert_eq!( res, "a@1 b@4 c@7" );
+
+	let mut patt: lupat::Pattern = lupat::Pattern::new("^x").unwrap();
+	assert_eq!( patt.gsub_with("xxx", |_| "y".to_owned()), "yxx" );
+}
+
+#[test]
+fn max_haystack_len() {
+	use lupat::{error::Error, Haystack, Pattern};
+
+	// Claims to be longer than any slice could be
+	struct Endless;
+	impl Haystack for Endless {
+		type Unit = u8;
+		fn len(&self) -> usize {
+			usize::MAX
+		}
+		fn unit_at(&self, _: usize) -> Option<u8> {
+			Some(b'a')
+		}
+	}
+
+	let max = Pattern::<1>::max_haystack_len();
+	assert_eq!( max, isize::MAX as usize );
+	let mut patt: Pattern<'_, 1> = Pattern::new("a").unwrap();
+	assert_eq!( patt.try_matches_haystack(&Endless), Err(Error::HaystackTooLong(max)) );
+	assert!( !patt.matches_haystack(&Endless) );
+	assert!( Error::HaystackTooLong(max).is_runtime() );
+	assert_eq!( patt.try_matches_haystack(&(&b"x"[..], &b"a"[..])), Ok(true) );
+}
+
+#[test]
+fn validate() {
+	use lupat::{error::Error, validate};
+
+	let info = validate("%s*()(%d%d?)").unwrap();
+	assert_eq!( info.captures, 2 );
+	assert!( !info.anchored_start && !info.anchored_end );
+	assert_eq!( info.min_len, 1 );
+	assert_eq!( info.max_len, None );
+	assert_eq!( validate("^ab?$").unwrap().max_len, Some(2) );
+
+	assert_eq!( validate("[a").err(), Some(Error::MissingEndBracket) );
+	assert_eq!( validate("(a)%2").err(), Some(Error::InvalidCapture(Some(2))) );
+	assert_eq!( validate(&"()".repeat(33)).err(), Some(Error::TooManyCaptures) );
+}
+
+#[test]
+fn compare() {
+	let corpus = ["key: value", "key:value", "2: x", ": x", ""];
+	let diff = lupat::compare("^(%a+):%s*(.*)", "^(%w+): (.*)", corpus.iter().copied()).unwrap();
+	let inputs: Vec<_> = diff.iter().map(|d| d.input).collect();
+	assert_eq!( inputs, ["key:value", "2: x"] );
+	assert_eq!( diff[0].a, Some(vec![0..9, 0..3, 4..9]) );
+	assert_eq!( diff[0].b, None );
+
+	// Same match, but the position capture lands elsewhere
+	let diff = lupat::compare("a()b", "ab()", ["ab"].iter().copied()).unwrap();
+	assert_eq!( diff.len(), 1 );
+
+	assert!( lupat::compare("%d+", "%d%d*", corpus.iter().copied()).unwrap().is_empty() );
+	assert!( lupat::compare("(", "a", corpus.iter().copied()).is_err() );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn try_gmatch() {
+	let mut patt: Pattern<'_, 1> = Pattern::new("%a+").unwrap();
+	let found: Vec<_> = patt.try_gmatch("ab, cd").map(|m| m.map(|m| m.range())).collect();
+	assert_eq!( found, [Ok(0..2), Ok(4..6)] );
+
+	let deep = "a".repeat(50);
+	let text = format!("b{}", deep);
+	let mut patt = Pattern::builder(&deep).captures::<1>().max_depth(20).build().unwrap();
+	let found: Vec<_> = patt.try_gmatch(&text).collect();
+	assert_eq!( found.len(), 1 );
+	assert_eq!( found[0].as_ref().err(), Some(&Error::TooComplex) );
+	assert_eq!( patt.gmatch_with_offsets(&text).count(), 0 );
+}
+
+#[test]
+fn set_bitmaps() {
+	use lupat::{LuaVersion, PatternArena};
+
+	let text = "Key_1 = [value]; other=%x";
+	let cases = [
+		("[%w_]+", "Key_1"),
+		("[^%s=]+$", "%x"),
+		("[]=]+", "="),
+		("[^]%w_ =]+", "["),
+		("%b[]", "[value]"),
+		("%f[%a]%a+%f[^%a]", "Key"),
+		("[%%x]+$", "%x"),
+		("[a-z]+", "ey"),
+		// Like Lua, a `]` right after the `[` is part of the set
+		("[]]", "]"),
+	];
+	for &(src, expected) in cases.iter() {
+		let mut patt: Pattern = Pattern::new(src).unwrap();
+		assert_eq!( patt.match_maybe(text).unwrap_or(""), expected, "{}", src );
+	}
+
+	let mut patt = Pattern::builder("[k-m]+").case_insensitive(true).build().unwrap();
+	assert_eq!( patt.match_maybe("ABKLMn"), Some("KLM") );
+	let mut patt = Pattern::builder("[%g]").version(LuaVersion::Lua51).build().unwrap();
+	assert_eq!( patt.match_maybe("x g"), Some("g") );
+	// Units past a byte aren't in any bitmap
+	#[cfg(feature="utf16")]
+	{
+		let mut patt: Pattern = Pattern::new("[^a]").unwrap();
+		assert!( patt.matches_utf16(&[0x100]) );
+		let mut patt: Pattern = Pattern::new(&b"[\x01-\xff]"[..]).unwrap();
+		assert!( !patt.matches_utf16(&[0x100]) );
+	}
+
+	let mut arena = PatternArena::new();
+	let digits = arena.add("[0-9]+").unwrap();
+	let upper = arena.add("[A-Z][a-z]*").unwrap();
+	assert_eq!( arena.get(digits).unwrap().match_maybe("ab 123"), Some("123") );
+	assert_eq!( arena.get(upper).unwrap().match_maybe("say Hi"), Some("Hi") );
+}
+
+#[test]
+fn oneshot() {
+	assert_eq!( lupat::is_match("%a+", "123"), Ok(false) );
+	assert_eq!( lupat::find("%a+", "12 ab"), Ok(Some(3..5)) );
+	assert_eq!( lupat::find("é", "aé"), Ok(Some(1..3)) );
+	assert_eq!( lupat::find("[", "x").err(), Some(Error::MissingEndBracket) );
+
+	// More patterns than are kept compiled, used over and over
+	let sources: Vec<String> = (0..40).map(|i| format!("^{}$", i)).collect();
+	for _ in 0..3 {
+		for (i, src) in sources.iter().enumerate() {
+			assert_eq!( lupat::is_match(src, &i.to_string()), Ok(true) );
+			assert_eq!( lupat::is_match(src, "x"), Ok(false) );
+		}
+	}
+
+	let deep = "a".repeat(300);
+	assert_eq!( lupat::is_match(&deep, &deep), Err(Error::TooComplex) );
+
+	#[cfg(feature="gsub")]
+	assert_eq!( lupat::gsub("%s+", "a  b\tc", " "), Ok("a b c".to_owned()) );
+}
+
+#[test]
+fn capture_tree() {
+	let mut patt: Pattern = Pattern::new("^(%a+)=((%d+)(%.(%d+)))()$").unwrap();
+	assert!( patt.matches("x=1.5") );
+	let captures = patt.match_captures("x=1.5");
+	assert_eq!( captures.children(0), [1, 2, 6] );
+	assert_eq!( captures.children(2), [3, 4] );
+	assert_eq!( captures.children(4), [5] );
+	assert!( captures.children(6).is_empty() );
+	assert_eq!( captures.parent(5), Some(4) );
+	assert_eq!( captures.parent(6), Some(0) );
+	assert_eq!( captures.parent(0), None );
+	assert_eq!( captures.parent(7), None );
+	assert!( captures.children(7).is_empty() );
+
+	// Every capture's span lies within its parent's
+	for i in 1..captures.num_matches() {
+		let (inner, outer) = (patt.capture(i), patt.capture(captures.parent(i).unwrap()));
+		assert!( outer.start <= inner.start && inner.end <= outer.end );
+	}
+}
+
+#[cfg(feature="gsub")]
+#[test]
+fn gsub_with_gaps() {
+	let mut patt: Pattern = Pattern::new("%d+").unwrap();
+	let mut gaps = Vec::new();
+	let res = patt.gsub_with_gaps("12ab3c45", |captures| format!("[{}]", captures.get(1)), |gap| {
+		gaps.push(gap.to_owned());
+		gap.to_uppercase()
+	});
+	assert_eq!( res, "[12]AB[3]C[45]" );
+	assert_eq!( gaps, ["ab", "c"] );
+
+	let mut patt: Pattern = Pattern::new("x").unwrap();
+	assert_eq!( patt.gsub_with_gaps("", |_| "!".to_owned(), |_| "?".to_owned()), "" );
+	assert_eq!( patt.gsub_with_gaps("ab", |_| "!".to_owned(), |gap| gap.len().to_string()), "2" );
+
+	// Empty matches leave every character as its own gap
+	let mut patt: Pattern = Pattern::new("").unwrap();
+	assert_eq!( patt.gsub_with_gaps("ab", |_| "|".to_owned(), |gap| format!("<{}>", gap)), "|<a>|<b>|" );
+}
+
+#[test]
+fn lossy_captures() {
+	use std::borrow::Cow;
+
+	let mut patt: Pattern = Pattern::new("(%d+) (%a+) (.-)$").unwrap();
+	let line = b"42 GET /caf\xe9";
+	let captures = patt.captures_lossy(line);
+	assert_eq!( captures, ["42 GET /caf\u{FFFD}", "42", "GET", "/caf\u{FFFD}"] );
+	assert!( matches!(captures[1], Cow::Borrowed(_)) );
+	assert!( matches!(captures[3], Cow::Owned(_)) );
+	assert!( patt.captures_lossy(b"\xff").is_empty() );
+
+	let mut patt: Pattern = Pattern::new("id=(%w+)").unwrap();
+	assert_eq!( patt.match_maybe_lossy(b"\xfe id=7"), Some(Cow::Borrowed("7")) );
+	assert_eq!( patt.match_maybe_lossy(b"nothing"), None );
+	// A capture cutting a char in two gets the broken half replaced
+	let mut patt: Pattern = Pattern::new("^(.)").unwrap();
+	assert_eq!( patt.match_maybe_lossy("é".as_bytes()).unwrap(), "\u{FFFD}" );
+}
+
+#[cfg(feature="metrics")]
+#[test]
+fn metrics_hook() {
+	use lupat::MatchMetrics;
+	use std::sync::Mutex;
+
+	static SEEN: Mutex<Vec<(bool, usize)>> = Mutex::new(Vec::new());
+	// Other tests run alongside, so only these patterns are recorded
+	lupat::set_metrics_hook(|m: &MatchMetrics| {
+		if m.pattern.starts_with(b"metric") {
+			SEEN.lock().unwrap().push((m.matched, m.steps));
+		}
+	});
+	let mut short: Pattern<'_, 1> = Pattern::new("metric%d").unwrap();
+	assert!( short.matches("metric1") );
+	assert!( !short.matches("nope") );
+	let mut backtracking: Pattern<'_, 1> = Pattern::new("metric.*.*.*x").unwrap();
+	assert!( !backtracking.matches("metric-------------------") );
+	lupat::clear_metrics_hook();
+	assert!( short.matches("metric2") );
+
+	let seen = SEEN.lock().unwrap();
+	assert_eq!( seen.len(), 3 );
+	assert!( seen[0].0 && !seen[1].0 && !seen[2].0 );
+	assert!( seen[0].1 > 0 );
+	assert!( seen[2].1 > 10 * seen[0].1 );
+}
+
+#[test]
+fn handle_table() {
+	use lupat::bindings::{self, CompiledPattern};
+	use std::thread;
+
+	let id = bindings::handle_create("^(%a+)$").unwrap();
+	assert_ne!( id, 0 );
+	assert!( bindings::handle_create("%").is_err() );
+	let patt = bindings::handle_get(id).unwrap();
+	assert!( bindings::handle_destroy(id) );
+	assert!( !bindings::handle_destroy(id) );
+	assert!( bindings::handle_get(id).is_none() );
+	// Still usable after the handle is gone
+	assert!( patt.is_match("word") );
+
+	let ids: Vec<u64> = (0..8)
+		.map(|i| thread::spawn(move || bindings::handle_create(&format!("x{}", i)).unwrap()))
+		.collect::<Vec<_>>()
+		.into_iter()
+		.map(|t| t.join().unwrap())
+		.collect();
+	for (i, id) in ids.iter().enumerate() {
+		assert!( *id > 0 && !ids[..i].contains(id) );
+		assert!( bindings::handle_get(*id).unwrap().is_match(&format!("ax{}", i)) );
+	}
+	let upper = bindings::handle_insert(CompiledPattern::with_options(Pattern::builder("abc").case_insensitive(true)).unwrap());
+	assert!( bindings::handle_get(upper).unwrap().is_match("ABC") );
+	assert!( ids.iter().all(|&id| bindings::handle_destroy(id)) );
+}
+
+#[test]
+fn validate_all() {
+	use lupat::{validate, validate_all};
+
+	let errors = validate_all("(a))%f.(b%").err().unwrap();
+	let found: Vec<_> = errors.iter().map(|e| (&e.error, e.span.clone())).collect();
+	assert_eq!( found, [
+		(&Error::NoOpenCapture, 3..4),
+		(&Error::MissingLBracketF, 4..6),
+		(&Error::EndsWithPercent, 9..10),
+		(&Error::UnfinishedCapture, 7..8),
+	] );
+	assert_eq!( validate_all("%f").err().unwrap()[0].error, Error::MissingLBracketF );
+	assert_eq!( validate_all("%b(").err().unwrap()[0].span, 0..3 );
+	assert_eq!( validate_all("(()%1)%0").err().unwrap()[0].error, Error::InvalidCapture(Some(1)) );
+	assert_eq!( validate_all(&"()".repeat(33)).err().unwrap()[0].span, 62..64 );
+	assert_eq!( validate_all("^(%a+)%1$").unwrap(), validate("^(%a+)%1$").unwrap() );
+
+	// The first error is the one validate stops at
+	for src in ["(%1)", "[%", "%", "a)", "((a)", "%f", "%bx", "%f[a]%0(", "x]"].iter() {
+		assert_eq!( validate_all(src).err().map(|e| e.into_iter().next().unwrap().error), validate(src).err(), "{}", src );
+	}
+}
+
+#[test]
+#[cfg(all(feature="gsub", feature="iter"))]
+fn zero_width() {
+	let mut patt = Pattern::<'_, 1>::new("").unwrap();
+	assert!( patt.is_zero_width() );
+	assert_eq!( patt.gsub("abc", "-").unwrap(), "-a-b-c-" );
+	assert_eq!( patt.gsub("é", "-").unwrap(), "-é-" );
+	assert_eq!( patt.gmatch_bytes(b"ab").count(), 3 );
+
+	let mut patt = Pattern::<'_, 1>::new("%f[%w]").unwrap();
+	assert!( patt.is_zero_width() );
+	let starts: Vec<usize> = patt.gmatch_with_offsets("to be").map(|m| m.range().start).collect();
+	assert_eq!( starts, [0, 3] );
+	assert_eq!( patt.gsub_bytes_map(b"to be", |_| Some(b"^"[..].into())), b"^to ^be" );
+
+	let mut patt = Pattern::<'_, 2>::new("()").unwrap();
+	assert!( patt.is_zero_width() );
+	assert_eq!( patt.gmatch("ab").collect::<Vec<_>>(), ["", "", ""] );
+	assert_eq!( patt.gsub("ab", "|").unwrap(), "|a|b|" );
+
+	assert!( !Pattern::<'_, 1>::new("a?").unwrap().is_zero_width() );
+}
+
+#[test]
+fn find_init() {
+	let text = "key=1; other=22";
+	let mut patt = Pattern::<'_, 2>::new("(%a+)=").unwrap();
+	assert_eq!( patt.find(text, 0), Some(0..4) );
+	assert_eq!( patt.find(text, 1), Some(1..4) );
+	assert_eq!( patt.find(text, 5), Some(7..13) );
+	assert_eq!( &text[patt.capture(1)], "other" );
+	assert_eq!( patt.find(text, text.len()), None );
+	assert_eq!( patt.find(text, 100), None );
+
+	// `^` anchors at init, not at the start of the text
+	let mut patt = Pattern::<'_, 1>::new("^%d+").unwrap();
+	assert_eq!( patt.find(text, 4), Some(4..5) );
+	assert_eq!( patt.find(text, 3), None );
+
+	let mut empty = Pattern::<'_, 1>::new("").unwrap();
+	assert_eq!( empty.find(text, text.len()), Some(text.len()..text.len()) );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn gmatch_owned() {
+	let (tx, rx) = std::sync::mpsc::channel();
+	let sender = std::thread::spawn(move || {
+		let text = String::from("k1=v1, k2=v2");
+		let mut patt = Pattern::<'_, 2>::new("(%w+)=").unwrap();
+		for key in patt.gmatch_owned(&text) {
+			tx.send(key).unwrap();
+		}
+	});
+	sender.join().unwrap();
+	assert_eq!( rx.iter().collect::<Vec<_>>(), ["k1", "k2"] );
+
+	let mut empty = Pattern::<'_, 1>::new("").unwrap();
+	assert_eq!( empty.gmatch_owned("ab").count(), 3 );
+}
+
+#[test]
+#[cfg(feature="gsub")]
+fn bound_substitute() {
+	use lupat::Substitute;
+
+	let patt = Pattern::<'_, 3>::new("(%a+)@(%a+)").unwrap();
+	assert_eq!( Substitute::for_pattern(&patt, "%3").err(), Some(Error::InvalidCapture(Some(3))) );
+
+	let mut bound = Substitute::for_pattern(&patt, "%2 <%1>").unwrap().with_default(1, "?");
+	assert_eq!( bound.num_captures(), 2 );
+	assert_eq!( bound.gsub("me@home, you@work"), "home <me>, work <you>" );
+	assert_eq!( bound.pattern().min_match_len(), 3 );
+
+	// Without captures %1 is the whole match, like gsub
+	let plain = Pattern::<'_, 1>::new("%d").unwrap();
+	let mut bound = Substitute::for_pattern(&plain, "[%1]").unwrap();
+	assert_eq!( bound.gsub("a1b2"), "a[1]b[2]" );
+}
+
+#[test]
+#[cfg(feature="iter")]
+fn gmatch_ranges_bytes() {
+	let mut patt = Pattern::<'_, 2>::new("^(a)").unwrap();
+	let found: Vec<Vec<std::ops::Range<usize>>> = patt.gmatch_ranges_bytes(b"aa").map(|m| m.to_vec()).collect();
+	assert_eq!( found, [vec![0..1, 0..1]] );
+
+	let mut patt = Pattern::<'_, 1>::new("x*").unwrap();
+	let found: Vec<_> = patt.gmatch_ranges_bytes(b"xbx").map(|m| m[0].clone()).collect();
+	assert_eq!( found, [0..1, 2..3] );
+}